@@ -15,5 +15,7 @@ CREATE TABLE IF NOT EXISTS "Notes" (
 	FOREIGN KEY("Document") REFERENCES "Notes"
 );
 INSERT INTO "Documents" VALUES (1,'Test','Test document');
+INSERT INTO "Documents" VALUES (2,'故乡','我冒了严寒，回到相隔二千余里的故乡去。');
 INSERT INTO "Notes" VALUES (1,1,3,1,'Test annotation');
+INSERT INTO "Notes" VALUES (2,0,45,2,'hometown');
 COMMIT;
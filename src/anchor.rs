@@ -0,0 +1,157 @@
+use crate::make_enum;
+
+make_enum!(NoteColor, [Yellow, Green, Blue, Red, Purple]);
+
+/// Characters of the text kept on each side of a note
+pub const CONTEXT_CHARS: usize = 24;
+/// Characters that must match for a note to be placed again
+const MIN_MATCH: usize = 4;
+
+impl NoteColor {
+    /// Square shown before a highlight in the Md view
+    pub fn marker(&self) -> &'static str {
+        match self {
+            Self::Yellow => "🟨",
+            Self::Green => "🟩",
+            Self::Blue => "🟦",
+            Self::Red => "🟥",
+            Self::Purple => "🟪",
+        }
+    }
+
+    pub fn parse(s: &str) -> Self {
+        Self::ALL.iter().find(|c| c.as_str() == s).copied().unwrap_or_default()
+    }
+}
+
+/// Byte offset of a line and character column, clamped to the text
+pub fn byte_offset(text: &str, line: usize, column: usize) -> usize {
+    let mut start = 0;
+    for _ in 0..line {
+        match text[start..].find('\n') {
+            Some(ix) => start += ix + 1,
+            None => return text.len(),
+        }
+    }
+    let end = text[start..].find('\n').map(|ix| start + ix).unwrap_or(text.len());
+    text[start..end].char_indices()
+        .nth(column)
+        .map(|(ix, _)| start + ix)
+        .unwrap_or(end)
+}
+
+/// Line and character column of a byte offset
+pub fn position(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map(|ix| ix + 1).unwrap_or(0);
+    (before.matches('\n').count(), before[line_start..].chars().count())
+}
+
+/// The text a note covers with some of the text around it, to find the note again
+/// when the document is edited
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Anchor {
+    pub quote: String,
+    pub prefix: String,
+    pub suffix: String,
+}
+
+impl Anchor {
+    /// Anchor of the byte range `start..end` of a text
+    pub fn new(text: &str, start: usize, end: usize) -> Self {
+        let before = &text[..start];
+        let prefix_start = before.char_indices().rev()
+            .nth(CONTEXT_CHARS - 1)
+            .map(|(ix, _)| ix)
+            .unwrap_or(0);
+        Self {
+            quote: text[start..end].to_string(),
+            prefix: before[prefix_start..].to_string(),
+            suffix: text[end..].chars().take(CONTEXT_CHARS).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.quote.is_empty() && self.prefix.is_empty() && self.suffix.is_empty()
+    }
+
+    /// Byte range of the anchor in `text`: the place whose context matches best,
+    /// the nearest to `hint` among equal ones. None when the anchored text is gone
+    pub fn find(&self, text: &str, hint: usize) -> Option<(usize, usize)> {
+        let candidates: Vec<(usize, usize)> = if !self.quote.is_empty() {
+            text.match_indices(self.quote.as_str()).map(|(s, q)| (s, s + q.len())).collect()
+        } else {
+            // A few characters on each side of the position are enough to find it,
+            // the score then counts how much of the context still matches
+            let head: String = self.suffix.chars().take(2 * MIN_MATCH).collect();
+            let tail_start = self.prefix.char_indices().rev()
+                .nth(2 * MIN_MATCH - 1)
+                .map(|(ix, _)| ix)
+                .unwrap_or(0);
+            let tail = &self.prefix[tail_start..];
+            let after_prefix = text.match_indices(tail)
+                .filter(|_| !tail.is_empty())
+                .map(|(s, p)| (s + p.len(), s + p.len()));
+            let before_suffix = text.match_indices(head.as_str())
+                .filter(|_| !head.is_empty())
+                .map(|(s, _)| (s, s));
+            after_prefix.chain(before_suffix).collect()
+        };
+        let context = self.prefix.chars().count() + self.suffix.chars().count();
+        let quote = self.quote.chars().count();
+        let needed = if quote == 0 { (2 * MIN_MATCH).min(context) } else { MIN_MATCH.min(quote + context) };
+        candidates.into_iter()
+            .map(|(s, e)| {
+                let score = common_suffix(&text[..s], &self.prefix) + common_prefix(&text[e..], &self.suffix);
+                (score, s.abs_diff(hint), s, e)
+            })
+            .filter(|(score, ..)| quote + score >= needed)
+            .min_by_key(|(score, distance, ..)| (std::cmp::Reverse(*score), *distance))
+            .map(|(_, _, s, e)| (s, e))
+    }
+}
+
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.chars().zip(b.chars()).take_while(|(x, y)| x == y).count()
+}
+
+fn common_suffix(a: &str, b: &str) -> usize {
+    a.chars().rev().zip(b.chars().rev()).take_while(|(x, y)| x == y).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "我冒了严寒，回到相隔二千余里，别了二十余年的故乡去。\n时候既然是深冬；渐近故乡时，天气又阴晦了。";
+
+    #[test]
+    fn test_positions() {
+        let offset = byte_offset(TEXT, 1, 10);
+        assert_eq!(&TEXT[offset..offset + "故乡".len()], "故乡");
+        assert_eq!(position(TEXT, offset), (1, 10));
+        assert_eq!(byte_offset(TEXT, 0, 100), TEXT.find('\n').unwrap());
+        assert_eq!(byte_offset(TEXT, 5, 0), TEXT.len());
+    }
+
+    #[test]
+    fn test_find() {
+        let start = byte_offset(TEXT, 1, 10);
+        let anchor = Anchor::new(TEXT, start, start + "故乡".len());
+        assert_eq!((anchor.quote.as_str(), anchor.suffix.as_str()), ("故乡", "时，天气又阴晦了。"));
+        assert_eq!(anchor.prefix.chars().count(), CONTEXT_CHARS);
+        assert_eq!(anchor.find(TEXT, 0), Some((start, start + "故乡".len())));
+
+        // The first 故乡 is nearer, the context says it is the second one
+        let edited = TEXT.replace("我冒了严寒，", "").replace("渐近", "渐渐近了");
+        let (s, e) = anchor.find(&edited, 0).unwrap();
+        assert_eq!((position(&edited, s), &edited[s..e]), ((1, 12), "故乡"));
+
+        let point = Anchor::new(TEXT, start, start);
+        let (s, _) = point.find(&edited, 0).unwrap();
+        assert_eq!(position(&edited, s), (1, 12));
+
+        assert_eq!(anchor.find("我的家乡", 0), None);
+        assert_eq!(point.find("别了二十余年", 0), None);
+    }
+}
@@ -0,0 +1,262 @@
+use std::io::{Read, Write};
+use std::path::Path;
+
+use chrono::Utc;
+use rusqlite::{Connection, params};
+use serde_json::json;
+use sha1::{Digest, Sha1};
+use tracing::debug;
+use zip::write::SimpleFileOptions;
+
+use super::connect::NoteData;
+use crate::error::ReaderResult;
+use crate::utils::TempFile;
+
+/// Id of the bundled note type, fixed so that re-imports update the same notes
+const MODEL_ID: i64 = 1_716_000_000_101;
+const MODEL_NAME: &str = "cnreader";
+const FIELD_SEP: &str = "\x1f";
+const GUID_CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789!#$%&()*+,-./:;<=>?@[]^_`{|}~";
+const CLOZE_GAP: &str = "[…]";
+
+const SCHEMA: &str = r#"
+CREATE TABLE col (
+    id integer primary key, crt integer not null, mod integer not null, scm integer not null,
+    ver integer not null, dty integer not null, usn integer not null, ls integer not null,
+    conf text not null, models text not null, decks text not null, dconf text not null, tags text not null
+);
+CREATE TABLE notes (
+    id integer primary key, guid text not null, mid integer not null, mod integer not null,
+    usn integer not null, tags text not null, flds text not null, sfld integer not null,
+    csum integer not null, flags integer not null, data text not null
+);
+CREATE TABLE cards (
+    id integer primary key, nid integer not null, did integer not null, ord integer not null,
+    mod integer not null, usn integer not null, type integer not null, queue integer not null,
+    due integer not null, ivl integer not null, factor integer not null, reps integer not null,
+    lapses integer not null, left integer not null, odue integer not null, odid integer not null,
+    flags integer not null, data text not null
+);
+CREATE TABLE revlog (
+    id integer primary key, cid integer not null, usn integer not null, ease integer not null,
+    ivl integer not null, lastIvl integer not null, factor integer not null, time integer not null,
+    type integer not null
+);
+CREATE TABLE graves (usn integer not null, oid integer not null, type integer not null);
+CREATE INDEX ix_notes_usn on notes (usn);
+CREATE INDEX ix_cards_usn on cards (usn);
+CREATE INDEX ix_revlog_usn on revlog (usn);
+CREATE INDEX ix_cards_nid on cards (nid);
+CREATE INDEX ix_cards_sched on cards (did, queue, due);
+CREATE INDEX ix_revlog_cid on revlog (cid);
+CREATE INDEX ix_notes_csum on notes (csum);
+"#;
+
+const CSS: &str = ".card { font-family: sans-serif; font-size: 22px; text-align: center; }\n.hanzi { font-size: 48px; }\n";
+
+/// 64 bit FNV-1a, stable between runs and versions
+fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Anki style base91 guid, derived from the word so it's the same on every export
+pub fn guid(hanzi: &str) -> String {
+    let mut n = fnv1a(format!("{}:{}", MODEL_NAME, hanzi).as_str());
+    let mut res = vec![];
+    while n > 0 {
+        res.push(GUID_CHARS[(n % GUID_CHARS.len() as u64) as usize]);
+        n /= GUID_CHARS.len() as u64;
+    }
+    res.reverse();
+    String::from_utf8(res).unwrap_or_default()
+}
+
+fn deck_id(deck: &str) -> i64 {
+    // Keep it in the range of millisecond timestamps, like Anki does
+    1_000_000_000_000 + (fnv1a(deck) % 1_000_000_000_000) as i64
+}
+
+fn checksum(sfld: &str) -> i64 {
+    let hash = Sha1::digest(sfld.as_bytes());
+    u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]) as i64
+}
+
+fn cloze(sentence: &str, hanzi: &str) -> String {
+    if !hanzi.is_empty() && sentence.contains(hanzi) {
+        sentence.replacen(hanzi, CLOZE_GAP, 1)
+    } else {
+        String::new()
+    }
+}
+
+fn models(did: i64, now: i64) -> serde_json::Value {
+    let fields = ["Hanzi", "Pinyin", "Meaning", "Sentence", "SentenceCloze", "Source"];
+    let flds: Vec<_> = fields.iter().enumerate()
+        .map(|(ord, name)| json!({
+            "name": name, "ord": ord, "sticky": false, "rtl": false,
+            "font": "Arial", "size": 20, "media": [],
+        }))
+        .collect();
+    let back = "<div class=hanzi>{{Hanzi}}</div>{{Pinyin}}<br>{{Meaning}}<br><br>{{Sentence}}<br><small>{{Source}}</small>";
+    json!({
+        MODEL_ID.to_string(): {
+            "id": MODEL_ID,
+            "name": MODEL_NAME,
+            "type": 0,
+            "mod": now,
+            "usn": -1,
+            "sortf": 0,
+            "did": did,
+            "tmpls": [
+                {
+                    "name": "Recognition", "ord": 0, "did": null, "bqfmt": "", "bafmt": "",
+                    "qfmt": "<div class=hanzi>{{Hanzi}}</div>",
+                    "afmt": format!("{{{{FrontSide}}}}<hr id=answer>{}", back),
+                },
+                {
+                    "name": "Sentence cloze", "ord": 1, "did": null, "bqfmt": "", "bafmt": "",
+                    "qfmt": "{{#SentenceCloze}}{{SentenceCloze}}<br><br>{{Meaning}}{{/SentenceCloze}}",
+                    "afmt": format!("{{{{SentenceCloze}}}}<hr id=answer>{}", back),
+                },
+            ],
+            "flds": flds,
+            "css": CSS,
+            "latexPre": "\\documentclass[12pt]{article}\n\\special{papersize=3in,5in}\n\\usepackage{amssymb,amsmath}\n\\pagestyle{empty}\n\\setlength{\\parindent}{0in}\n\\begin{document}\n",
+            "latexPost": "\\end{document}",
+            "tags": [],
+            "vers": [],
+            "req": [[0, "any", [0]], [1, "any", [4]]],
+        }
+    })
+}
+
+fn deck(did: i64, name: &str, now: i64) -> serde_json::Value {
+    let deck = |id: i64, name: &str| json!({
+        "id": id, "name": name, "mod": now, "usn": -1, "desc": "", "dyn": 0, "conf": 1,
+        "collapsed": false, "extendNew": 0, "extendRev": 0,
+        "lrnToday": [0, 0], "revToday": [0, 0], "newToday": [0, 0], "timeToday": [0, 0],
+    });
+    json!({
+        "1": deck(1, "Default"),
+        did.to_string(): deck(did, name),
+    })
+}
+
+fn dconf() -> serde_json::Value {
+    json!({
+        "1": {
+            "id": 1, "name": "Default", "mod": 0, "usn": 0, "maxTaken": 60, "autoplay": true,
+            "timer": 0, "replayq": true, "dyn": false,
+            "new": { "bury": true, "delays": [1, 10], "initialFactor": 2500, "ints": [1, 4, 7], "order": 1, "perDay": 20, "separate": true },
+            "lapse": { "delays": [10], "leechAction": 0, "leechFails": 8, "minInt": 1, "mult": 0 },
+            "rev": { "bury": true, "ease4": 1.3, "fuzz": 0.05, "ivlFct": 1, "maxIvl": 36500, "minSpace": 1, "perDay": 100 },
+        }
+    })
+}
+
+fn write_collection(conn: &Connection, deck_name: &str, notes: &[NoteData]) -> ReaderResult<usize> {
+    let now = Utc::now();
+    let now_ms = now.timestamp_millis();
+    let now_s = now.timestamp();
+    let did = deck_id(deck_name);
+    let conf = json!({
+        "nextPos": notes.len() + 1, "estTimes": true, "activeDecks": [1], "sortType": "noteFld",
+        "timeLim": 0, "sortBackwards": false, "addToCur": true, "curDeck": 1, "newBury": true,
+        "newSpread": 0, "dueCounts": true, "curModel": MODEL_ID.to_string(), "collapseTime": 1200,
+    });
+
+    conn.execute_batch(SCHEMA)?;
+    conn.execute("INSERT INTO col VALUES (1, ?1, ?2, ?2, 11, 0, 0, 0, ?3, ?4, ?5, ?6, '{}')",
+        params![now_s, now_ms, conf.to_string(), models(did, now_s).to_string(), deck(did, deck_name, now_s).to_string(), dconf().to_string()])?;
+
+    let mut cards = 0;
+    for (ix, note) in notes.iter().enumerate() {
+        let nid = now_ms + ix as i64;
+        let sentence_cloze = cloze(note.sentence.as_str(), note.hanzi.as_str());
+        let flds = [
+            note.hanzi.as_str(), note.pinyin.as_str(), note.meaning.as_str(),
+            note.sentence.as_str(), sentence_cloze.as_str(), note.source.as_str(),
+        ].join(FIELD_SEP);
+        conn.execute("INSERT INTO notes VALUES (?1, ?2, ?3, ?4, -1, ?5, ?6, ?7, ?8, 0, '')",
+            params![nid, guid(note.hanzi.as_str()), MODEL_ID, now_s, " cnreader ", flds, note.hanzi, checksum(note.hanzi.as_str())])?;
+
+        let ords = if sentence_cloze.is_empty() { vec![0] } else { vec![0, 1] };
+        for ord in ords {
+            conn.execute("INSERT INTO cards VALUES (?1, ?2, ?3, ?4, ?5, -1, 0, 0, ?6, 0, 0, 0, 0, 0, 0, 0, 0, '')",
+                params![nid * 10 + ord, nid, did, ord, now_s, ix as i64 + 1])?;
+            cards += 1;
+        }
+    }
+    Ok(cards)
+}
+
+/// Write the notes as an Anki package, returns the number of cards
+pub fn export_apkg(path: impl AsRef<Path>, deck_name: &str, notes: &[NoteData]) -> ReaderResult<usize> {
+    let tmp = TempFile::new("anki2");
+    debug!("Writing collection to {:?}", tmp.0);
+
+    let cards = {
+        let conn = Connection::open(&tmp.0)?;
+        write_collection(&conn, deck_name, notes)?
+    };
+    let mut collection = vec![];
+    std::fs::File::open(&tmp.0)?.read_to_end(&mut collection)?;
+    drop(tmp);
+
+    let file = std::fs::File::create(path)?;
+    let mut zip = zip::ZipWriter::new(file);
+    let options = SimpleFileOptions::default();
+    zip.start_file("collection.anki2", options)?;
+    zip.write_all(&collection)?;
+    zip.start_file("media", options)?;
+    zip.write_all(b"{}")?;
+    zip.finish()?;
+    Ok(cards)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The collection of a package, with the file it was unpacked to
+    fn read_collection(path: &Path) -> (TempFile, Connection) {
+        let mut zip = zip::ZipArchive::new(std::fs::File::open(path).unwrap()).unwrap();
+        let mut media = String::new();
+        zip.by_name("media").unwrap().read_to_string(&mut media).unwrap();
+        assert_eq!(media, "{}");
+        let mut data = vec![];
+        zip.by_name("collection.anki2").unwrap().read_to_end(&mut data).unwrap();
+        let tmp = TempFile::new("anki2");
+        std::fs::write(&tmp.0, data).unwrap();
+        let conn = Connection::open(&tmp.0).unwrap();
+        (tmp, conn)
+    }
+
+    #[test]
+    fn test_guid() {
+        assert_eq!(guid("喜欢"), guid("喜欢"));
+        assert_ne!(guid("喜欢"), guid("欢喜"));
+        assert!(guid("喜欢").bytes().all(|b| GUID_CHARS.contains(&b)));
+    }
+
+    #[test]
+    fn test_export() {
+        let notes = vec![
+            NoteData { hanzi: String::from("喜欢"), pinyin: String::from("xi3 huan5"), meaning: String::from("to like"), sentence: String::from("我喜欢看书。"), source: String::from("课文"), ..Default::default() },
+            NoteData { hanzi: String::from("书"), pinyin: String::from("shu1"), meaning: String::from("book"), ..Default::default() },
+        ];
+        let path = TempFile::new("apkg");
+        assert_eq!(export_apkg(&path.0, "Reader", &notes).unwrap(), 3);
+
+        let (_collection, conn) = read_collection(&path.0);
+        let flds: String = conn.query_row("SELECT flds FROM notes WHERE guid = ?1", [guid("喜欢")], |row| row.get(0)).unwrap();
+        let flds: Vec<&str> = flds.split(FIELD_SEP).collect();
+        assert_eq!(flds[0], "喜欢");
+        assert_eq!(flds[4], "我[…]看书。");
+        let models: String = conn.query_row("SELECT models FROM col", [], |row| row.get(0)).unwrap();
+        let models: serde_json::Value = serde_json::from_str(models.as_str()).unwrap();
+        assert_eq!(models[MODEL_ID.to_string()]["flds"].as_array().unwrap().len(), 6);
+        let cards: i64 = conn.query_row("SELECT count(*) FROM cards WHERE did = ?1", [deck_id("Reader")], |row| row.get(0)).unwrap();
+        assert_eq!(cards, 3);
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::utils::is_chinese_char;
+
+/// Entries of the list of characters that unlock unknown words
+const UNLOCK_LIST: usize = 30;
+
+/// Character level knowledge
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CharStats {
+    pub known: usize,
+    /// Known characters among the characters of HSK words
+    pub hsk_known: usize,
+    pub hsk_total: usize,
+    /// Unknown characters of a text with their number of uses, most used first
+    pub new_in_text: Vec<(char, usize)>,
+    /// Characters that are all that is missing to read unknown HSK words, with those words
+    pub unlocks: Vec<(char, Vec<String>)>,
+}
+
+/// The characters used by HSK words
+pub fn hsk_chars<'a>(hsk_words: impl Iterator<Item = &'a str>) -> HashSet<char> {
+    hsk_words.flat_map(|w| w.chars().filter(is_chinese_char)).collect()
+}
+
+pub fn new_chars(text: &str, known: &HashSet<char>) -> Vec<(char, usize)> {
+    let mut counts: HashMap<char, usize> = HashMap::new();
+    for c in text.chars().filter(|c| is_chinese_char(c) && !known.contains(c)) {
+        *counts.entry(c).or_default() += 1;
+    }
+    let mut res: Vec<(char, usize)> = counts.into_iter().collect();
+    res.sort_by(|(c1, n1), (c2, n2)| n2.cmp(n1).then(c1.cmp(c2)));
+    res
+}
+
+/// Characters that are the only unknown one in the most words
+pub fn unlocking_chars<'a>(words: impl Iterator<Item = &'a str>, known: &HashSet<char>, n: usize) -> Vec<(char, Vec<String>)> {
+    let mut seen = HashSet::new();
+    let mut unlocks: HashMap<char, Vec<String>> = HashMap::new();
+    for word in words.filter(|w| seen.insert(*w)) {
+        let missing: HashSet<char> = word.chars()
+            .filter(|c| is_chinese_char(c) && !known.contains(c))
+            .collect();
+        if missing.len() == 1 {
+            let c = missing.into_iter().next().unwrap();
+            unlocks.entry(c).or_default().push(word.to_string());
+        }
+    }
+    let mut res: Vec<(char, Vec<String>)> = unlocks.into_iter().collect();
+    res.iter_mut().for_each(|(_, words)| words.sort());
+    res.sort_by(|(c1, w1), (c2, w2)| w2.len().cmp(&w1.len()).then(c1.cmp(c2)));
+    res.truncate(n);
+    res
+}
+
+impl CharStats {
+    /// `is_known` tells the HSK words already learned and `known` their characters
+    pub fn new(hsk_words: &[&str], is_known: impl Fn(&str) -> bool, known: &HashSet<char>, text: &str) -> Self {
+        let hsk = hsk_chars(hsk_words.iter().copied());
+        let unknown_words = hsk_words.iter()
+            .copied()
+            .filter(|w| !is_known(w));
+        Self {
+            known: known.len(),
+            hsk_known: hsk.intersection(known).count(),
+            hsk_total: hsk.len(),
+            new_in_text: new_chars(text, known),
+            unlocks: unlocking_chars(unknown_words, known, UNLOCK_LIST),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_char_stats() {
+        let hsk = ["你好", "好", "喜欢", "欢迎", "欢乐", "谢谢", "迎接"];
+        assert_eq!(hsk_chars(["你好", "好", "A好"].into_iter()), HashSet::from(['你', '好']));
+
+        let known: HashSet<char> = "你好喜".chars().collect();
+        let stats = CharStats::new(&hsk, |w| ["你好", "好"].contains(&w), &known, "你好！欢迎欢迎，谢谢。");
+        assert_eq!((stats.known, stats.hsk_known, stats.hsk_total), (3, 3, 8));
+        assert_eq!(stats.new_in_text, vec![('欢', 2), ('谢', 2), ('迎', 2)]);
+        // 欢 completes 喜欢, 谢 completes 谢谢; 欢迎 and 欢乐 miss two characters
+        assert_eq!(stats.unlocks, vec![('欢', vec![String::from("喜欢")]), ('谢', vec![String::from("谢谢")])]);
+    }
+}
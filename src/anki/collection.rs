@@ -0,0 +1,231 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use rusqlite::Connection;
+use tracing::{debug, warn};
+
+use crate::error::{ReaderError, ReaderResult};
+
+const SQLITE_MAGIC: &[u8] = b"SQLite format 3\0";
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+/// Collection files inside a package, newest format first
+const PACKAGE_FILES: [&str; 3] = ["collection.anki21b", "collection.anki21", "collection.anki2"];
+/// Attempts at copying a collection that keeps changing
+const COPY_RETRIES: usize = 3;
+
+/// Private copy of a collection, readable while Anki has it open and removed when dropped
+pub struct Snapshot {
+    conn: Option<Connection>,
+    dir: PathBuf,
+}
+
+impl Snapshot {
+    pub fn conn(&self) -> &Connection {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        // Close the database before removing its files
+        drop(self.conn.take());
+        if let Err(e) = std::fs::remove_dir_all(&self.dir) {
+            warn!("Error removing Anki snapshot {:?}: {}", self.dir, e);
+        }
+    }
+}
+
+fn wal_path(path: &Path) -> PathBuf {
+    let mut wal = path.as_os_str().to_owned();
+    wal.push("-wal");
+    PathBuf::from(wal)
+}
+
+/// Last change of the collection, including changes still in the write-ahead log
+pub fn modified(path: impl AsRef<Path>) -> Option<SystemTime> {
+    let path = path.as_ref();
+    let db = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let wal = std::fs::metadata(wal_path(path)).and_then(|m| m.modified()).ok();
+    std::cmp::max(db, wal)
+}
+
+fn copy_sqlite(path: &Path, dst: &Path) -> ReaderResult<()> {
+    let wal = wal_path(path);
+    for _ in 0..COPY_RETRIES {
+        let before = modified(path);
+        std::fs::copy(path, dst)?;
+        if wal.exists() {
+            std::fs::copy(&wal, wal_path(dst))?;
+        } else {
+            let _ = std::fs::remove_file(wal_path(dst));
+        }
+        if modified(path) == before {
+            return Ok(());
+        }
+        debug!("Anki collection changed while copying, retrying");
+    }
+    Err(ReaderError::Anki(format!("{:?} keeps changing", path)))
+}
+
+fn unpack_package(path: &Path, dst: &Path) -> ReaderResult<()> {
+    let mut zip = zip::ZipArchive::new(std::fs::File::open(path)?)?;
+    for name in PACKAGE_FILES {
+        if let Ok(mut file) = zip.by_name(name) {
+            debug!("Unpacking {} from {:?}", name, path);
+            let mut data = vec![];
+            file.read_to_end(&mut data)?;
+            return write_collection(data, dst);
+        }
+    }
+    Err(ReaderError::Anki(format!("{:?} has no collection", path)))
+}
+
+fn write_collection(data: Vec<u8>, dst: &Path) -> ReaderResult<()> {
+    let data = if data.starts_with(ZSTD_MAGIC) {
+        zstd::decode_all(data.as_slice())?
+    } else {
+        data
+    };
+    if !data.starts_with(SQLITE_MAGIC) {
+        return Err(ReaderError::Anki(String::from("not an Anki collection")));
+    }
+    std::fs::write(dst, data)?;
+    Ok(())
+}
+
+/// Copy a collection (`collection.anki2`, zstd compressed `collection.anki21b`, or a
+/// `.colpkg`/`.apkg` package) and open the copy
+pub fn snapshot(path: impl AsRef<Path>) -> ReaderResult<Snapshot> {
+    let path = path.as_ref();
+    let mut magic = [0u8; 16];
+    let n = std::fs::File::open(path)?.read(&mut magic)?;
+    let magic = &magic[..n];
+
+    let mut dir = std::env::temp_dir();
+    dir.push(format!("{}-anki-{}", crate::utils::APP_NAME, crate::utils::random_name()));
+    std::fs::create_dir_all(&dir)?;
+    let dst = dir.join("collection.anki2");
+
+    let copied = if magic.starts_with(SQLITE_MAGIC) {
+        copy_sqlite(path, &dst)
+    } else if magic.starts_with(ZIP_MAGIC) {
+        unpack_package(path, &dst)
+    } else {
+        std::fs::read(path)
+            .map_err(ReaderError::from)
+            .and_then(|data| write_collection(data, &dst))
+    };
+    let conn = copied.and_then(|_| {
+        let conn = Connection::open(&dst)?;
+        conn.pragma_update(None, "query_only", true)?;
+        Ok(conn)
+    });
+    match conn {
+        Ok(conn) => {
+            debug!("Anki snapshot of {:?} in {:?}", path, dir);
+            Ok(Snapshot { conn: Some(conn), dir })
+        }
+        Err(e) => {
+            let _ = std::fs::remove_dir_all(&dir);
+            Err(e)
+        }
+    }
+}
+
+/// Anki's data directory, holding a folder for each profile
+pub fn anki_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("Anki2"))
+}
+
+/// Collection set in the config, or the first Anki profile found
+pub fn resolve(anki_fname: &Option<String>) -> Option<PathBuf> {
+    match anki_fname.as_ref().filter(|f| !f.is_empty()) {
+        Some(f) => Some(PathBuf::from(f)),
+        None => profiles().into_iter().next(),
+    }
+}
+
+/// Collections of the Anki profiles found in the data directory. Newer Anki versions
+/// may only keep a `collection.anki21b`
+pub fn profiles() -> Vec<PathBuf> {
+    let Some(dir) = anki_dir() else {
+        return vec![];
+    };
+    let mut res: Vec<PathBuf> = std::fs::read_dir(dir).into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| ["collection.anki2", "collection.anki21b"].into_iter()
+            .map(|name| e.path().join(name))
+            .find(|p| p.is_file()))
+        .collect();
+    res.sort();
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("test-{}", crate::utils::random_name()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    fn count(snapshot: &Snapshot) -> i64 {
+        snapshot.conn().query_row("SELECT count(*) FROM notes", [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_live_collection() {
+        let path = temp_path("collection.anki2");
+        // Like a running Anki: WAL, exclusive lock and changes not yet checkpointed
+        let live = Connection::open(&path).unwrap();
+        live.execute_batch(r#"
+            PRAGMA journal_mode = WAL;
+            PRAGMA wal_autocheckpoint = 0;
+            PRAGMA locking_mode = EXCLUSIVE;
+            CREATE TABLE notes (id integer primary key, sfld text);
+            INSERT INTO notes VALUES (1, '喜欢'), (2, '书');
+        "#).unwrap();
+        assert!(wal_path(&path).exists());
+        assert!(modified(&path).is_some());
+
+        let snapshot = snapshot(&path).unwrap();
+        assert_eq!(count(&snapshot), 2);
+        assert!(snapshot.conn().execute("DELETE FROM notes", []).is_err());
+        let dir = snapshot.dir.clone();
+        drop(snapshot);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_compressed_collection() {
+        let path = temp_path("collection.anki2");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch("CREATE TABLE notes (id integer primary key, sfld text); INSERT INTO notes VALUES (1, '喜欢');").unwrap();
+        drop(conn);
+        let data = zstd::encode_all(std::fs::read(&path).unwrap().as_slice(), 0).unwrap();
+
+        let anki21b = path.with_file_name("collection.anki21b");
+        std::fs::write(&anki21b, &data).unwrap();
+        assert_eq!(count(&snapshot(&anki21b).unwrap()), 1);
+
+        let colpkg = path.with_file_name("backup.colpkg");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&colpkg).unwrap());
+        let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        zip.start_file("collection.anki2", options).unwrap();
+        zip.write_all(b"SQLite format 3\0 legacy dummy").unwrap();
+        zip.start_file("collection.anki21b", options).unwrap();
+        zip.write_all(&data).unwrap();
+        zip.finish().unwrap();
+        assert_eq!(count(&snapshot(&colpkg).unwrap()), 1);
+
+        std::fs::write(&anki21b, b"garbage").unwrap();
+        assert!(matches!(snapshot(&anki21b), Err(ReaderError::Anki(_))));
+    }
+}
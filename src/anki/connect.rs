@@ -0,0 +1,217 @@
+use reqwest::Client;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+use tracing::debug;
+
+use crate::config::AnkiConnectConfig;
+use crate::error::{ReaderError, ReaderResult};
+
+const API_VERSION: u32 = 6;
+
+/// Data of a note created from the reader
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NoteData {
+    pub hanzi: String,
+    pub pinyin: String,
+    pub meaning: String,
+    pub sentence: String,
+    pub translation: String,
+    pub source: String,
+}
+
+impl NoteData {
+    /// The sentence with the first use of the word as an Anki cloze deletion, pinyin as hint.
+    /// Empty when the sentence does not have the word
+    pub fn cloze(&self) -> String {
+        if self.hanzi.is_empty() || !self.sentence.contains(self.hanzi.as_str()) {
+            return String::new();
+        }
+        let deletion = if self.pinyin.is_empty() {
+            format!("{{{{c1::{}}}}}", self.hanzi)
+        } else {
+            format!("{{{{c1::{}::{}}}}}", self.hanzi, self.pinyin)
+        };
+        self.sentence.replacen(self.hanzi.as_str(), deletion.as_str(), 1)
+    }
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    action: &'a str,
+    version: u32,
+    params: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct Response<T> {
+    result: Option<T>,
+    error: Option<String>,
+}
+
+/// Client for AnkiConnect and compatible servers
+pub struct AnkiConnect {
+    client: Client,
+    conf: AnkiConnectConfig,
+}
+
+impl AnkiConnect {
+    pub fn new(conf: &AnkiConnectConfig) -> Self {
+        Self {
+            client: Client::new(),
+            conf: conf.clone(),
+        }
+    }
+
+    async fn invoke<T: DeserializeOwned>(&self, action: &str, params: Value) -> ReaderResult<T> {
+        debug!("AnkiConnect {}: {}", action, params);
+        let request = Request {
+            action,
+            version: API_VERSION,
+            params,
+            key: self.conf.key.as_deref().filter(|k| !k.is_empty()),
+        };
+        let response: Response<T> = self.client.post(self.conf.url.as_str())
+            .json(&request)
+            .send()
+            .await?
+            .json()
+            .await?;
+        match (response.result, response.error) {
+            (_, Some(e)) => Err(ReaderError::Anki(e)),
+            (Some(r), None) => Ok(r),
+            (None, None) => Err(ReaderError::Anki(format!("{}: empty result", action))),
+        }
+    }
+
+    pub async fn version(&self) -> ReaderResult<u32> {
+        self.invoke("version", json!({})).await
+    }
+
+    pub async fn deck_names(&self) -> ReaderResult<Vec<String>> {
+        self.invoke("deckNames", json!({})).await
+    }
+
+    pub async fn model_names(&self) -> ReaderResult<Vec<String>> {
+        self.invoke("modelNames", json!({})).await
+    }
+
+    pub async fn model_field_names(&self, model: &str) -> ReaderResult<Vec<String>> {
+        self.invoke("modelFieldNames", json!({ "modelName": model })).await
+    }
+
+    /// Note fields according to the configured mapping, unmapped values are skipped
+    pub fn fields(&self, note: &NoteData) -> serde_json::Map<String, Value> {
+        let f = &self.conf.fields;
+        [
+            (&f.hanzi, &note.hanzi),
+            (&f.pinyin, &note.pinyin),
+            (&f.meaning, &note.meaning),
+            (&f.sentence, &note.sentence),
+            (&f.cloze, &note.cloze()),
+            (&f.translation, &note.translation),
+            (&f.source, &note.source),
+        ].into_iter()
+            .filter(|(name, _)| !name.is_empty())
+            .map(|(name, value)| (name.clone(), Value::String(value.clone())))
+            .collect()
+    }
+
+    /// Add a note, returns its id
+    pub async fn add_note(&self, note: &NoteData) -> ReaderResult<i64> {
+        let params = json!({
+            "note": {
+                "deckName": self.conf.deck,
+                "modelName": self.conf.note_type,
+                "fields": self.fields(note),
+                "options": { "allowDuplicate": false },
+                "tags": self.conf.tags,
+            }
+        });
+        self.invoke("addNote", params).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Minimal stand-in for AnkiConnect, answers `requests` requests and returns their bodies
+    async fn stand_in(requests: usize) -> (String, tokio::task::JoinHandle<Vec<Value>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let mut bodies = vec![];
+            for _ in 0..requests {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = vec![];
+                let mut chunk = [0u8; 4096];
+                let body = loop {
+                    let n = socket.read(&mut chunk).await.unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+                    let s = String::from_utf8_lossy(&buf).to_string();
+                    if let Some(ix) = s.find("\r\n\r\n") {
+                        let len = s[..ix].lines()
+                            .find_map(|l| l.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                            .unwrap_or(0);
+                        if buf.len() >= ix + 4 + len {
+                            break serde_json::from_slice::<Value>(&buf[ix + 4..ix + 4 + len]).unwrap();
+                        }
+                    }
+                };
+                let result = match body["action"].as_str() {
+                    Some("version") => json!({ "result": 6, "error": null }),
+                    Some("addNote") if body["params"]["note"]["fields"]["Hanzi"] == "重复" => json!({ "result": null, "error": "cannot create note because it is a duplicate" }),
+                    Some("addNote") => json!({ "result": 1496198395707i64, "error": null }),
+                    _ => json!({ "result": null, "error": "unsupported action" }),
+                }.to_string();
+                let response = format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", result.len(), result);
+                socket.write_all(response.as_bytes()).await.unwrap();
+                socket.shutdown().await.unwrap();
+                bodies.push(body);
+            }
+            bodies
+        });
+        (url, handle)
+    }
+
+    fn note() -> NoteData {
+        NoteData {
+            hanzi: String::from("喜欢"),
+            pinyin: String::from("xi3 huan5"),
+            meaning: String::from("to like"),
+            sentence: String::from("我喜欢看书。"),
+            translation: String::from("I like reading."),
+            source: String::from("课文"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_note() {
+        let (url, handle) = stand_in(3).await;
+        let mut conf = AnkiConnectConfig { url, ..Default::default() };
+        conf.fields.source = String::new();
+        conf.fields.cloze = String::from("Text");
+        let anki = AnkiConnect::new(&conf);
+
+        assert_eq!(anki.version().await.unwrap(), 6);
+        assert_eq!(anki.add_note(&note()).await.unwrap(), 1496198395707);
+        let duplicate = NoteData { hanzi: String::from("重复"), ..note() };
+        assert!(matches!(anki.add_note(&duplicate).await, Err(ReaderError::Anki(_))));
+
+        let bodies = handle.await.unwrap();
+        assert_eq!(bodies[0]["action"], "version");
+        assert_eq!(bodies[1]["version"], 6);
+        let sent = &bodies[1]["params"]["note"];
+        assert_eq!(sent["deckName"], conf.deck.as_str());
+        assert_eq!(sent["modelName"], conf.note_type.as_str());
+        assert_eq!(sent["fields"]["Hanzi"], "喜欢");
+        assert_eq!(sent["fields"]["Sentence"], "我喜欢看书。");
+        assert_eq!(sent["fields"]["Text"], "我{{c1::喜欢::xi3 huan5}}看书。");
+        assert!(sent["fields"].get("Translation").is_none());
+        assert!(sent["fields"].get("Source").is_none());
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+use crate::utils::is_chinese_char;
+
+static SOUND: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[sound:[^\]]*\]").unwrap());
+static CLOZE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{\{c\d+::(.*?)(::[^}]*)?\}\}").unwrap());
+static RUBY_TEXT: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?is)<(rt|rp)[^>]*>.*?</(rt|rp)>").unwrap());
+static LINE_BREAK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)<br\s*/?>|</div>|</p>").unwrap());
+static TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?s)<[^>]*>").unwrap());
+/// Furigana style readings, like 中国[zhōngguó]
+static READING: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[[^\]]*\]").unwrap());
+
+/// Plain text of a note field without markup, sound tags, cloze syntax and readings
+pub fn clean_field(field: &str) -> String {
+    let s = SOUND.replace_all(field, "");
+    let s = CLOZE.replace_all(&s, "$1");
+    let s = RUBY_TEXT.replace_all(&s, "");
+    let s = LINE_BREAK.replace_all(&s, " ");
+    let s = TAG.replace_all(&s, "");
+    let s = READING.replace_all(&s, "");
+    s.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+        .replace('\n', " ")
+        .trim()
+        .to_string()
+}
+
+/// First run of Chinese characters in a field, if there is one
+pub fn hanzi(field: &str) -> Option<String> {
+    let cleaned = clean_field(field);
+    let word: String = cleaned.chars()
+        .skip_while(|c| !is_chinese_char(c))
+        .take_while(is_chinese_char)
+        .collect();
+    if word.is_empty() { None } else { Some(word) }
+}
+
+/// The word of a note: from the mapped field, else the sort field, else any field with Hanzi
+pub fn note_word(fields: &[&str], field_ix: Option<usize>, sfld: &str) -> Option<String> {
+    match field_ix {
+        Some(ix) => fields.get(ix).and_then(|f| hanzi(f)),
+        None => hanzi(sfld).or_else(|| fields.iter().find_map(|f| hanzi(f))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_field() {
+        assert_eq!(clean_field("<b>学生</b>&nbsp;[sound:xuesheng.mp3]"), "学生");
+        assert_eq!(clean_field("我{{c1::喜欢::like}}看书"), "我喜欢看书");
+        assert_eq!(clean_field("<ruby>中<rt>zhōng</rt></ruby><ruby>国<rt>guó</rt></ruby>"), "中国");
+        assert_eq!(clean_field(" 中国[zhōngguó]"), "中国");
+        assert_eq!(clean_field("<div>一</div><div>二</div>"), "一 二");
+    }
+
+    #[test]
+    fn test_note_word() {
+        // Sort field is pinyin
+        let fields = ["xǐhuan", "<span style=\"font-size: 40px\">喜欢</span> / 喜歡", "to like"];
+        assert_eq!(note_word(&fields, None, "xǐhuan"), Some(String::from("喜欢")));
+        assert_eq!(note_word(&fields, Some(1), "xǐhuan"), Some(String::from("喜欢")));
+        assert_eq!(note_word(&fields, Some(2), "xǐhuan"), None);
+        assert_eq!(note_word(&["hello"], None, "hello"), None);
+    }
+}
@@ -0,0 +1,472 @@
+pub mod apkg;
+pub mod chars;
+pub mod collection;
+pub mod connect;
+pub mod fields;
+pub mod stats;
+
+use std::collections::{HashMap, HashSet};
+use chrono::{DateTime, Days, Utc};
+use rusqlite::Connection;
+use tracing::{debug, warn};
+use std::fmt;
+
+use crate::config::AnkiNotesConfig;
+use crate::error::{ReaderError, ReaderResult};
+
+
+/// Interval in days from which a card counts as mature, same as in Anki
+pub const MATURE_IVL: i64 = 21;
+
+/// How well a word is known, from the scheduling state of its cards
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Knowledge {
+    Suspended,
+    New,
+    Learning,
+    Young,
+    Mature,
+}
+
+impl Knowledge {
+    pub const ALL: &'static [Self] = &[Knowledge::New, Knowledge::Learning, Knowledge::Young, Knowledge::Mature, Knowledge::Suspended];
+
+    /// Class of a single card from its type, queue and interval
+    pub fn from_card(ctype: i64, queue: i64, ivl: i64) -> Self {
+        match (ctype, queue) {
+            (_, -1) => Knowledge::Suspended,
+            (0, _) => Knowledge::New,
+            (1, _) | (3, _) => Knowledge::Learning,
+            _ if ivl >= MATURE_IVL => Knowledge::Mature,
+            _ => Knowledge::Young,
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            Knowledge::Suspended => t!("anki_suspended").to_string(),
+            Knowledge::New => t!("anki_new").to_string(),
+            Knowledge::Learning => t!("anki_learning").to_string(),
+            Knowledge::Young => t!("anki_young").to_string(),
+            Knowledge::Mature => t!("anki_mature").to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Knowledge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.label())
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct AnkiEntry {
+    pub word: String,
+    deck: i64,
+    added: DateTime<Utc>,
+    pub deck_name: String,
+    pub knowledge: Knowledge,
+    /// Longest interval of the word's cards, in days
+    pub interval: i64,
+    pub lapses: i64,
+    pub reviews: i64,
+    pub last_review: Option<DateTime<Utc>>,
+}
+
+impl Eq for AnkiEntry {}
+
+impl std::hash::Hash for AnkiEntry {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        state.write(self.word.as_bytes());
+    }
+}
+
+impl AnkiEntry {
+    pub fn new(word: &str, did: i64, id: i64) -> Self {
+        let added = DateTime::from_timestamp_millis(id)
+            .unwrap_or_else(Utc::now);
+
+        Self {
+            word: word.to_string(),
+            added,
+            deck: did,
+            deck_name: String::new(),
+            knowledge: Knowledge::New,
+            interval: 0,
+            lapses: 0,
+            reviews: 0,
+            last_review: None,
+        }
+    }
+
+    pub fn added(&self) -> DateTime<Utc> {
+        self.added
+    }
+
+    /// Combine with another card of the same word, the best known card decides
+    fn merge(&mut self, other: AnkiEntry) {
+        self.knowledge = std::cmp::max(self.knowledge, other.knowledge);
+        self.interval = std::cmp::max(self.interval, other.interval);
+        self.lapses += other.lapses;
+        self.reviews += other.reviews;
+        self.last_review = std::cmp::max(self.last_review, other.last_review);
+    }
+}
+
+impl fmt::Display for AnkiEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}]", self.word, self.added)
+    }
+}
+
+pub fn last_n_days(conn: &Connection, conf: &AnkiNotesConfig, n: u64) -> ReaderResult<Vec<AnkiEntry>> {
+    let d_from = Utc::now()
+        .checked_sub_days(Days::new(n))
+        .unwrap();
+    let d_from_timestamp = d_from.timestamp_millis();
+    let words = note_words(conn, conf)?;
+    let decks = deck_names(conn)?;
+    let mut st = conn.prepare("SELECT N.id, (SELECT MIN(C.did) FROM cards C WHERE C.nid = N.id) FROM notes N WHERE N.id > ?")?;
+    let rows = st.query_map([d_from_timestamp], |row| {
+        Ok((row.get::<_, i64>(0)?, row.get::<_, Option<i64>>(1)?.unwrap_or(0)))
+    })?;
+
+    let mut results = Vec::new();
+    for row in rows {
+        let (id, did) = row?;
+        let deck_name = decks.get(&did).cloned().unwrap_or_default();
+        if let Some(word) = words.get(&id)
+            && conf.deck_included(deck_name.as_str()) {
+            let mut entry = AnkiEntry::new(word.as_str(), did, id);
+            entry.deck_name = deck_name;
+            results.push(entry);
+        }
+    }
+    Ok(results)
+}
+
+pub fn search_anki(conn: &Connection, sel: &str) -> ReaderResult<Vec<String>> {
+    let mut st = conn.prepare("SELECT REPLACE(sfld, CHAR(10), ' ') FROM notes WHERE REPLACE(sfld, CHAR(10), '') LIKE ?")?;
+    let pattern = format!("%{}%", sel);
+    let rows = st.query_map([pattern], |row| row.get(0))?;
+    let mut results = Vec::new();
+    for row in rows {
+        results.push(row?);
+    }
+    Ok(results)
+}
+
+pub fn find_anki(conn: &Connection, sel: &str) -> ReaderResult<bool> {
+    let exists: bool = conn.query_row(
+    "SELECT REPLACE(sfld, CHAR(10), ' ') FROM notes WHERE REPLACE(sfld, CHAR(10), '') = ?",
+    [&sel],
+    |row| row.get(0),
+    )?;
+    Ok(exists)
+}
+
+pub fn count_anki(conn: &Connection) -> ReaderResult<i64> {
+    let res = conn.query_row("SELECT COUNT(*) FROM notes",
+        [], |row| row.get(0) )?;
+    Ok(res)
+}
+
+
+
+fn table_exists(conn: &Connection, name: &str) -> ReaderResult<bool> {
+    let exists: i32 = conn.query_row(
+        "SELECT count(*) FROM sqlite_master WHERE type='table' AND name=?1",
+        [name],
+        |row| row.get(0)
+    )?;
+    Ok(exists > 0)
+}
+
+fn col_json(conn: &Connection, column: &str) -> ReaderResult<serde_json::Value> {
+    let json: String = conn.query_row(format!("SELECT {} FROM col", column).as_str(), [], |row| row.get(0))?;
+    serde_json::from_str(json.as_str())
+        .map_err(|e| ReaderError::Anki(e.to_string()))
+}
+
+/// Name and field names of each note type
+fn note_types(conn: &Connection) -> ReaderResult<HashMap<i64, (String, Vec<String>)>> {
+    let mut res = HashMap::new();
+    if table_exists(conn, "notetypes")? {
+        let mut st = conn.prepare("SELECT id, name FROM notetypes")?;
+        let rows = st.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
+        for row in rows {
+            let (id, name) = row?;
+            res.insert(id, (name, vec![]));
+        }
+        let mut st = conn.prepare("SELECT ntid, name FROM fields ORDER BY ntid, ord")?;
+        let rows = st.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
+        for row in rows {
+            let (id, field) = row?;
+            if let Some((_, fields)) = res.get_mut(&id) {
+                fields.push(field);
+            }
+        }
+    } else {
+        // Older collections keep note types as JSON
+        let models = col_json(conn, "models")?;
+        for model in models.as_object().into_iter().flat_map(|m| m.values()) {
+            let mut fields: Vec<(i64, String)> = model["flds"].as_array().into_iter().flatten()
+                .map(|f| (f["ord"].as_i64().unwrap_or(0), f["name"].as_str().unwrap_or_default().to_string()))
+                .collect();
+            fields.sort();
+            if let Some(id) = model["id"].as_i64() {
+                let name = model["name"].as_str().unwrap_or_default().to_string();
+                res.insert(id, (name, fields.into_iter().map(|(_, f)| f).collect()));
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Full names of the decks, with `::` between parent and subdeck
+pub fn deck_names(conn: &Connection) -> ReaderResult<HashMap<i64, String>> {
+    let mut res = HashMap::new();
+    if table_exists(conn, "decks")? {
+        let mut st = conn.prepare("SELECT id, name FROM decks")?;
+        let rows = st.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
+        for row in rows {
+            let (id, name) = row?;
+            res.insert(id, name.replace('\x1f', "::"));
+        }
+    } else {
+        let decks = col_json(conn, "decks")?;
+        for deck in decks.as_object().into_iter().flat_map(|d| d.values()) {
+            if let Some(id) = deck["id"].as_i64() {
+                res.insert(id, deck["name"].as_str().unwrap_or_default().to_string());
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Word of each note of the selected note types, by note id
+pub fn note_words(conn: &Connection, conf: &AnkiNotesConfig) -> ReaderResult<HashMap<i64, String>> {
+    let types = note_types(conn)?;
+    let mut mapping = HashMap::<i64, usize>::new();
+    for nt in &conf.note_types {
+        match types.iter().find(|(_, (name, _))| *name == nt.name) {
+            Some((id, (_, fields))) => match fields.iter().position(|f| *f == nt.field) {
+                Some(ix) => {
+                    mapping.insert(*id, ix);
+                }
+                None => warn!("Anki note type {} has no field {}", nt.name, nt.field),
+            },
+            None => warn!("Anki note type {} not found", nt.name),
+        }
+    }
+
+    // Notes with a card in one of the selected decks
+    let mut in_decks = None;
+    if !conf.include_decks.is_empty() || !conf.exclude_decks.is_empty() {
+        let decks = deck_names(conn)?;
+        let mut st = conn.prepare("SELECT DISTINCT nid, did FROM cards")?;
+        let rows = st.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))?;
+        let mut ids = HashSet::new();
+        for row in rows {
+            let (nid, did) = row?;
+            if conf.deck_included(decks.get(&did).map(|d| d.as_str()).unwrap_or_default()) {
+                ids.insert(nid);
+            }
+        }
+        in_decks = Some(ids);
+    }
+
+    let mut st = conn.prepare("SELECT id, mid, flds, CAST(sfld AS TEXT) FROM notes")?;
+    let rows = st.query_map([], |row| {
+        Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, String>(2)?, row.get::<_, String>(3)?))
+    })?;
+    let mut res = HashMap::new();
+    for row in rows {
+        let (id, mid, flds, sfld) = row?;
+        let field_ix = mapping.get(&mid).copied();
+        if (!conf.note_types.is_empty() && field_ix.is_none())
+            || in_decks.as_ref().is_some_and(|ids| !ids.contains(&id)) {
+            continue;
+        }
+        let fields: Vec<&str> = flds.split('\x1f').collect();
+        if let Some(word) = fields::note_word(&fields, field_ix, sfld.as_str()) {
+            res.insert(id, word);
+        }
+    }
+    debug!("Anki notes with words: {}", res.len());
+    Ok(res)
+}
+
+pub fn anki_chars(conn: &Connection, conf: &AnkiNotesConfig) -> ReaderResult<HashSet<char>> {
+    Ok(note_words(conn, conf)?.values()
+        .flat_map(|w| w.chars())
+        .collect())
+}
+
+pub fn anki_words(conn: &Connection, conf: &AnkiNotesConfig) -> ReaderResult<HashSet<String>> {
+    Ok(note_words(conn, conf)?.into_values().collect())
+}
+
+pub fn anki_words_entry(conn: &Connection, conf: &AnkiNotesConfig) -> ReaderResult<HashMap<String, AnkiEntry>> {
+    let words = note_words(conn, conf)?;
+    let decks = deck_names(conn)?;
+    let mut st = conn.prepare(
+            r#"
+            SELECT C.nid,C.did,C.type,C.queue,C.ivl,C.lapses,
+                (SELECT COUNT(*) FROM revlog R WHERE R.cid = C.id),
+                (SELECT MAX(R.id) FROM revlog R WHERE R.cid = C.id)
+            FROM cards C 
+            "#
+        )?;
+    let rows = st.query_map([], |row| {
+        let ivl: i64 = row.get(4)?;
+        let last_review: Option<i64> = row.get(7)?;
+        Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, Knowledge::from_card(row.get(2)?, row.get(3)?, ivl),
+            // Negative intervals of learning cards are in seconds
+            std::cmp::max(ivl, 0), row.get::<_, i64>(5)?, row.get::<_, i64>(6)?, last_review))
+    })?;
+    let mut results = HashMap::<String, AnkiEntry>::new();
+    let mut rows_count: usize = 0;
+    for row in rows {
+        let (nid, deck, knowledge, interval, lapses, reviews, last_review) = row?;
+        rows_count = rows_count + 1;
+        let deck_name = decks.get(&deck).cloned().unwrap_or_default();
+        let Some(word) = words.get(&nid) else {
+            continue;
+        };
+        if !conf.deck_included(deck_name.as_str()) {
+            continue;
+        }
+        let r = AnkiEntry {
+            word: word.clone(),
+            deck,
+            added: DateTime::from_timestamp_millis(nid).unwrap_or(DateTime::<Utc>::MIN_UTC),
+            deck_name,
+            knowledge,
+            interval,
+            lapses,
+            reviews,
+            last_review: last_review.and_then(DateTime::from_timestamp_millis),
+        };
+        match results.get_mut(&r.word) {
+            Some(e) => e.merge(r),
+            None => {
+                results.insert(r.word.clone(), r);
+            }
+        }
+    }
+    debug!("Rows: {}", rows_count);
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AnkiNoteType, AnkiNotesField};
+    /// Collection with the tables of the current Anki schema
+    fn collection() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(r#"
+            CREATE TABLE notetypes (id integer primary key, name text);
+            CREATE TABLE fields (ntid integer, ord integer, name text);
+            CREATE TABLE notes (id integer primary key, mid integer, flds text, sfld text);
+            CREATE TABLE decks (id integer primary key, name text);
+            CREATE TABLE cards (id integer primary key, nid integer, did integer, type integer, queue integer, ivl integer, lapses integer);
+            CREATE TABLE revlog (id integer primary key, cid integer);
+            INSERT INTO notetypes VALUES (1, 'Basic'), (2, 'Mandarin');
+            INSERT INTO fields VALUES (1, 0, 'Front'), (1, 1, 'Back'), (2, 0, 'Pinyin'), (2, 1, 'Simplified'), (2, 2, 'Meaning');
+            INSERT INTO decks VALUES (1, 'HSK'), (2, 'Community'), (3, 'Community' || char(31) || 'Archive');
+        "#).unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_knowledge() {
+        let conn = collection();
+        conn.execute_batch(r#"
+            INSERT INTO notes VALUES (1, 1, '喜欢' || char(31) || 'to like', '喜欢'), (2, 1, '书', '书'), (3, 1, '看', '看'), (4, 1, '中国', '中国'), (5, 1, '学', '学');
+            INSERT INTO cards VALUES (10, 1, 1, 2, 2, 30, 1), (11, 1, 1, 2, 2, 5, 2);
+            INSERT INTO cards VALUES (20, 2, 1, 1, 1, -600, 0), (30, 3, 1, 0, 0, 0, 0);
+            INSERT INTO cards VALUES (40, 4, 1, 2, -1, 40, 0), (50, 5, 1, 2, 2, 3, 0);
+            INSERT INTO revlog VALUES (1700000000000, 10), (1700000100000, 11), (1700000200000, 11);
+        "#).unwrap();
+        let r = anki_words_entry(&conn, &AnkiNotesConfig::default()).unwrap();
+        assert_eq!(r.len(), 5);
+        assert_eq!(r["喜欢"].knowledge, Knowledge::Mature);
+        assert_eq!((r["喜欢"].interval, r["喜欢"].lapses, r["喜欢"].reviews), (30, 3, 3));
+        assert_eq!(r["喜欢"].last_review.unwrap().timestamp_millis(), 1700000200000);
+        assert_eq!(r["喜欢"].deck_name, "HSK");
+        assert_eq!(r["书"].knowledge, Knowledge::Learning);
+        assert_eq!(r["书"].interval, 0);
+        assert_eq!(r["看"].knowledge, Knowledge::New);
+        assert_eq!(r["中国"].knowledge, Knowledge::Suspended);
+        assert_eq!(r["学"].knowledge, Knowledge::Young);
+        assert!(r["学"].last_review.is_none());
+    }
+
+    #[test]
+    fn test_note_types() {
+        let conn = collection();
+        conn.execute_batch(r#"
+            INSERT INTO notes VALUES (1, 1, '<b>喜欢</b>[sound:a.mp3]' || char(31) || 'to like', '<b>喜欢</b>[sound:a.mp3]');
+            INSERT INTO notes VALUES (2, 2, 'shū' || char(31) || '<div>书</div>' || char(31) || 'book', 'shū');
+            INSERT INTO notes VALUES (3, 2, 'kàn' || char(31) || '看' || char(31) || 'look', 'kàn');
+            INSERT INTO cards VALUES (10, 1, 1, 2, 2, 30, 0), (20, 2, 2, 2, 2, 30, 0), (30, 3, 3, 2, 2, 30, 0);
+        "#).unwrap();
+        let mut conf = AnkiNotesConfig::default();
+        let r = anki_words_entry(&conn, &conf).unwrap();
+        assert_eq!(r.len(), 3);
+        assert!(r.contains_key("喜欢"));
+        assert_eq!(r["看"].deck_name, "Community::Archive");
+
+        conf.set(AnkiNotesField::NoteTypes, String::from("Mandarin:Simplified"));
+        conf.set(AnkiNotesField::ExcludeDecks, String::from("Community::Archive"));
+        let r = anki_words_entry(&conn, &conf).unwrap();
+        assert_eq!(r.keys().collect::<Vec<_>>(), vec!["书"]);
+
+        conf.set(AnkiNotesField::NoteTypes, String::new());
+        conf.set(AnkiNotesField::ExcludeDecks, String::new());
+        conf.set(AnkiNotesField::IncludeDecks, String::from("Community"));
+        assert_eq!(anki_words_entry(&conn, &conf).unwrap().len(), 2);
+        assert_eq!(anki_chars(&conn, &conf).unwrap(), HashSet::from(['书', '看']));
+    }
+
+    #[test]
+    fn test_legacy_collection() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(r#"
+            CREATE TABLE col (models text, decks text);
+            CREATE TABLE notes (id integer primary key, mid integer, flds text, sfld text);
+            CREATE TABLE cards (id integer primary key, nid integer, did integer, type integer, queue integer, ivl integer, lapses integer);
+            CREATE TABLE revlog (id integer primary key, cid integer);
+            INSERT INTO col VALUES (
+                '{"7": {"id": 7, "name": "Chinese", "flds": [{"name": "English", "ord": 0}, {"name": "Hanzi", "ord": 1}]}}',
+                '{"1": {"id": 1, "name": "Default"}, "5": {"id": 5, "name": "Chinese::HSK1"}}'
+            );
+            INSERT INTO notes VALUES (1, 7, 'good' || char(31) || '好', 'good');
+            INSERT INTO cards VALUES (10, 1, 5, 0, 0, 0, 0);
+        "#).unwrap();
+        let conf = AnkiNotesConfig {
+            note_types: vec![AnkiNoteType { name: String::from("Chinese"), field: String::from("Hanzi") }],
+            include_decks: vec![String::from("Chinese")],
+            ..Default::default()
+        };
+        let r = anki_words_entry(&conn, &conf).unwrap();
+        assert_eq!(r["好"].deck_name, "Chinese::HSK1");
+    }
+
+    #[test]
+    fn test_search() {
+        let conn = Connection::open("/home/morti/.local/share/Anki2/User 1/collection.anki2").unwrap();
+        let r = search_anki(&conn, "年").unwrap();
+        assert!(r.len() > 0);
+    }
+
+    #[test]
+    fn test_chars() {
+        let conn = Connection::open("/home/morti/.local/share/Anki2/User 1/collection.anki2").unwrap();
+        let r = anki_chars(&conn, &AnkiNotesConfig::default()).unwrap();
+        let cs = r.len();
+        assert!(cs > 3000);
+    }
+}
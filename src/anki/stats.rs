@@ -0,0 +1,184 @@
+use std::collections::{BTreeMap, HashSet};
+
+use chrono::{Datelike, Days, NaiveDate};
+
+use super::AnkiEntry;
+
+/// Number of HSK levels, the last one is 7-9
+pub const HSK_LEVELS: usize = 7;
+
+/// Progress at the end of a day
+#[derive(Debug, Clone, PartialEq)]
+pub struct DayStat {
+    pub day: NaiveDate,
+    /// Words added that day
+    pub words: usize,
+    pub total_words: usize,
+    pub total_chars: usize,
+    /// Known words of each HSK level
+    pub hsk: [usize; HSK_LEVELS],
+}
+
+/// Expected time to know every word of an HSK level at the current pace
+#[derive(Debug, Clone, PartialEq)]
+pub struct Projection {
+    pub level: u32,
+    pub known: usize,
+    pub total: usize,
+    /// Words of the level added per day
+    pub per_day: f64,
+    pub days_left: Option<f64>,
+}
+
+impl Projection {
+    pub fn finish(&self, today: NaiveDate) -> Option<NaiveDate> {
+        self.days_left.and_then(|d| today.checked_add_days(Days::new(d.ceil() as u64)))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    pub days: Vec<DayStat>,
+    /// Words added in the weeks starting on the given Monday
+    pub weeks: Vec<(NaiveDate, usize)>,
+    pub projections: Vec<Projection>,
+}
+
+#[derive(Default)]
+struct Known {
+    words: HashSet<String>,
+    chars: HashSet<char>,
+    hsk: [usize; HSK_LEVELS],
+}
+
+impl Known {
+    fn learn(&mut self, word: &str, level: Option<u32>) -> bool {
+        if !self.words.insert(word.to_string()) {
+            return false;
+        }
+        self.chars.extend(word.chars());
+        if let Some(level) = level.filter(|l| (1..=HSK_LEVELS as u32).contains(l)) {
+            self.hsk[level as usize - 1] += 1;
+        }
+        true
+    }
+}
+
+impl Stats {
+    /// Progress from `from` to `to`: `entries` are the words added in that time, `before`
+    /// the words known earlier
+    pub fn new<'a>(entries: &[AnkiEntry], before: impl Iterator<Item = &'a str>, hsk_of: impl Fn(&str) -> Option<u32>,
+        hsk_total: &[f32], from: NaiveDate, to: NaiveDate) -> Self {
+        let mut known = Known::default();
+        for word in before {
+            known.learn(word, hsk_of(word));
+        }
+        let hsk_start = known.hsk;
+
+        let mut added = BTreeMap::<NaiveDate, Vec<&str>>::new();
+        for e in entries {
+            added.entry(e.added().date_naive()).or_default().push(e.word.as_str());
+        }
+
+        let mut days = vec![];
+        let mut weeks = BTreeMap::<NaiveDate, usize>::new();
+        for day in from.iter_days().take_while(|d| *d <= to) {
+            let words = added.get(&day).into_iter().flatten()
+                .filter(|w| known.learn(w, hsk_of(w)))
+                .count();
+            let monday = day - Days::new(day.weekday().num_days_from_monday() as u64);
+            *weeks.entry(monday).or_default() += words;
+            days.push(DayStat {
+                day,
+                words,
+                total_words: known.words.len(),
+                total_chars: known.chars.len(),
+                hsk: known.hsk,
+            });
+        }
+
+        let span = std::cmp::max((to - from).num_days() + 1, 1) as f64;
+        let projections = (0..HSK_LEVELS)
+            .map(|ix| {
+                let total = hsk_total.get(ix).copied().unwrap_or(0.0) as usize;
+                let per_day = (known.hsk[ix] - hsk_start[ix]) as f64 / span;
+                let left = total.saturating_sub(known.hsk[ix]) as f64;
+                let days_left = if left == 0.0 {
+                    Some(0.0)
+                } else if per_day > 0.0 {
+                    Some(left / per_day)
+                } else {
+                    None
+                };
+                Projection { level: ix as u32 + 1, known: known.hsk[ix], total, per_day, days_left }
+            })
+            .collect();
+
+        Self { days, weeks: weeks.into_iter().collect(), projections }
+    }
+
+    /// Daily progress as CSV
+    pub fn to_csv(&self) -> String {
+        let mut res = String::from("date,words_added,words_total,chars_total");
+        for level in 1..=HSK_LEVELS {
+            res.push_str(format!(",hsk{}", level).as_str());
+        }
+        res.push('\n');
+        for d in &self.days {
+            res.push_str(format!("{},{},{},{}", d.day, d.words, d.total_words, d.total_chars).as_str());
+            for n in d.hsk {
+                res.push_str(format!(",{}", n).as_str());
+            }
+            res.push('\n');
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn entry(word: &str, y: i32, m: u32, d: u32) -> AnkiEntry {
+        let id = Utc.with_ymd_and_hms(y, m, d, 12, 0, 0).unwrap().timestamp_millis();
+        AnkiEntry::new(word, 1, id)
+    }
+
+    #[test]
+    fn test_stats() {
+        let hsk_of = |w: &str| match w {
+            "你" | "好" | "你好" => Some(1),
+            "喜欢" => Some(2),
+            _ => None,
+        };
+        let entries = vec![
+            entry("你好", 2024, 1, 1), entry("好", 2024, 1, 1), entry("你好", 2024, 1, 2),
+            entry("喜欢", 2024, 1, 8), entry("欢迎", 2024, 1, 8),
+        ];
+        let from = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let to = NaiveDate::from_ymd_opt(2024, 1, 10).unwrap();
+        let stats = Stats::new(&entries, ["你"].into_iter(), hsk_of, &[4.0, 10.0, 5.0], from, to);
+
+        assert_eq!(stats.days.len(), 10);
+        assert_eq!((stats.days[0].words, stats.days[0].total_words, stats.days[0].total_chars), (2, 3, 2));
+        // Already known words are not counted again
+        assert_eq!(stats.days[1].words, 0);
+        assert_eq!(stats.days[9].total_words, 5);
+        assert_eq!(stats.days[9].total_chars, 5);
+        assert_eq!(stats.days[9].hsk[..2], [3, 1]);
+        // 2024-01-01 is a Monday
+        assert_eq!(stats.weeks, vec![(from, 2), (NaiveDate::from_ymd_opt(2024, 1, 8).unwrap(), 2)]);
+
+        let p = &stats.projections;
+        assert_eq!((p[0].known, p[0].total), (3, 4));
+        assert_eq!(p[0].days_left, Some(5.0));
+        assert_eq!(p[1].finish(to), NaiveDate::from_ymd_opt(2024, 4, 9));
+        assert_eq!(p[2].days_left, None);
+
+        let csv = stats.to_csv();
+        assert_eq!(csv.lines().count(), 11);
+        assert!(csv.starts_with("date,words_added,words_total,chars_total,hsk1,"));
+        assert_eq!(csv.lines().nth(1).unwrap(), "2024-01-01,2,3,2,3,0,0,0,0,0,0");
+    }
+}
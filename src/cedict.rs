@@ -1,9 +1,10 @@
 use rusqlite::{Connection, Row};
-use std::{collections::{HashMap, HashSet}, fmt, hash::Hash, time::Instant};
+use std::{collections::{HashMap, HashSet}, fmt, hash::Hash, path::PathBuf, sync::Arc, time::Instant};
 use rayon::prelude::*;
 use tracing::{debug, warn};
 use std::collections::BTreeMap;
-use crate::{anki::AnkiEntry, utils::is_chinese_char};
+use crate::{anki::{AnkiEntry, Knowledge, chars::CharStats}, utils::is_chinese_char};
+use crate::config::AnkiNotesConfig;
 use crate::error::ReaderResult;
 
 pub const HSK_TOTAL: [f32; 7] = [477.0, 736.0, 940.0, 971.0, 1056.0, 1076.0, 5301.0];
@@ -18,7 +19,6 @@ pub struct Entry {
     chr: bool,
     idx: char,
     link: Option<String>,
-    anki: Option<crate::anki::AnkiEntry>,
 }
 
 impl PartialEq for Entry {
@@ -49,23 +49,15 @@ impl Entry {
             chr,
             idx,
             link: None,
-            anki: None,
         }
     }
 
-    pub fn is_anki(&self) -> bool {
-        self.anki.is_some()
-    }
-
     pub fn index(&self) -> char {
         self.idx
     }
 
-    pub fn deck(&self) -> Option<&String> {
-        self.anki.as_ref().map(|a| &a.deck_name)
-    }
-
-    pub fn to_md(&self) -> String {
+    /// `anki` is the Anki card of the word, if there is one
+    pub fn to_md(&self, anki: Option<&AnkiEntry>) -> String {
         let meanings = self.mea.split("/")
             .map(|s| {
                 if s.starts_with("variant of") {
@@ -91,10 +83,11 @@ impl Entry {
             .reduce(|acc,a| format!("{}\n- {}",acc,a));
 
         let hsk = if self.hsk.is_some() { format!("HSK{}", self.hsk.unwrap()) } else { String::new() };
-        let anki = if let Some(anki) = &self.anki { &format!(" (anki:{}) ", anki.deck_name) } else { "" };
+        let anki = if let Some(anki) = anki { &format!(" (anki:{}, {}) ", anki.deck_name, anki.knowledge) } else { "" };
+        let texts = format!("[{}](s:{})", t!("show_in_texts"), self.sim);
         match meanings {
             None => format!("Error formatting meanings!"),
-            Some(meanings) => format!("\n## {} | {}\n *{}* {}\n {}\n- {}", self.sim, self.tra, self.pin, hsk, anki, meanings )
+            Some(meanings) => format!("\n## {} | {}\n *{}* {}\n {}\n- {}\n\n{}\n", self.sim, self.tra, self.pin, hsk, anki, meanings, texts )
         }
     }
 
@@ -113,6 +106,26 @@ impl Entry {
     pub fn meanings(&self) -> &str {
         self.mea.as_str()
     }
+
+    pub fn simplified(&self) -> &str {
+        self.sim.as_str()
+    }
+
+    pub fn traditional(&self) -> &str {
+        self.tra.as_str()
+    }
+
+    pub fn pinyin(&self) -> &str {
+        self.pin.as_str()
+    }
+
+    /// Meanings as a single line
+    pub fn gloss(&self) -> String {
+        self.mea.split("/")
+            .filter(|s| !s.trim().is_empty())
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
 }
 
 impl fmt::Display for Entry {
@@ -122,14 +135,54 @@ impl fmt::Display for Entry {
     }
 }
 
-pub struct Cedict {
+/// Entries of the dictionary, they do not change when the Anki collection is read again
+struct Dictionary {
     data_t: BTreeMap<char, Vec<Entry>>,
     data_hsk: HashMap<u32,Vec<Entry>>,
+}
+
+#[derive(Clone)]
+pub struct Cedict {
+    dict: Arc<Dictionary>,
     anki: HashMap<String, AnkiEntry>,
+    /// Distinct characters of the Anki words
+    anki_chars: HashSet<char>,
+    anki_path: Option<PathBuf>,
+    anki_error: Option<String>,
+}
+
+impl fmt::Debug for Cedict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cedict({} entries, {} Anki words from {:?})", self.dict.data_t.len(), self.anki.len(), self.anki_path)
+    }
+}
+
+/// Anki words and characters of the collection, or the reason they could not be read.
+/// The collection is read from a snapshot so Anki can stay open
+fn load_anki(path: Option<&PathBuf>, anki_conf: &AnkiNotesConfig) -> (HashMap<String, AnkiEntry>, HashSet<char>, Option<String>) {
+    debug!("Querying Anki");
+    let Some(path) = path else {
+        debug!("No Anki collection");
+        return (HashMap::new(), HashSet::new(), None);
+    };
+    let loaded = crate::anki::collection::snapshot(path).and_then(|snapshot| Ok((
+        crate::anki::anki_words_entry(snapshot.conn(), anki_conf)?,
+        crate::anki::anki_chars(snapshot.conn(), anki_conf)?,
+    )));
+    match loaded {
+        Ok((anki, chars)) => {
+            debug!("Anki base loaded: {}", anki.len());
+            (anki, chars, None)
+        }
+        Err(e) => {
+            warn!("Error loading Anki collection {:?}: {}", path, e);
+            (HashMap::new(), HashSet::new(), Some(e.to_string()))
+        }
+    }
 }
 
 impl Cedict {
-    pub fn new(fname: &str, anki_fname: &Option<String>) -> ReaderResult<Self> {
+    pub fn new(fname: &str, anki_fname: &Option<String>, anki_conf: &AnkiNotesConfig) -> ReaderResult<Self> {
         let path = format!("/usr/share/cnreader/{}",fname);
         let conn = match std::fs::exists(&path) {
             Ok(true) => {
@@ -147,30 +200,13 @@ impl Cedict {
         let mut data_hsk: HashMap<u32, Vec<Entry>> = HashMap::new();
         let mut data_tr = st.query([])?;
 
-        debug!("Querying Anki");
-
-        let anki = if let Some(anki_fname) = anki_fname
-            && let Ok(true) = std::fs::exists(anki_fname) {
-            let anki_conn = Connection::open_with_flags(anki_fname, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY);
-            match anki_conn {
-                Ok(aconn) => crate::anki::anki_words_entry(&aconn)?,
-                Err(e) => {
-                    warn!("Error connecting Anki: {}", e);
-                    HashMap::new()
-                }
-            }
-        } else {
-            warn!("Empty Anki!");
-            HashMap::new()
-        };
-
-        debug!("Anki base loaded: {}", anki.len());
+        let anki_path = crate::anki::collection::resolve(anki_fname);
+        let (anki, anki_chars, anki_error) = load_anki(anki_path.as_ref(), anki_conf);
         let start = Instant::now();
         
         while let Ok(next) = data_tr.next() {
             if let Some(row) = next {
-                let mut e = Entry::from_row(row);
-                e.anki = anki.get(e.sim.trim()).cloned();
+                let e = Entry::from_row(row);
                 let k = e.index();
                 data_t.entry(k).or_default().push(e.clone());
                 if let Some(hsk) = e.hsk {
@@ -185,20 +221,53 @@ impl Cedict {
 
 
         Ok(Self { 
-            data_t,
-            data_hsk,
+            dict: Arc::new(Dictionary { data_t, data_hsk }),
             anki,
+            anki_chars,
+            anki_path,
+            anki_error,
         })
     }
 
+    /// The dictionary with the Anki collection read again, sharing its entries
+    pub fn reload_anki(&self, anki_fname: &Option<String>, anki_conf: &AnkiNotesConfig) -> Self {
+        let anki_path = crate::anki::collection::resolve(anki_fname);
+        let (anki, anki_chars, anki_error) = load_anki(anki_path.as_ref(), anki_conf);
+        Self {
+            dict: self.dict.clone(),
+            anki,
+            anki_chars,
+            anki_path,
+            anki_error,
+        }
+    }
+
+    /// Anki card of the word of an entry
+    pub fn anki_entry(&self, entry: &Entry) -> Option<&AnkiEntry> {
+        self.anki.get(entry.sim.trim())
+    }
+
+    pub fn knowledge(&self, entry: &Entry) -> Option<Knowledge> {
+        self.anki_entry(entry).map(|a| a.knowledge)
+    }
+
+    /// Collection the Anki words come from
+    pub fn anki_path(&self) -> Option<&PathBuf> {
+        self.anki_path.as_ref()
+    }
+
+    pub fn anki_error(&self) -> Option<&str> {
+        self.anki_error.as_deref()
+    }
+
     pub fn characters(&self) -> Vec<&Entry> {
-        self.data_t.par_iter()
+        self.dict.data_t.par_iter()
             .map(|(_,v)| v.iter().filter(|&e| e.chr).collect())
             .reduce(|| vec![], |a,b| ([a,b]).concat() )
     }
 
     fn characters_filtered(&self, s: &str) -> Vec<&Entry> {
-        self.data_t.par_iter()
+        self.dict.data_t.par_iter()
             .map(|(_,v)| v.iter().filter(|&e| e.chr && (s.contains(e.sim.as_str()) || s.contains(e.tra.as_str())) ).collect())
             .reduce(|| vec![], |a,b| ([a,b]).concat() )
 
@@ -220,12 +289,12 @@ impl Cedict {
     }
 
     pub fn len(&self) -> usize {
-        self.data_t.len()
+        self.dict.data_t.len()
     }
 
     /// Search all containing
     pub fn search(&self, s: &str) -> Vec<&Entry> {
-        self.data_t.par_iter()
+        self.dict.data_t.par_iter()
             .map(|(_,v)| v.iter().filter(|&e| e.sim.contains(s)).collect() )
             .reduce(|| vec![], |a,b| ([a,b]).concat() )
     }
@@ -237,7 +306,7 @@ impl Cedict {
         }
         debug!("find: {}", s);
         let c = s.chars().nth(0).unwrap();
-        if let Some(r) = self.data_t.get(&c) {
+        if let Some(r) = self.dict.data_t.get(&c) {
             return r.iter()
                 .filter(|e| e.sim.as_str() == s)
                 .collect::<Vec<&Entry>>();
@@ -245,16 +314,56 @@ impl Cedict {
         vec![]
     }
 
+    /// Split text into words, longest dictionary match first
+    pub fn segment<'a>(&self, text: &'a str) -> Vec<(&'a str, Option<&Entry>)> {
+        let mut res = vec![];
+        let mut pos = 0;
+        while pos < text.len() {
+            let rest = &text[pos..];
+            let c = rest.chars().next().unwrap();
+            let entry = self.dict.data_t.get(&c)
+                .and_then(|v| v.iter()
+                    .filter(|e| rest.starts_with(e.sim.as_str()))
+                    .max_by_key(|e| e.sim.len()));
+            let len = match entry {
+                Some(e) => e.sim.len(),
+                None => c.len_utf8(),
+            };
+            res.push((&rest[..len], entry));
+            pos += len;
+        }
+        res
+    }
+
+    /// Distinct dictionary words of the text that are not in Anki, in order of appearance
+    pub fn unknown_words(&self, text: &str) -> Vec<&Entry> {
+        let mut seen = HashSet::new();
+        self.segment(text).into_iter()
+            .filter_map(|(_, e)| e)
+            .filter(|e| self.anki_entry(e).is_none() && e.sim.chars().any(|c| is_chinese_char(&c)))
+            .filter(|e| seen.insert(e.sim.as_str()))
+            .collect()
+    }
+
     pub fn data_hsk_len(&self) -> usize {
-        self.data_hsk.len()
+        self.dict.data_hsk.len()
     }
 
     /// How many entries that are in Anki for each HSK level there are?
     pub fn count_hsk_anki(&self) -> HashMap<u32, usize> {
+        self.count_hsk_filtered(|x| self.anki_entry(x).is_some())
+    }
+
+    /// How many entries with mature Anki cards for each HSK level there are?
+    pub fn count_hsk_mature(&self) -> HashMap<u32, usize> {
+        self.count_hsk_filtered(|x| self.knowledge(x) == Some(Knowledge::Mature))
+    }
+
+    fn count_hsk_filtered(&self, f: impl Fn(&Entry) -> bool + Sync) -> HashMap<u32, usize> {
         debug!("Anki stats");
         let start = Instant::now();
-        let res = self.data_hsk.par_iter()
-            .map(|(hsk, e)| (*hsk, e.iter().filter(|x| x.anki.is_some()).collect::<HashSet<_>>().len() ) )
+        let res = self.dict.data_hsk.par_iter()
+            .map(|(hsk, e)| (*hsk, e.iter().filter(|x| f(x)).collect::<HashSet<_>>().len() ) )
             .collect();
         let elapsed = start.elapsed();
         debug!("Anki starts exec time: {}ms", elapsed.as_millis());
@@ -263,7 +372,7 @@ impl Cedict {
 
     /// How many entries that are for each HSK level there are?
     pub fn count_hsk(&self) -> HashMap<u32, usize> {
-        self.data_hsk.par_iter()
+        self.dict.data_hsk.par_iter()
             .map(|(hsk, e)| (*hsk, e.len()) )
             .collect()
     }
@@ -272,6 +381,55 @@ impl Cedict {
         self.anki.len()
     }
 
+    pub fn anki_words(&self) -> impl Iterator<Item = &str> {
+        self.anki.keys().map(|w| w.as_str())
+    }
+
+    /// Character knowledge, with the characters `text` introduces
+    pub fn char_stats(&self, text: &str) -> CharStats {
+        let hsk_words: Vec<&str> = self.dict.data_hsk.values()
+            .flat_map(|e| e.iter().map(|e| e.sim.trim()))
+            .collect();
+        CharStats::new(&hsk_words, |w| self.anki.contains_key(w), &self.anki_chars, text)
+    }
+
+    /// HSK level a reader needs for most of the words of a text
+    pub fn difficulty(&self, text: &str) -> Option<u32> {
+        let levels = self.segment(text).into_iter()
+            .filter(|(w, _)| w.chars().any(|c| is_chinese_char(&c)))
+            .map(|(w, e)| e.and_then(|e| e.hsk).or_else(|| self.hsk(w)));
+        crate::library::difficulty(levels)
+    }
+
+    /// Lowest HSK level of a word
+    pub fn hsk(&self, word: &str) -> Option<u32> {
+        self.find(word).iter()
+            .filter_map(|e| e.hsk)
+            .min()
+    }
+
+    /// Number of Anki words in each knowledge class
+    pub fn count_knowledge(&self) -> HashMap<Knowledge, usize> {
+        let mut res = HashMap::new();
+        for a in self.anki.values() {
+            *res.entry(a.knowledge).or_default() += 1;
+        }
+        res
+    }
+
+    /// Knowledge class of the Anki words in a line, as byte ranges
+    pub fn knowledge_line(&self, line: &str) -> Vec<(std::ops::Range<usize>, Knowledge)> {
+        let mut pos = 0;
+        let mut res = vec![];
+        for (word, entry) in self.segment(line) {
+            if let Some(k) = entry.and_then(|e| self.knowledge(e)) {
+                res.push((pos..pos + word.len(), k));
+            }
+            pos += word.len();
+        }
+        res
+    }
+
 }
 
 
@@ -0,0 +1,133 @@
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, params};
+use tracing::debug;
+
+use crate::anki::connect::NoteData;
+use crate::error::ReaderResult;
+
+/// Columns of the TSV export, in the order of the `#columns` header
+const TSV_COLUMNS: [&str; 7] = ["Word", "Cloze", "Sentence", "Pinyin", "Meaning", "Translation", "Source"];
+
+/// A sentence card waiting to be exported
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueuedCard {
+    pub id: i64,
+    pub note: NoteData,
+    pub created: DateTime<Utc>,
+}
+
+pub fn init_cloze(conn: &Connection) -> ReaderResult<()> {
+    conn.execute_batch("
+        CREATE TABLE IF NOT EXISTS ClozeQueue (
+            Id INTEGER,
+            Word TEXT NOT NULL,
+            Sentence TEXT NOT NULL,
+            Pinyin TEXT NOT NULL DEFAULT '',
+            Meaning TEXT NOT NULL DEFAULT '',
+            Translation TEXT NOT NULL DEFAULT '',
+            Source TEXT NOT NULL DEFAULT '',
+            Created INTEGER NOT NULL,
+            PRIMARY KEY(Id AUTOINCREMENT) );
+    ")?;
+    Ok(())
+}
+
+/// Queue a card, returns its id
+pub fn queue(conn: &Connection, note: &NoteData, now: DateTime<Utc>) -> ReaderResult<i64> {
+    conn.execute("INSERT INTO ClozeQueue (Word, Sentence, Pinyin, Meaning, Translation, Source, Created) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![note.hanzi, note.sentence, note.pinyin, note.meaning, note.translation, note.source, now.timestamp()])?;
+    let id = conn.last_insert_rowid();
+    debug!("Queued sentence card {} for {}", id, note.hanzi);
+    Ok(id)
+}
+
+pub fn set_translation(conn: &Connection, id: i64, translation: &str) -> ReaderResult<()> {
+    conn.execute("UPDATE ClozeQueue SET Translation = ?2 WHERE Id = ?1", params![id, translation.trim()])?;
+    Ok(())
+}
+
+pub fn queued(conn: &Connection) -> ReaderResult<Vec<QueuedCard>> {
+    let mut st = conn.prepare("SELECT Id, Word, Sentence, Pinyin, Meaning, Translation, Source, Created FROM ClozeQueue ORDER BY Id")?;
+    let res = st.query_map([], |row| {
+        Ok(QueuedCard {
+            id: row.get(0)?,
+            note: NoteData {
+                hanzi: row.get(1)?,
+                sentence: row.get(2)?,
+                pinyin: row.get(3)?,
+                meaning: row.get(4)?,
+                translation: row.get(5)?,
+                source: row.get(6)?,
+            },
+            created: DateTime::from_timestamp(row.get(7)?, 0).unwrap_or_default(),
+        })
+    })?.collect::<Result<Vec<_>, _>>()?;
+    Ok(res)
+}
+
+pub fn remove(conn: &mut Connection, ids: &[i64]) -> ReaderResult<()> {
+    let tx = conn.transaction()?;
+    for id in ids {
+        tx.execute("DELETE FROM ClozeQueue WHERE Id = ?1", [id])?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\t', " ")
+        .replace("\r\n", "<br>")
+        .replace('\n', "<br>")
+}
+
+/// Cards as a tab separated file with the headers of Anki's text import; the cloze column
+/// uses Anki's cloze syntax, so it fits the built-in Cloze note type
+pub fn to_tsv(cards: &[QueuedCard]) -> String {
+    let mut res = format!("#separator:tab\n#html:true\n#columns:{}\n", TSV_COLUMNS.join("\t"));
+    for card in cards {
+        let n = &card.note;
+        let cloze = n.cloze();
+        let fields = [&n.hanzi, &cloze, &n.sentence, &n.pinyin, &n.meaning, &n.translation, &n.source]
+            .map(|f| html(f));
+        res.push_str(fields.join("\t").as_str());
+        res.push('\n');
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        init_cloze(&conn).unwrap();
+        let note = NoteData {
+            hanzi: String::from("喜欢"),
+            pinyin: String::from("xi3 huan5"),
+            meaning: String::from("to like"),
+            sentence: String::from("我喜欢<看>书。"),
+            translation: String::new(),
+            source: String::from("课文"),
+        };
+        let id = queue(&conn, &note, Utc::now()).unwrap();
+        queue(&conn, &NoteData { hanzi: String::from("书"), ..note.clone() }, Utc::now()).unwrap();
+        set_translation(&conn, id, " I like reading.\n").unwrap();
+
+        let cards = queued(&conn).unwrap();
+        assert_eq!(cards.len(), 2);
+        assert_eq!(cards[0].note.translation, "I like reading.");
+
+        let tsv = to_tsv(&cards[..1]);
+        let lines: Vec<&str> = tsv.lines().collect();
+        assert_eq!(lines[2], "#columns:Word\tCloze\tSentence\tPinyin\tMeaning\tTranslation\tSource");
+        assert_eq!(lines[3], "喜欢\t我{{c1::喜欢::xi3 huan5}}&lt;看&gt;书。\t我喜欢&lt;看&gt;书。\txi3 huan5\tto like\tI like reading.\t课文");
+
+        remove(&mut conn, &[id]).unwrap();
+        assert_eq!(queued(&conn).unwrap().len(), 1);
+    }
+}
@@ -85,6 +85,179 @@ impl fmt::Display for AiChatConfig {
     }
 }
 
+/// Names of the Anki note fields, empty ones are not filled
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct AnkiFields {
+    pub hanzi: String,
+    pub pinyin: String,
+    pub meaning: String,
+    pub sentence: String,
+    pub source: String,
+    /// Sentence with the word as a cloze deletion
+    #[serde(default)]
+    pub cloze: String,
+    #[serde(default)]
+    pub translation: String,
+}
+
+impl Default for AnkiFields {
+    fn default() -> Self {
+        Self {
+            hanzi: String::from("Hanzi"),
+            pinyin: String::from("Pinyin"),
+            meaning: String::from("Meaning"),
+            sentence: String::from("Sentence"),
+            source: String::from("Source"),
+            cloze: String::new(),
+            translation: String::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct AnkiConnectConfig {
+    pub url: String,
+    pub key: Option<String>,
+    pub deck: String,
+    pub note_type: String,
+    pub fields: AnkiFields,
+    pub tags: Vec<String>,
+}
+
+impl Default for AnkiConnectConfig {
+    fn default() -> Self {
+        Self {
+            url: String::from("http://127.0.0.1:8765"),
+            key: None,
+            deck: String::from("Default"),
+            note_type: String::from("Chinese"),
+            fields: AnkiFields::default(),
+            tags: vec![String::from("cnreader")],
+        }
+    }
+}
+
+crate::make_enum!(AnkiConnectField, [Url, Key, Deck, NoteType, Hanzi, Pinyin, Meaning, Sentence, Source, Cloze, Translation]);
+
+impl AnkiConnectConfig {
+    pub fn get(&self, field: AnkiConnectField) -> String {
+        match field {
+            AnkiConnectField::Url => self.url.clone(),
+            AnkiConnectField::Key => self.key.clone().unwrap_or_default(),
+            AnkiConnectField::Deck => self.deck.clone(),
+            AnkiConnectField::NoteType => self.note_type.clone(),
+            AnkiConnectField::Hanzi => self.fields.hanzi.clone(),
+            AnkiConnectField::Pinyin => self.fields.pinyin.clone(),
+            AnkiConnectField::Meaning => self.fields.meaning.clone(),
+            AnkiConnectField::Sentence => self.fields.sentence.clone(),
+            AnkiConnectField::Source => self.fields.source.clone(),
+            AnkiConnectField::Cloze => self.fields.cloze.clone(),
+            AnkiConnectField::Translation => self.fields.translation.clone(),
+        }
+    }
+
+    pub fn set(&mut self, field: AnkiConnectField, value: String) {
+        match field {
+            AnkiConnectField::Url => self.url = value,
+            AnkiConnectField::Key => self.key = crate::utils::str_to_op(value),
+            AnkiConnectField::Deck => self.deck = value,
+            AnkiConnectField::NoteType => self.note_type = value,
+            AnkiConnectField::Hanzi => self.fields.hanzi = value,
+            AnkiConnectField::Pinyin => self.fields.pinyin = value,
+            AnkiConnectField::Meaning => self.fields.meaning = value,
+            AnkiConnectField::Sentence => self.fields.sentence = value,
+            AnkiConnectField::Source => self.fields.source = value,
+            AnkiConnectField::Cloze => self.fields.cloze = value,
+            AnkiConnectField::Translation => self.fields.translation = value,
+        }
+    }
+}
+
+impl AnkiConnectField {
+    pub fn label(&self) -> String {
+        match self {
+            Self::Url => t!("anki_connect_url").to_string(),
+            Self::Key => t!("anki_connect_key").to_string(),
+            Self::Deck => t!("anki_connect_deck").to_string(),
+            Self::NoteType => t!("anki_connect_note_type").to_string(),
+            Self::Hanzi => t!("anki_connect_hanzi").to_string(),
+            Self::Pinyin => t!("anki_connect_pinyin").to_string(),
+            Self::Meaning => t!("anki_connect_meaning").to_string(),
+            Self::Sentence => t!("anki_connect_sentence").to_string(),
+            Self::Source => t!("anki_connect_source").to_string(),
+            Self::Cloze => t!("anki_connect_cloze").to_string(),
+            Self::Translation => t!("anki_connect_translation").to_string(),
+        }
+    }
+}
+
+/// Field holding the Hanzi in notes of a note type
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct AnkiNoteType {
+    pub name: String,
+    pub field: String,
+}
+
+/// Which notes of the collection are read as known words
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
+pub struct AnkiNotesConfig {
+    /// When empty all note types are read, taking the Hanzi from the sort field
+    pub note_types: Vec<AnkiNoteType>,
+    /// When empty all decks are read, subdecks are included with their parent
+    pub include_decks: Vec<String>,
+    pub exclude_decks: Vec<String>,
+}
+
+crate::make_enum!(AnkiNotesField, [NoteTypes, IncludeDecks, ExcludeDecks]);
+
+impl AnkiNotesConfig {
+    /// Settings text, note types as `type:field` and decks separated with commas
+    pub fn get(&self, field: AnkiNotesField) -> String {
+        match field {
+            AnkiNotesField::NoteTypes => self.note_types.iter()
+                .map(|t| format!("{}:{}", t.name, t.field))
+                .collect::<Vec<_>>()
+                .join(", "),
+            AnkiNotesField::IncludeDecks => self.include_decks.join(", "),
+            AnkiNotesField::ExcludeDecks => self.exclude_decks.join(", "),
+        }
+    }
+
+    pub fn set(&mut self, field: AnkiNotesField, value: String) {
+        let list: Vec<String> = value.split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        match field {
+            AnkiNotesField::NoteTypes => {
+                self.note_types = list.iter()
+                    .filter_map(|s| s.rsplit_once(':'))
+                    .map(|(name, field)| AnkiNoteType { name: name.trim().to_string(), field: field.trim().to_string() })
+                    .collect();
+            }
+            AnkiNotesField::IncludeDecks => self.include_decks = list,
+            AnkiNotesField::ExcludeDecks => self.exclude_decks = list,
+        }
+    }
+
+    /// Is the deck, or one of its parents, selected
+    pub fn deck_included(&self, deck: &str) -> bool {
+        let matches = |d: &String| deck == d || deck.starts_with(format!("{}::", d).as_str());
+        (self.include_decks.is_empty() || self.include_decks.iter().any(matches))
+            && !self.exclude_decks.iter().any(matches)
+    }
+}
+
+impl AnkiNotesField {
+    pub fn label(&self) -> String {
+        match self {
+            Self::NoteTypes => t!("anki_note_types").to_string(),
+            Self::IncludeDecks => t!("anki_include_decks").to_string(),
+            Self::ExcludeDecks => t!("anki_exclude_decks").to_string(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct Prompts {
     pub meaning: String,
@@ -113,6 +286,13 @@ pub struct Config {
     pub ai_role: String,
     pub ai_preamble: String,
     pub deepl_lang: Option<deepl::Lang>,
+    pub grammar: Option<String>,
+    /// Font with Chinese characters embedded in PDF exports
+    pub pdf_font: Option<String>,
+    #[serde(default)]
+    pub anki_connect: AnkiConnectConfig,
+    #[serde(default)]
+    pub anki_notes: AnkiNotesConfig,
 
     pub new_ai: Option<AiChatConfig>,
 }
@@ -152,6 +332,10 @@ impl Default for Config {
             ai_preamble: String::new(), 
             new_ai: None,
             deepl_lang: None,
+            grammar: None,
+            pdf_font: None,
+            anki_connect: AnkiConnectConfig::default(),
+            anki_notes: AnkiNotesConfig::default(),
         }
     }
 }
@@ -0,0 +1,119 @@
+/// Above this many line pairs the changed part is shown as replaced at once
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// A line kept, removed from the old text or added in the new one
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change<L> {
+    Same(L),
+    Removed(L),
+    Added(L),
+}
+
+impl<L> Change<L> {
+    pub fn is_same(&self) -> bool {
+        matches!(self, Self::Same(_))
+    }
+
+    /// The same change of another form of the line
+    pub fn map<M>(&self, f: impl FnOnce(&L) -> M) -> Change<M> {
+        match self {
+            Self::Same(l) => Change::Same(f(l)),
+            Self::Removed(l) => Change::Removed(f(l)),
+            Self::Added(l) => Change::Added(f(l)),
+        }
+    }
+}
+
+/// Lines to remove from `old` and add to get `new`
+pub fn diff<'a>(old: &'a str, new: &'a str) -> Vec<Change<&'a str>> {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    let prefix = a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|(x, y)| x == y).count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut res: Vec<Change<&str>> = a[..prefix].iter().map(|l| Change::Same(*l)).collect();
+    let (n, m) = (a_mid.len(), b_mid.len());
+    if n * m > MAX_DIFF_CELLS {
+        res.extend(a_mid.iter().map(|l| Change::Removed(*l)));
+        res.extend(b_mid.iter().map(|l| Change::Added(*l)));
+    } else {
+        // Longest common subsequence of the lines after i and j
+        let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+        let at = |i: usize, j: usize| i * (m + 1) + j;
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[at(i, j)] = if a_mid[i] == b_mid[j] {
+                    lcs[at(i + 1, j + 1)] + 1
+                } else {
+                    lcs[at(i + 1, j)].max(lcs[at(i, j + 1)])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < n && j < m {
+            if a_mid[i] == b_mid[j] {
+                res.push(Change::Same(a_mid[i]));
+                i += 1;
+                j += 1;
+            } else if lcs[at(i + 1, j)] >= lcs[at(i, j + 1)] {
+                res.push(Change::Removed(a_mid[i]));
+                i += 1;
+            } else {
+                res.push(Change::Added(b_mid[j]));
+                j += 1;
+            }
+        }
+        res.extend(a_mid[i..].iter().map(|l| Change::Removed(*l)));
+        res.extend(b_mid[j..].iter().map(|l| Change::Added(*l)));
+    }
+    res.extend(a[a.len() - suffix..].iter().map(|l| Change::Same(*l)));
+    res
+}
+
+/// Where each line of `old` is in `new`, None for removed lines. A changed line keeps
+/// its place: removed lines are paired with the lines added in their place
+pub fn line_map(old: &str, new: &str) -> Vec<Option<usize>> {
+    let mut res = vec![];
+    let mut replaced = std::collections::VecDeque::new();
+    let mut line = 0;
+    for change in diff(old, new) {
+        match change {
+            Change::Same(_) => {
+                replaced.clear();
+                res.push(Some(line));
+                line += 1;
+            }
+            Change::Removed(_) => {
+                replaced.push_back(res.len());
+                res.push(None);
+            }
+            Change::Added(_) => {
+                if let Some(ix) = replaced.pop_front() {
+                    res[ix] = Some(line);
+                }
+                line += 1;
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let old = "一\n二\n三\n四\n五\n六";
+        let new = "一\n二\n3\n四\n五\n六\n七";
+        let changes = diff(old, new);
+        assert_eq!(changes[2..4], [Change::Removed("三"), Change::Added("3")]);
+        assert_eq!(changes.last(), Some(&Change::Added("七")));
+        assert_eq!(changes.iter().filter(|c| c.is_same()).count(), 5);
+        assert_eq!(changes[3].map(|l| l.to_string()), Change::Added(String::from("3")));
+
+        assert_eq!(line_map(old, new), vec![Some(0), Some(1), Some(2), Some(3), Some(4), Some(5)]);
+        assert_eq!(line_map(old, "零\n一\n四\n五\n六"), vec![Some(1), None, None, Some(2), Some(3), Some(4)]);
+    }
+}
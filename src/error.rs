@@ -15,6 +15,9 @@ pub enum ReaderError {
     Ocr(String),
     Other(String),
     Scraper(String),
+    Anki(String),
+    Srs(String),
+    Import(String),
 }
 
 impl ReaderError {
@@ -38,6 +41,9 @@ impl std::fmt::Display for ReaderError {
             Self::Image(s) => f.write_str(format!("Image Error: {}", s).as_str()),
             Self::Ocr(s) => f.write_str(format!("OCR Error: {}", s).as_str()),
             Self::Scraper(s) => f.write_str(format!("Scraper Error: {}", s).as_str()),
+            Self::Anki(s) => f.write_str(format!("Anki Error: {}", s).as_str()),
+            Self::Srs(s) => f.write_str(format!("Review Error: {}", s).as_str()),
+            Self::Import(s) => f.write_str(format!("Import Error: {}", s).as_str()),
             Self::Other(s) => f.write_str(format!("Error: {}", s).as_str()),
         }
     }
@@ -49,6 +55,12 @@ impl From<rusqlite::Error> for ReaderError {
     }
 }
 
+impl From<serde_json::Error> for ReaderError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Other(e.to_string())
+    }
+}
+
 impl From<JoinError> for ReaderError {
     fn from(e: JoinError) -> Self {
         Self::Other(e.to_string())
@@ -92,6 +104,18 @@ impl From<deepl::Error> for ReaderError {
     }
 }
 
+impl From<zip::result::ZipError> for ReaderError {
+    fn from(e: zip::result::ZipError) -> Self {
+        Self::Io(e.to_string())
+    }
+}
+
+impl From<lopdf::Error> for ReaderError {
+    fn from(e: lopdf::Error) -> Self {
+        Self::Import(e.to_string())
+    }
+}
+
 impl From<FromUtf8Error> for ReaderError {
     fn from(e: FromUtf8Error) -> Self {
         Self::Io(e.to_string())
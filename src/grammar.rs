@@ -0,0 +1,154 @@
+use regex::Regex;
+use serde::Deserialize;
+use tracing::{debug, warn};
+use std::ops::Range;
+
+use crate::error::{ReaderError, ReaderResult};
+
+/// Pattern library bundled with the application
+const DEFAULT_PATTERNS: &str = include_str!("../grammar.toml");
+
+#[derive(Deserialize, Debug, Clone)]
+struct PatternDef {
+    name: String,
+    description: String,
+    pattern: String,
+    link: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct PatternFile {
+    pattern: Vec<PatternDef>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GrammarPattern {
+    pub name: String,
+    pub description: String,
+    pub link: String,
+    regex: Regex,
+}
+
+/// Single occurrence of a pattern, `start` and `end` are byte offsets in the line
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrammarMatch {
+    pub pattern: usize,
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Grammar {
+    patterns: Vec<GrammarPattern>,
+}
+
+impl Grammar {
+    /// Load the patterns from `file_name`, or the bundled ones if it's not given
+    pub fn new(file_name: &Option<String>) -> ReaderResult<Self> {
+        match file_name {
+            Some(file_name) if !file_name.is_empty() => {
+                debug!("Loading grammar patterns from {}", file_name);
+                Self::parse(std::fs::read_to_string(file_name)?.as_str())
+            }
+            _ => Self::parse(DEFAULT_PATTERNS),
+        }
+    }
+
+    pub fn parse(s: &str) -> ReaderResult<Self> {
+        let file: PatternFile = toml::from_str(s)
+            .map_err(|e| ReaderError::Other(e.to_string()))?;
+        let mut patterns = Vec::with_capacity(file.pattern.len());
+        for def in file.pattern {
+            match Regex::new(def.pattern.as_str()) {
+                Ok(regex) => patterns.push(GrammarPattern {
+                    name: def.name,
+                    description: def.description,
+                    link: def.link,
+                    regex,
+                }),
+                Err(e) => warn!("Invalid grammar pattern {}: {}", def.name, e),
+            }
+        }
+        Ok(Self { patterns })
+    }
+
+    pub fn patterns(&self) -> &[GrammarPattern] {
+        self.patterns.as_slice()
+    }
+
+    pub fn get(&self, ix: usize) -> Option<&GrammarPattern> {
+        self.patterns.get(ix)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Matches in a single line as (pattern index, byte range)
+    pub fn find_line(&self, line: &str) -> Vec<(usize, Range<usize>)> {
+        let mut res: Vec<(usize, Range<usize>)> = self.patterns.iter()
+            .enumerate()
+            .flat_map(|(ix, p)| p.regex.find_iter(line).map(move |m| (ix, m.range())))
+            .collect();
+        res.sort_by_key(|a| a.1.start);
+        res
+    }
+
+    /// All matches in the text, line by line
+    pub fn find(&self, text: &str) -> Vec<GrammarMatch> {
+        text.lines()
+            .enumerate()
+            .flat_map(|(line_ix, line)| {
+                self.find_line(line).into_iter()
+                    .map(move |(pattern, r)| GrammarMatch {
+                        pattern,
+                        line: line_ix,
+                        start: r.start,
+                        end: r.end,
+                        text: line[r].to_string(),
+                    })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_patterns() {
+        let g = Grammar::new(&None).unwrap();
+        assert!(!g.is_empty());
+        let names: Vec<&str> = g.patterns().iter().map(|p| p.name.as_str()).collect();
+        assert!(names.contains(&"把"));
+        assert!(names.contains(&"越…越"));
+    }
+
+    #[test]
+    fn test_find() {
+        let g = Grammar::new(&None).unwrap();
+        let m = g.find("他把书放在桌子上。\n我越看越喜欢。");
+        let ba = m.iter().find(|m| g.get(m.pattern).unwrap().name == "把").unwrap();
+        assert_eq!(ba.line, 0);
+        assert_eq!(ba.text, "把书放在");
+        let yue = m.iter().find(|m| g.get(m.pattern).unwrap().name == "越…越").unwrap();
+        assert_eq!(yue.line, 1);
+        assert_eq!(yue.start, "我".len());
+        assert_eq!(yue.text, "越看越");
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let g = Grammar::parse(r#"
+            [[pattern]]
+            name = "broken"
+            description = ""
+            pattern = "(把"
+            link = ""
+        "#).unwrap();
+        assert!(g.is_empty());
+    }
+}
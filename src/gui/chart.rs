@@ -0,0 +1,107 @@
+use iced::Color;
+use iced::widget::svg;
+
+const WIDTH: f64 = 640.0;
+const HEIGHT: f64 = 240.0;
+const MARGIN: f64 = 40.0;
+const FONT_SIZE: f64 = 12.0;
+
+/// Colours of series that have no theme colour
+pub const COLORS: [Color; 7] = [
+    Color::from_rgb8(0x4e, 0x79, 0xa7),
+    Color::from_rgb8(0xf2, 0x8e, 0x2b),
+    Color::from_rgb8(0xe1, 0x57, 0x59),
+    Color::from_rgb8(0x76, 0xb7, 0xb2),
+    Color::from_rgb8(0x59, 0xa1, 0x4f),
+    Color::from_rgb8(0xed, 0xc9, 0x48),
+    Color::from_rgb8(0xb0, 0x7a, 0xa1),
+];
+
+/// One line of a line chart
+pub struct Series<'a> {
+    pub name: &'a str,
+    pub values: Vec<f64>,
+    pub color: Color,
+}
+
+fn hex(c: Color) -> String {
+    let [r, g, b, _] = c.into_rgba8();
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Top of the value axis, rounded up so the labels stay short
+fn nice_max(max: f64) -> f64 {
+    if max <= 0.0 {
+        return 1.0;
+    }
+    let magnitude = 10f64.powf(max.log10().floor());
+    [1.0, 2.0, 5.0, 10.0].iter()
+        .map(|m| m * magnitude)
+        .find(|m| *m >= max)
+        .unwrap_or(max)
+}
+
+fn x_of(ix: usize, len: usize) -> f64 {
+    let plot = WIDTH - 2.0 * MARGIN;
+    if len <= 1 { MARGIN + plot / 2.0 } else { MARGIN + plot * ix as f64 / (len - 1) as f64 }
+}
+
+fn y_of(value: f64, max: f64) -> f64 {
+    HEIGHT - MARGIN - (HEIGHT - 2.0 * MARGIN) * value / max
+}
+
+/// Axes, title and labels, the plot goes between them
+fn frame(title: &str, labels: &[String], max: f64, text: Color, body: &str) -> svg::Handle {
+    let text = hex(text);
+    let bottom = HEIGHT - MARGIN;
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{HEIGHT}" viewBox="0 0 {WIDTH} {HEIGHT}" font-family="sans-serif" font-size="{FONT_SIZE}">"#
+    );
+    svg.push_str(&format!(r#"<text x="{MARGIN}" y="{}" fill="{text}" font-weight="bold">{}</text>"#, MARGIN / 2.0, escape(title)));
+    svg.push_str(&format!(r#"<line x1="{MARGIN}" y1="{bottom}" x2="{}" y2="{bottom}" stroke="{text}"/>"#, WIDTH - MARGIN));
+    svg.push_str(&format!(r#"<line x1="{MARGIN}" y1="{MARGIN}" x2="{MARGIN}" y2="{bottom}" stroke="{text}"/>"#));
+    svg.push_str(&format!(r#"<text x="{}" y="{}" fill="{text}" text-anchor="end">{}</text>"#, MARGIN - 4.0, MARGIN + 4.0, max));
+    svg.push_str(&format!(r#"<text x="{}" y="{}" fill="{text}" text-anchor="end">0</text>"#, MARGIN - 4.0, bottom));
+    if let (Some(first), Some(last)) = (labels.first(), labels.last()) {
+        let y = bottom + FONT_SIZE + 4.0;
+        svg.push_str(&format!(r#"<text x="{MARGIN}" y="{y}" fill="{text}">{}</text>"#, escape(first)));
+        svg.push_str(&format!(r#"<text x="{}" y="{y}" fill="{text}" text-anchor="end">{}</text>"#, WIDTH - MARGIN, escape(last)));
+    }
+    svg.push_str(body);
+    svg.push_str("</svg>");
+    svg::Handle::from_memory(svg.into_bytes())
+}
+
+pub fn bars(title: &str, labels: &[String], values: &[f64], color: Color, text: Color) -> svg::Handle {
+    let max = nice_max(values.iter().copied().fold(0.0, f64::max));
+    let slot = (WIDTH - 2.0 * MARGIN) / values.len().max(1) as f64;
+    let color = hex(color);
+    let body: String = values.iter().enumerate()
+        .map(|(ix, v)| {
+            let y = y_of(*v, max);
+            format!(r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{color}"/>"#,
+                MARGIN + slot * ix as f64 + slot * 0.1, y, slot * 0.8, HEIGHT - MARGIN - y)
+        })
+        .collect();
+    frame(title, labels, max, text, body.as_str())
+}
+
+pub fn lines(title: &str, labels: &[String], series: &[Series], text: Color) -> svg::Handle {
+    let max = nice_max(series.iter().flat_map(|s| s.values.iter().copied()).fold(0.0, f64::max));
+    let mut body = String::new();
+    for (ix, s) in series.iter().enumerate() {
+        let color = hex(s.color);
+        let points: Vec<String> = s.values.iter().enumerate()
+            .map(|(i, v)| format!("{:.1},{:.1}", x_of(i, s.values.len()), y_of(*v, max)))
+            .collect();
+        body.push_str(&format!(r#"<polyline points="{}" fill="none" stroke="{color}" stroke-width="2"/>"#, points.join(" ")));
+        // Legend in the top right corner
+        let y = MARGIN / 2.0 + ix as f64 * (FONT_SIZE + 2.0);
+        body.push_str(&format!(r#"<text x="{}" y="{y}" fill="{color}" text-anchor="end">{}</text>"#, WIDTH - MARGIN, escape(s.name)));
+    }
+    frame(title, labels, max, text, body.as_str())
+}
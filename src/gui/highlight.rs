@@ -0,0 +1,93 @@
+use iced::advanced::text::highlighter::{Format, Highlighter};
+use iced::{Color, Font, Theme};
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::anki::Knowledge;
+use crate::cedict::Cedict;
+use crate::grammar::Grammar;
+
+/// What the reader marks in the text editor
+#[derive(Clone, Default)]
+pub struct Settings {
+    pub grammar: Option<Arc<Grammar>>,
+    pub knowledge: Option<Arc<Cedict>>,
+}
+
+fn same<T>(a: &Option<Arc<T>>, b: &Option<Arc<T>>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+impl PartialEq for Settings {
+    fn eq(&self, other: &Self) -> bool {
+        same(&self.grammar, &other.grammar) && same(&self.knowledge, &other.knowledge)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Highlight {
+    Grammar(usize),
+    Knowledge(Knowledge),
+}
+
+impl Highlight {
+    pub fn to_format(&self, theme: &Theme) -> Format<Font> {
+        let palette = theme.palette();
+        let color: Option<Color> = match self {
+            Highlight::Grammar(_) => Some(palette.primary),
+            Highlight::Knowledge(Knowledge::New) => Some(palette.danger),
+            Highlight::Knowledge(Knowledge::Learning) => Some(palette.warning),
+            Highlight::Knowledge(Knowledge::Young) => Some(palette.success),
+            // Well known words are left as they are
+            Highlight::Knowledge(Knowledge::Mature) => None,
+            Highlight::Knowledge(Knowledge::Suspended) => Some(palette.text.scale_alpha(0.5)),
+        };
+        Format { color, font: None }
+    }
+}
+
+pub struct ReaderHighlighter {
+    settings: Settings,
+    current_line: usize,
+}
+
+impl Highlighter for ReaderHighlighter {
+    type Settings = Settings;
+    type Highlight = Highlight;
+    type Iterator<'a> = std::vec::IntoIter<(Range<usize>, Self::Highlight)>;
+
+    fn new(settings: &Self::Settings) -> Self {
+        Self { settings: settings.clone(), current_line: 0 }
+    }
+
+    fn update(&mut self, new_settings: &Self::Settings) {
+        self.settings = new_settings.clone();
+        self.current_line = 0;
+    }
+
+    fn change_line(&mut self, line: usize) {
+        self.current_line = std::cmp::min(line, self.current_line);
+    }
+
+    fn highlight_line(&mut self, line: &str) -> Self::Iterator<'_> {
+        self.current_line += 1;
+        let mut res = vec![];
+        if let Some(cedict) = self.settings.knowledge.as_ref() {
+            res.extend(cedict.knowledge_line(line).into_iter()
+                .map(|(r, k)| (r, Highlight::Knowledge(k))));
+        }
+        if let Some(grammar) = self.settings.grammar.as_ref() {
+            res.extend(grammar.find_line(line).into_iter()
+                .map(|(ix, r)| (r, Highlight::Grammar(ix))));
+        }
+        res.into_iter()
+    }
+
+    fn current_line(&self) -> usize {
+        self.current_line
+    }
+}
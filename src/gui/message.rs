@@ -20,6 +20,9 @@ pub enum Message {
     Settings,
     SettingsSave,
     AnkiStats,
+    AnkiStatsDays(u64),
+    AnkiStatsLoaded(std::sync::Arc<crate::anki::stats::Stats>),
+    AnkiStatsExport,
 
     EditAction(text_editor::Action),
     TextAction(super::TextOption),
@@ -79,6 +82,7 @@ pub enum Message {
     AnswerCopy,
     AnswerToNotes,
     RemoveNote,
+    NoteColor(crate::anchor::NoteColor),
     SaveNote,
 
     DbChange,
@@ -91,6 +95,59 @@ pub enum Message {
     CancelLoad,
     NewTextCheck(bool),
     NewText,
+    ImportSplit(bool),
+    ImportEpub,
+    EpubLoaded(std::sync::Arc<crate::import::epub::Book>),
+    ImportPdf,
+    PdfLoaded{ title: String, source: String, pdf: std::sync::Arc<crate::import::pdf::PdfText> },
+    ImportSubtitles,
+    ImportTxt,
+    TxtEncoding(crate::import::txt::TextEncoding),
+    TxtTitleChanged(String),
+    TxtSave,
+    SubtitlesLoaded{ title: String, source: String, cues: std::sync::Arc<Vec<crate::import::subtitle::Cue>> },
+    CuePrev,
+    CueNext,
+
+    Library,
+    LibrarySort(crate::library::LibrarySort),
+    LibraryQuery(String),
+    LibraryCollectionFilter(String),
+    LibraryTagFilter(String),
+    LibraryClearFilter,
+    LibrarySelect(u32, bool),
+    LibrarySelectAll(bool),
+    LibraryTagChanged(String),
+    LibraryAddTag,
+    LibraryRemoveTag,
+    LibraryCollectionChanged(String),
+    LibraryMove,
+    LibraryEstimate,
+    LibraryEstimated(u32, Option<u32>),
+    LibraryDelete,
+    LibraryOpen(u32),
+    LibraryEdit(Option<u32>),
+    LibraryEditField(crate::library::LibraryField, String),
+    LibrarySaveInfo,
+
+    NoteBrowser,
+    NoteBrowserQuery(String),
+    NoteBrowserDocument(crate::textbase::Document),
+    NoteBrowserTagFilter(String),
+    NoteBrowserFrom(String),
+    NoteBrowserTo(String),
+    NoteBrowserClearFilter,
+    NoteBrowserSelect(u32, bool),
+    NoteBrowserSelectAll(bool),
+    NoteBrowserTagChanged(String),
+    NoteBrowserAddTag,
+    NoteBrowserRemoveTag,
+    NoteBrowserExport,
+
+    ReadingStats,
+    ReadingStatsDays(u64),
+    ReadingDocument(Option<u32>),
+    ReadingTick,
 
     AiChatEvent(ChatEvent),
     PromptMeaning,
@@ -107,6 +164,9 @@ pub enum Message {
     ClearImage,
 
     UpdateProgress,
+    ProgressTick,
+    /// The window is closing, what is not saved yet is saved first
+    CloseRequested(iced::window::Id),
 
     DlEvent(crate::ocr::dl::DlEvent),
 
@@ -115,6 +175,38 @@ pub enum Message {
     AppDataChanged(String),
     //AnkiResUpdate(Vec<String>),
     AnkiChanged(String),
+    AnkiConnectChanged(crate::config::AnkiConnectField, String),
+    AnkiNotesChanged(crate::config::AnkiNotesField, String),
+    AnkiAddNote,
+    AnkiCheck,
+    AnkiReload,
+    AnkiReloaded(std::sync::Arc<crate::cedict::Cedict>),
+    KnowledgeMarks,
+    ApkgExport,
+    ApkgWordsAction(text_editor::Action),
+    ApkgDeckChanged(String),
+    ApkgFillUnknown,
+    ApkgSave,
+
+    Review,
+    ReviewShow,
+    ReviewGrade(crate::srs::Grade),
+    ReviewAddLookup,
+    ReviewAddWords,
+    ReviewAddNote(crate::textbase::Note),
+    ReviewImport,
+    ReviewExport,
+
+    ClozeAdd,
+    ClozeQueue,
+    ClozeTranslate(i64),
+    ClozeTranslated(i64, String),
+    ClozeTranslationChanged(i64, String),
+    ClozeTranslationSubmit,
+    ClozeRemove(i64),
+    ClozeExport,
+    ClozeToAnki,
+    ClozeSent(Vec<i64>, Option<String>),
 
     //MarkDown(bool),
 
@@ -125,6 +217,26 @@ pub enum Message {
     NotesCopy(String),
     NotesDelete{ document: u32, line: i64, character: i64 },
 
+    PdfExport,
+    PdfOptionsChanged(crate::pdf::PdfOptions),
+    PdfFontChanged(String),
+    PdfSave,
+    PdfSaveNotes,
+    HtmlExport,
+
+    Revisions,
+    RevisionSelect(i64),
+    RevisionRestore(i64),
+
+    Search,
+    SearchChanged(String),
+    SearchSubmit,
+    SearchOpen{ document: u32, line: i64, character: i64 },
+
+    GrammarScan,
+    GrammarSelect(usize),
+    GrammarPrompt(usize),
+
     DeeplAsk,
     DeeplLangSel(deepl::Lang),
     DeeplKeyChanged(String),
@@ -1,12 +1,22 @@
 mod settings;
+mod highlight;
+mod chart;
 pub mod message;
 
 use crate::ai::{CancellationToken, ChatCommand, ChatEvent};
-use crate::cedict::Cedict;
+use crate::cedict::{Cedict, Entry};
 use crate::error::ReaderError;
 use crate::ocr::dl::{DlCommand, DlEvent};
 use crate::{ai, make_enum, modal, ocr};
-use crate::config::{AiChatConfig, Config};
+use crate::config::{AiChatConfig, AnkiNotesField, Config};
+use crate::grammar::{Grammar, GrammarMatch};
+use crate::search::SearchHit;
+use crate::anki::chars::CharStats;
+use crate::anki::stats::Stats;
+use crate::library::{DocInfo, LibraryField, LibraryFilter, LibrarySort};
+use crate::reading::{ReadingStats, Session, Tracker};
+use crate::anchor::NoteColor;
+use crate::notes::{NoteEntry, NotesFilter};
 use iced::widget::text_editor::{Content, Position};
 use iced::{clipboard, Element, Subscription, Theme};
 use iced::widget::{text_editor,markdown};
@@ -17,14 +27,30 @@ use rusqlite::Connection;
 use tokio::sync::RwLock;
 use tokio::sync::mpsc::Sender;
 use crate::textbase::{*, Document as Doc};
-use crate::utils::{find_config_path, get_image, str_to_op, url_for_provider};
+use crate::utils::{byte_column, char_column, find_config_path, get_image, str_to_op, url_for_provider};
 use tracing::{debug, error, info, trace, warn};
+use std::collections::HashSet;
 use std::path::PathBuf;
 use std::sync::Arc;
 use message::Message;
 use base64::prelude::*;
 
-make_enum!(SidebarMode, [AI, Notes, Dictionary]);
+/// How often the Anki collection is checked for changes
+const ANKI_CHECK_SECS: u64 = 30;
+
+/// How often the reading session is saved
+const READING_SAVE_SECS: u64 = 60;
+
+/// Quiet time after the cursor stops before its position is saved
+const PROGRESS_SAVE_SECS: u64 = 2;
+
+/// Answers listed on the review screen
+const REVIEW_HISTORY: usize = 30;
+
+/// Periods of the statistics screen, in days
+pub const STATS_PERIODS: [u64; 3] = [30, 90, 365];
+
+make_enum!(SidebarMode, [AI, Notes, Dictionary, Grammar]);
 make_enum!(TextOption, [Load, Save, Add, New, Delete]);
 
 #[derive(PartialEq, Clone, Debug)]
@@ -107,6 +133,11 @@ fn ac_to_str(ac: &AssistantContent) -> &str {
     }
 }
 
+/// Links under an AI answer, `ix` is its place in the conversation
+fn answer_footer(ix: usize) -> String {
+    format!("\n[{}](B:{ix}) [{}](C:{ix}) [{}](N:{ix})\n", t!("append"), t!("replace"), t!("to_notes"))
+}
+
 fn message_to_str(msg: &Rmsg) -> String {
     match msg {
         Rmsg::User { .. } => {
@@ -122,6 +153,24 @@ fn message_to_str(msg: &Rmsg) -> String {
     }
 }
 
+/// Keys of the review screen: space or enter shows the answer, 1-4 grade it
+fn review_key(event: iced::keyboard::Event) -> Option<Message> {
+    use iced::keyboard::{Event, Key, key::Named};
+    use crate::srs::Grade;
+    let Event::KeyPressed { key, .. } = event else {
+        return None;
+    };
+    match key.as_ref() {
+        Key::Named(Named::Space) | Key::Named(Named::Enter) => Some(Message::ReviewShow),
+        Key::Named(Named::Escape) => Some(Message::Close),
+        Key::Character("1") => Some(Message::ReviewGrade(Grade::Again)),
+        Key::Character("2") => Some(Message::ReviewGrade(Grade::Hard)),
+        Key::Character("3") => Some(Message::ReviewGrade(Grade::Good)),
+        Key::Character("4") => Some(Message::ReviewGrade(Grade::Easy)),
+        _ => None,
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum TextMode {
     Raw,
@@ -138,6 +187,17 @@ pub enum AppState {
     Notes,
     AnkiStats,
     FileDl,
+    Search,
+    Concordance,
+    ApkgExport,
+    Review,
+    ClozeQueue,
+    TxtImport,
+    Library,
+    ReadingStats,
+    NoteBrowser,
+    PdfExport,
+    Revisions,
 }
 
 pub struct App {
@@ -157,7 +217,18 @@ pub struct App {
 
     image_data: Arc<RwLock<Vec<u8>>>,
     loaded_text: Doc,
-    cedict: Option<Cedict>,
+    cedict: Option<Arc<Cedict>>,
+    lookup: Option<Entry>,
+    grammar: Arc<Grammar>,
+    grammar_matches: Vec<GrammarMatch>,
+    knowledge_marks: bool,
+    anki_modified: Option<std::time::SystemTime>,
+    stats_days: u64,
+    stats: Option<Arc<Stats>>,
+    stats_charts: Vec<iced::widget::svg::Handle>,
+    char_stats: Option<CharStats>,
+    /// Words with mature cards for each HSK level, counted when the Anki collection is read
+    hsk_mature: std::collections::HashMap<u32, usize>,
 
     new_ai: Option<AiChatConfig>,
     chat_history: Vec<Rmsg>,
@@ -171,9 +242,52 @@ pub struct App {
     documents: Vec<Doc>,
     notes: Vec<Note>,
     note_idx: usize,
+    /// Colour of the next highlight
+    note_color: NoteColor,
+    note_entries: Vec<NoteEntry>,
+    note_filter: NotesFilter,
+    /// Notes whose text has the query of the filter
+    note_hits: HashSet<u32>,
+    note_selected: HashSet<u32>,
+    note_tag: String,
+    /// Dates of the filter, as typed
+    note_from: String,
+    note_to: String,
+    pdf_options: crate::pdf::PdfOptions,
+    revisions: Vec<crate::revisions::Revision>,
+    revision_selected: Option<i64>,
+    /// Saved content of the loaded text, the revisions are compared with it
+    revision_current: String,
+    /// Changes from the selected revision to the current text, None where lines are left out
+    revision_diff: Vec<Option<crate::diff::Change<String>>>,
 
     note_edited: bool,
     new_text: bool,
+    import_split: bool,
+    ocr_confidence: std::collections::HashMap<i64, f32>,
+    cues: Vec<crate::import::subtitle::CueLine>,
+    txt_file: Option<crate::import::txt::TxtFile>,
+
+    library: Vec<DocInfo>,
+    library_sort: LibrarySort,
+    library_ascending: bool,
+    library_filter: LibraryFilter,
+    library_selected: HashSet<u32>,
+    /// Tag and collection of the bulk operations, as typed
+    library_tag: String,
+    library_collection: String,
+    /// Document whose metadata is edited, with its tags as typed
+    library_edit: Option<(DocInfo, String)>,
+
+    reading: Option<Tracker>,
+    /// Line of the cursor and the characters before it, kept while the cursor stays on the line
+    reading_line: Option<(usize, usize)>,
+    reading_days: u64,
+    reading_sessions: Vec<Session>,
+    reading_stats: Option<ReadingStats>,
+    reading_charts: Vec<iced::widget::svg::Handle>,
+    /// Document whose sessions are listed
+    reading_document: Option<u32>,
 
     text_mode: TextMode,
     sc_new: bool,
@@ -195,16 +309,39 @@ pub struct App {
     image_include: bool,
 
     last_save: (i64,i64),
-}
-
-impl Default for App {
-    fn default() -> Self {
-        Self::new()
-    }
+    /// Last change of the position or view not saved yet
+    progress_changed: Option<std::time::Instant>,
+
+    search_query: String,
+    search_hits: Vec<SearchHit>,
+    concordance_word: String,
+    concordance: Vec<SearchHit>,
+
+    /// Settings text of the Anki note filters, as typed
+    anki_notes_text: Vec<String>,
+    /// Anki profile collections, found when the settings are opened
+    anki_profiles: Vec<String>,
+
+    apkg_words: text_editor::Content,
+    apkg_deck: String,
+
+    review_card: Option<crate::srs::Card>,
+    review_shown: bool,
+    /// Cards due and cards in total
+    review_counts: (usize, usize),
+    review_today: usize,
+    review_history: Vec<crate::srs::ReviewLog>,
+
+    cloze_cards: Vec<crate::cloze::QueuedCard>,
+    /// Queued card waiting for the AI translation of its sentence
+    cloze_pending: Option<i64>,
+    /// Cards with a translation typed but not saved yet
+    cloze_edited: HashSet<i64>,
 }
 
 impl App {
-    pub fn new() -> Self {
+    /// The app with the task that opens the document of the last session
+    pub fn new() -> (Self, iced::Task<Message>) {
         let conf: Config = match find_config_path() {
             Some(conf_path) => {
                 debug!("Config found at: {:?}", conf_path);
@@ -309,13 +446,28 @@ impl App {
 
         debug!("OCR models dir {}", models_dir);
 
+        let anki_notes_text = AnkiNotesField::ALL.iter()
+            .map(|f| conf.anki_notes.get(*f))
+            .collect();
         let anki_fname = conf.anki.clone();
-        let cedict = Cedict::new("dict.db", &anki_fname);
+        let cedict = Cedict::new("dict.db", &anki_fname, &conf.anki_notes);
         if let Err(e) = &cedict {
             error!("Cedict error: {}", e);
         }
 
-        Self {
+        let anki_modified = cedict.as_ref().ok()
+            .and_then(|c| c.anki_path())
+            .and_then(crate::anki::collection::modified);
+
+        let grammar = match Grammar::new(&conf.grammar) {
+            Ok(grammar) => grammar,
+            Err(e) => {
+                error!("Error loading grammar patterns: {}", e);
+                Grammar::default()
+            }
+        };
+
+        let mut app = Self {
             conf,
             state: AppState::Default,
             doc_conn,
@@ -331,7 +483,17 @@ impl App {
 
             image_data: Arc::new(RwLock::new(vec![])),
             loaded_text: crate::textbase::Document::default(),
-            cedict: cedict.ok(),
+            hsk_mature: cedict.as_ref().map(|cd| cd.count_hsk_mature()).unwrap_or_default(),
+            cedict: cedict.ok().map(Arc::new),
+            lookup: None,
+            grammar: Arc::new(grammar),
+            grammar_matches: vec![],
+            knowledge_marks: false,
+            anki_modified,
+            stats_days: STATS_PERIODS[1],
+            stats: None,
+            stats_charts: vec![],
+            char_stats: None,
 
             new_ai: None,
             chat_history: vec![],
@@ -344,9 +506,43 @@ impl App {
             documents,
             notes: vec![],
             note_idx: 0,
+            note_color: NoteColor::default(),
+            note_entries: vec![],
+            note_filter: NotesFilter::default(),
+            note_hits: HashSet::new(),
+            note_selected: HashSet::new(),
+            note_tag: String::new(),
+            note_from: String::new(),
+            note_to: String::new(),
+            pdf_options: crate::pdf::PdfOptions::default(),
+            revisions: vec![],
+            revision_selected: None,
+            revision_current: String::new(),
+            revision_diff: vec![],
 
             note_edited: false,
             new_text: false,
+            import_split: false,
+            ocr_confidence: Default::default(),
+            cues: vec![],
+            txt_file: None,
+
+            library: vec![],
+            library_sort: LibrarySort::Title,
+            library_ascending: true,
+            library_filter: LibraryFilter::default(),
+            library_selected: HashSet::new(),
+            library_tag: String::new(),
+            library_collection: String::new(),
+            library_edit: None,
+
+            reading: None,
+            reading_line: None,
+            reading_days: STATS_PERIODS[0],
+            reading_sessions: vec![],
+            reading_stats: None,
+            reading_charts: vec![],
+            reading_document: None,
 
             text_mode: TextMode::Raw,
             text_md: markdown::Content::new(),
@@ -369,7 +565,31 @@ impl App {
             image_include: false,
 
             last_save: (0,0),
-        }
+            progress_changed: None,
+
+            search_query: String::new(),
+            search_hits: vec![],
+            concordance_word: String::new(),
+            concordance: vec![],
+
+            anki_notes_text,
+            anki_profiles: vec![],
+
+            apkg_words: text_editor::Content::new(),
+            apkg_deck: String::from(crate::utils::APP_NAME),
+
+            review_card: None,
+            review_shown: false,
+            review_counts: (0, 0),
+            review_today: 0,
+            review_history: vec![],
+
+            cloze_cards: vec![],
+            cloze_pending: None,
+            cloze_edited: HashSet::new(),
+        };
+        let task = app.restore_session();
+        (app, task)
     }
 
     pub fn theme(&self) -> Theme {
@@ -401,7 +621,20 @@ impl App {
 
         let ai_chat_sub = Subscription::run(ai::connect).map(Message::AiChatEvent);
         let dl_sub = Subscription::run(ocr::dl::connect).map(Message::DlEvent);
-        let subs = vec![ai_chat_sub, dl_sub];
+        let mut subs = vec![ai_chat_sub, dl_sub];
+        if self.cedict.as_ref().is_some_and(|c| c.anki_path().is_some()) {
+            subs.push(iced::time::every(std::time::Duration::from_secs(ANKI_CHECK_SECS)).map(|_| Message::AnkiCheck));
+        }
+        if matches!(self.state, AppState::Review) {
+            subs.push(iced::keyboard::listen().filter_map(review_key));
+        }
+        if self.reading.is_some() {
+            subs.push(iced::time::every(std::time::Duration::from_secs(READING_SAVE_SECS)).map(|_| Message::ReadingTick));
+        }
+        if self.progress_changed.is_some() {
+            subs.push(iced::time::every(std::time::Duration::from_secs(1)).map(|_| Message::ProgressTick));
+        }
+        subs.push(iced::window::close_requests().map(Message::CloseRequested));
         Subscription::batch(subs)
     }
 
@@ -431,6 +664,39 @@ impl App {
             AppState::AnkiStats => {
                 settings::anki_stats(self).into()
             }
+            AppState::Search => {
+                settings::search(self).into()
+            }
+            AppState::Concordance => {
+                settings::concordance(self).into()
+            }
+            AppState::ApkgExport => {
+                settings::apkg_export(self).into()
+            }
+            AppState::PdfExport => {
+                settings::pdf_export(self).into()
+            }
+            AppState::Revisions => {
+                settings::revisions(self).into()
+            }
+            AppState::Review => {
+                settings::review(self).into()
+            }
+            AppState::ClozeQueue => {
+                settings::cloze_queue(self).into()
+            }
+            AppState::TxtImport => {
+                settings::txt_import(self).into()
+            }
+            AppState::Library => {
+                settings::library(self).into()
+            }
+            AppState::NoteBrowser => {
+                settings::note_browser(self).into()
+            }
+            AppState::ReadingStats => {
+                settings::reading_stats(self).into()
+            }
         }
     }
 
@@ -442,6 +708,7 @@ impl App {
     pub fn update(&mut self, message: Message) -> iced::Task<Message> {
         match message {
             Message::Close => {
+                self.save_cloze_translations();
                 self.state = AppState::Default;
                 self.new_ai = None;
                 self.new_text = false;
@@ -480,6 +747,10 @@ impl App {
             }
             Message::SidebarModeChanged(sm) => {
                 self.sidebar_mode = sm;
+                if sm == SidebarMode::Grammar {
+                    self.scan_grammar();
+                }
+                self.schedule_save();
             }
             Message::AiTextCtxToggle(x) => {
                 self.text_ctx = x;
@@ -491,12 +762,17 @@ impl App {
                 self.state = AppState::AiSettings;
             }
             Message::Settings => {
+                self.anki_profiles = crate::anki::collection::profiles().iter()
+                    .map(|p| p.to_string_lossy().to_string())
+                    .collect();
                 self.state = AppState::Settings;
             }
             Message::SetTextValue(s) => {
                 if !self.dtn_append {
                     self.text = text_editor::Content::new();
                     self.loaded_text = Doc::default();
+                    self.ocr_confidence.clear();
+                    self.cues.clear();
                 }
                 self.text.perform(
                     text_editor::Action::Edit(text_editor::Edit::Paste(Arc::new(s)))
@@ -610,6 +886,7 @@ impl App {
                 }
             }
             Message::EditAction(a) => {
+                let mut looked_up: Option<String> = None;
                 match a {
                     text_editor::Action::Click(_) => {
                         self.text.perform(a);
@@ -626,14 +903,17 @@ impl App {
 
                         self.result_text = markdown::Content::new();
                         self.result_raw = String::new();
+                        self.lookup = None;
                         if let Some(s) = self.text.selection() 
                             && s.len() <= 15 { // is word
                             if let Some(cedict) = &self.cedict {
                                 let res = cedict.find(s.trim());
                                 debug!("CEDict::Result {:?}", res);
+                                self.lookup = res.first().map(|e| (*e).clone());
+                                looked_up = self.lookup.as_ref().map(|e| e.simplified().to_string());
                                 for e in res {
                                     debug!("Entry: {:?}", e);
-                                    self.result_text.push_str(e.to_md().as_str());
+                                    self.result_text.push_str(e.to_md(cedict.anki_entry(e)).as_str());
                                     self.result_raw.push_str( format!("{}{}", self.result_raw, e).as_str() );
                                 }
                             } else {
@@ -642,17 +922,32 @@ impl App {
                         }
                     }
                     _ => {
+                        let is_edit = a.is_edit();
                         self.text.perform(a);
                         self.position = self.text.cursor().position;
+                        if is_edit {
+                            self.reading_line = None;
+                        }
+                        if is_edit && self.sidebar_mode == SidebarMode::Grammar {
+                            self.scan_grammar();
+                        }
                     }
                 }
+                self.track_reading();
+                if let (Some(tracker), Some(word)) = (self.reading.as_mut(), looked_up) {
+                    tracker.lookup(&word, chrono::Utc::now());
+                }
 
                 let c = self.position;
+                if (c.line as i64, c.column as i64) != self.last_save {
+                    self.schedule_save();
+                }
                 if self.notes.is_empty() && !self.loaded_text.is_empty() 
                     && let Ok(notes) = get_notes(&self.doc_conn, self.loaded_text.id) {
                     self.notes = notes;
                 }
-                if let Some(note) = self.notes.iter().find(|n| n.line == (c.line as i64) && n.char == (c.column as i64) ) {
+                let (line, column) = self.char_position(c);
+                if let Some(note) = self.notes.iter().find(|n| n.contains(line, column)) {
                     debug!("Found note");
                     self.sidebar_notes = text_editor::Content::with_text(note.text.as_str());
                 } else {
@@ -822,11 +1117,16 @@ impl App {
                     }
                     ChatEvent::Final => {
                         debug!("Received final message from stream");
-                        let answer_ix = self.chat_history.len();
-                        let answer_footer = format!("\n[{}](B:{answer_ix}) [{}](C:{answer_ix}) [{}](N:{answer_ix})\n", t!("append"), t!("replace"), t!("to_notes"));
+                        let answer_footer = answer_footer(self.chat_history.len());
                         debug!("footer: {}", answer_footer);
                         debug!("answer_t: {}", self.answer_t );
                         if !self.answer_t.is_empty() {
+                            if let Some(id) = self.cloze_pending.take() {
+                                if let Err(e) = crate::cloze::set_translation(&self.doc_conn, id, self.answer_t.as_str()) {
+                                    error!("Error saving translation: {}", e);
+                                }
+                                self.load_cloze_cards();
+                            }
                             self.answer_text.push_str(&answer_footer);
                             self.answer_raw.push_str("\n");
                             let answer_t = std::mem::take(&mut self.answer_t);
@@ -835,6 +1135,7 @@ impl App {
                                 id: Some(String::from("**agent**")),
                                 content: OneOrMany::one(rig::message::AssistantContent::Text(rig::agent::Text::from(answer_t))) 
                             });
+                            self.schedule_save();
                         }
                     }
                     ChatEvent::ToolCall { id, function, args } => {
@@ -851,6 +1152,7 @@ impl App {
                     }
                     ChatEvent::ChatError(error) => {
                         self.answer_t.clear();
+                        self.cloze_pending = None;
                         return iced::Task::done(Message::ShowModal(error));
                     }
                 }
@@ -873,6 +1175,7 @@ impl App {
             Message::AiHistoryClear => {
                 self.chat_history.clear();
                 self.answer_text = markdown::Content::new();
+                self.schedule_save();
             }
             Message::AiCurrentTextToEditor => {
                 if !self.dtn_append {
@@ -929,32 +1232,48 @@ impl App {
             Message::AnswerCopy => {
             }
             Message::UpdateProgress => {
-                let c = self.text.cursor().position;
-                let line = c.line as i64;
-                let character = c.column as i64;
-
-                self.loaded_text.line = line;
-                self.loaded_text.character = character;
-
-                if let Err(e) = update_progress(&mut self.doc_conn, self.loaded_text.id as i64, character, line) {
+                if let Err(e) = self.save_progress() {
                     error!("Error updating progress {}", e);
                     return iced::Task::done(Message::ShowModal(e.to_string()));
                 }
+                self.track_reading();
+                self.save_reading();
+            }
+            Message::ProgressTick => {
+                if self.progress_changed.is_some_and(|t| t.elapsed().as_secs() >= PROGRESS_SAVE_SECS)
+                    && let Err(e) = self.save_progress() {
+                    error!("Error updating progress {}", e);
+                }
             }
             Message::SaveNote => {
                 if self.loaded_text.id > 0 {
-                    let c = self.text.cursor().position;
-                    debug!("Cursor position: {:?}", c);
-                    let line = c.line as i64;
-                    let character = c.column as i64;
-                    let content = self.sidebar_notes.text();
-                    
-                    self.notes.push(Note { id: 0, doc: self.loaded_text.id, line, char: character, text: content.clone() });
-
-                    match save_note(&mut self.doc_conn, line, character, self.loaded_text.id, content.as_str()) {
+                    let cursor = self.text.cursor();
+                    debug!("Cursor position: {:?}", cursor.position);
+                    // A selection becomes a highlight
+                    let (start, end) = match cursor.selection {
+                        Some(s) if (s.line, s.column) < (cursor.position.line, cursor.position.column) => (s, cursor.position),
+                        Some(s) => (cursor.position, s),
+                        None => (cursor.position, cursor.position),
+                    };
+                    let (line, character) = self.char_position(start);
+                    let (end_line, end_char) = self.char_position(end);
+                    let note = Note {
+                        doc: self.loaded_text.id,
+                        line,
+                        char: character,
+                        end_line,
+                        end_char,
+                        color: self.note_color,
+                        text: self.sidebar_notes.text(),
+                        ..Default::default()
+                    }.anchored(&self.text.text());
+
+                    match save_note(&mut self.doc_conn, &note) {
                         Err(e) => error!("Error saving note: {}", e),
                         Ok(id) => {
                             info!("Successfully saved note id {}, {}:{}", id, line, character);
+                            self.notes.push(Note { id: id as u32, ..note });
+                            self.notes.sort_by_key(|n| n.pos());
                             self.note_edited = false;
                             self.sidebar_notes = Content::new();
                             self.sidebar_mode = SidebarMode::Dictionary;
@@ -964,48 +1283,38 @@ impl App {
                 }
             }
             Message::RemoveNote => {
-                if self.loaded_text.id > 0 {
-                    let c = self.text.cursor().position;
-                    let line = c.line as i64;
-                    let character = c.column as i64;
+                let (line, column) = self.char_position(self.text.cursor().position);
+                if self.loaded_text.id > 0
+                    && let Some((line, character)) = self.notes.iter()
+                        .find(|n| n.contains(line, column))
+                        .map(|n| n.pos()) {
 
                     if let Err(e) = delete_note(&mut self.doc_conn, self.loaded_text.id, line, character) {
                         error!("Error deleting note: {}", e);
                         return iced::Task::done(Message::ShowModal(format!("Error deleting note\n{}", e)));
                     } else {
+                        self.notes.retain(|n| n.pos() != (line, character));
                         self.note_edited = false;
                     }
                 }
             }
+            Message::NoteColor(color) => {
+                self.note_color = color;
+                let (line, column) = self.char_position(self.text.cursor().position);
+                if let Some(note) = self.notes.iter_mut().find(|n| n.is_range() && n.contains(line, column)) {
+                    note.color = color;
+                    if let Err(e) = set_note_color(&self.doc_conn, note.id, color) {
+                        error!("Error saving the colour of the note: {}", e);
+                        return modal!(e);
+                    }
+                }
+            }
             Message::LoadText => {
                 let document = self.documents.iter()
-                    .find(|s| s.title == self.loaded_text.title);
+                    .find(|s| s.title == self.loaded_text.title)
+                    .cloned();
                 if let Some(document) = document {
-                    match get_content(&self.doc_conn, document.id) {
-                        Ok(Some(cc)) => {
-                            self.text = text_editor::Content::with_text(cc.as_str());
-                            self.state = AppState::Default;
-                            self.loaded_text.line = document.line;
-                            self.loaded_text.character = document.character;
-                            match get_notes(&self.doc_conn, document.id) {
-                                Ok(notes) => {
-                                    self.notes = notes;
-                                    info!("Loaded {} notes", self.notes.len());
-                                }
-                                Err(e) => {
-                                    error!("Error fetching notes: {}", e);
-                                    return iced::Task::done(Message::ShowModal(format!("Error fetching note\n{}", e)));
-                                }
-                            }
-                        }
-                        Ok(None) => {
-                            return iced::Task::done(Message::ShowModal(format!("Text not found!")));
-                        }
-                        Err(e) => {
-                            error!("Error retrieving document: {}", e);
-                            return iced::Task::done(Message::ShowModal(format!("Error retrieving text\n{}", e)));
-                        }
-                    }
+                    return self.load_document(document);
                 }
             }
             Message::SaveText => {
@@ -1014,7 +1323,15 @@ impl App {
                     Ok(id) => {
                         info!("Text saved successfully: {id}");
                         self.loaded_text.id = id as u32;
+                        // Edits move the notes, subtitle times and OCR scores of lines
+                        self.notes = get_notes(&self.doc_conn, self.loaded_text.id)
+                            .unwrap_or_default();
+                        self.ocr_confidence = crate::import::confidence(&self.doc_conn, self.loaded_text.id)
+                            .unwrap_or_default();
+                        self.cues = crate::import::cues(&self.doc_conn, self.loaded_text.id)
+                            .unwrap_or_default();
                         self.state = AppState::Default;
+                        return self.estimate_difficulty(self.loaded_text.id, self.text.text());
                     }
                     Err(e) => {
                         error!("Error saving text: {}", e);
@@ -1024,9 +1341,12 @@ impl App {
                 }
             }
             Message::CancelLoad => {
+                self.finish_reading();
                 self.state = AppState::Default;
                 self.loaded_text = Doc::default();
                 self.text = text_editor::Content::new();
+                self.ocr_confidence.clear();
+                self.cues.clear();
                 self.note_edited = false;
                 self.new_text = false;
                 self.sidebar_notes = text_editor::Content::new();
@@ -1039,6 +1359,7 @@ impl App {
                 match delete_text(&mut self.doc_conn, self.loaded_text.title.as_str()) {
                     Ok(_) => {
                         info!("Text deleted successfully");
+                        self.reading = None;
                         self.loaded_text = Doc::default();
                         self.text = text_editor::Content::new();
                         self.documents = vec![];
@@ -1092,32 +1413,38 @@ impl App {
                         debug!("Query: {:?}", q);
                         if !q.is_empty() {
                             let res = cedict.find(q.trim());
+                            self.lookup = res.first().map(|e| (*e).clone());
                             for e in res {
                                 self.result_text = markdown::Content::new();
-                                self.result_text.push_str(e.to_md().as_str());
+                                self.result_text.push_str(e.to_md(cedict.anki_entry(e)).as_str());
                                 self.result_raw.push_str( format!("{}{}", self.result_raw, e.to_string()).as_str() );
                             }
                             self.sidebar_mode = SidebarMode::Dictionary;
                         }
                     }
+                } else if e.starts_with("s:") {
+                    let word = e[2..].trim().to_string();
+                    debug!("Concordance for {}", word);
+                    match crate::search::concordance(&self.doc_conn, word.as_str()) {
+                        Ok(hits) => {
+                            self.concordance = hits;
+                            self.concordance_word = word;
+                            self.state = AppState::Concordance;
+                        }
+                        Err(e) => {
+                            error!("Concordance error: {}", e);
+                            return modal!(e);
+                        }
+                    }
                 } else if e.starts_with("a:") {
                     debug!("Anki");
                 } else if e.starts_with("n:") {
                     debug!("Note link clicked");
-                    let q = &e[2..];
-                    if let Some(sep_ix) = q.find(":") {
-                        let line = &e[..sep_ix];
-                        let column = &e[sep_ix..];
-                        debug!("clicked note link: {}:{}",line,column);
-                        if let Ok(line) = line.parse::<usize>()
-                            && let Ok(column) = column.parse::<usize>() {
-
-                            self.sidebar_notes = Content::new();
-                            let note = self.notes.iter().find(|n| n.line == line as i64 && n.char == column as i64);
-                            if let Some(note) = note {
-                                self.sidebar_notes = Content::with_text(note.text.as_str());
-                                self.sidebar_mode = SidebarMode::Notes;
-                            }
+                    if let Ok(id) = e[2..].parse::<u32>() {
+                        self.sidebar_notes = Content::new();
+                        if let Some(note) = self.notes.iter().find(|n| n.id == id) {
+                            self.sidebar_notes = Content::with_text(note.text.as_str());
+                            self.sidebar_mode = SidebarMode::Notes;
                         }
                     }
                 } else if e.starts_with("B:") || e.starts_with("C:") {
@@ -1167,12 +1494,587 @@ impl App {
             Message::NewTextCheck(n) => {
                 self.new_text = n;
             }
+            Message::ImportSplit(split) => {
+                self.import_split = split;
+            }
+            Message::ImportEpub => {
+                if let Some(f) = rfd::FileDialog::new()
+                    .add_filter("EPUB", &["epub"])
+                    .pick_file() {
+                    return iced::Task::perform(async move {
+                        tokio::task::spawn_blocking(move || crate::import::epub::read_file(f)).await
+                    }, |r| match r {
+                        Ok(Ok(book)) => Message::EpubLoaded(Arc::new(book)),
+                        Ok(Err(e)) => Message::ShowModal(e.to_string()),
+                        Err(e) => Message::ShowModal(e.to_string()),
+                    });
+                }
+            }
+            Message::ImportPdf => {
+                if let Some(f) = rfd::FileDialog::new()
+                    .add_filter("PDF", &["pdf"])
+                    .pick_file() {
+                    let model_path = self.models_dir.clone();
+                    return iced::Task::perform(async move {
+                        tokio::task::spawn_blocking(move || {
+                            let data = std::fs::read(&f)?;
+                            // The models are only loaded for scanned pages
+                            let mut models = None;
+                            let pdf = crate::import::pdf::read_pdf(&data, |img| {
+                                if models.is_none() {
+                                    models = Some(ocr::load_models(&model_path)?);
+                                }
+                                let (det, rec) = models.as_mut().unwrap();
+                                ocr::ocr_lines(det, rec, img)
+                            })?;
+                            let title = f.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+                            let source = format!("pdf:{:x}", <sha1::Sha1 as sha1::Digest>::digest(&data));
+                            Ok::<_, ReaderError>((title, source, pdf))
+                        }).await
+                    }, |r| match r {
+                        Ok(Ok((title, source, pdf))) => Message::PdfLoaded { title, source, pdf: Arc::new(pdf) },
+                        Ok(Err(e)) => Message::ShowModal(e.to_string()),
+                        Err(e) => Message::ShowModal(e.to_string()),
+                    });
+                }
+            }
+            Message::PdfLoaded { title, source, pdf } => {
+                let (content, scores) = pdf.content();
+                let imported = crate::import::save_documents(&mut self.doc_conn, &source, "", false, &[(title.clone(), content)])
+                    .and_then(|imported| {
+                        crate::import::save_confidence(&mut self.doc_conn, imported.ids[0], &scores)?;
+                        Ok(imported)
+                    });
+                match imported {
+                    Ok(imported) => {
+                        info!("Imported {}: {} pages, {} recognised", title, pdf.pages.len(), pdf.ocr_pages());
+                        return self.open_imported(&imported);
+                    }
+                    Err(e) => {
+                        error!("Error importing {}: {}", title, e);
+                        return modal!(e);
+                    }
+                }
+            }
+            Message::EpubLoaded(book) => {
+                let documents = book.documents(self.import_split);
+                match crate::import::save_documents(&mut self.doc_conn, &book.identifier, &book.author, self.import_split, &documents) {
+                    Ok(imported) => {
+                        info!("Imported {}: {:?}", book.title, imported);
+                        return self.open_imported(&imported);
+                    }
+                    Err(e) => {
+                        error!("Error importing {}: {}", book.title, e);
+                        return modal!(e);
+                    }
+                }
+            }
+            Message::ImportTxt => {
+                if let Some(f) = rfd::FileDialog::new()
+                    .add_filter("TXT", &["txt"])
+                    .pick_file() {
+                    match crate::import::txt::TxtFile::read(f) {
+                        Ok(file) => {
+                            debug!("Text file {} detected as {}", file.title, file.detected);
+                            self.txt_file = Some(file);
+                            self.state = AppState::TxtImport;
+                        }
+                        Err(e) => {
+                            error!("Error reading text file: {}", e);
+                            return modal!(e);
+                        }
+                    }
+                }
+            }
+            Message::TxtEncoding(encoding) => {
+                if let Some(file) = self.txt_file.as_mut() {
+                    file.set_encoding(encoding);
+                }
+            }
+            Message::TxtTitleChanged(title) => {
+                if let Some(file) = self.txt_file.as_mut() {
+                    file.title = title;
+                }
+            }
+            Message::TxtSave => {
+                if let Some(file) = self.txt_file.take() {
+                    let source = format!("txt:{:x}", <sha1::Sha1 as sha1::Digest>::digest(&file.data));
+                    match crate::import::save_documents(&mut self.doc_conn, &source, "", false, &[(file.title.clone(), file.text())]) {
+                        Ok(imported) => {
+                            info!("Imported {} as {}", file.title, file.encoding);
+                            return self.open_imported(&imported);
+                        }
+                        Err(e) => {
+                            error!("Error importing {}: {}", file.title, e);
+                            return modal!(e);
+                        }
+                    }
+                }
+            }
+            Message::ImportSubtitles => {
+                if let Some(files) = rfd::FileDialog::new()
+                    .add_filter("Subtitles", &["srt", "ass", "ssa", "vtt"])
+                    .pick_files() {
+                    return iced::Task::perform(async move {
+                        tokio::task::spawn_blocking(move || {
+                            let tracks = files.iter()
+                                .map(|f| std::fs::read(f).map(|data| crate::import::txt::decode(&data, crate::import::txt::detect(&data))))
+                                .collect::<Result<Vec<_>, _>>()?;
+                            let cues = crate::import::subtitle::from_tracks(&tracks)?;
+                            let title = files.first()
+                                .and_then(|f| f.file_stem())
+                                .map(|s| s.to_string_lossy().to_string())
+                                .unwrap_or_default();
+                            let source = format!("subtitle:{:x}", <sha1::Sha1 as sha1::Digest>::digest(tracks.concat()));
+                            Ok::<_, ReaderError>((title, source, cues))
+                        }).await
+                    }, |r| match r {
+                        Ok(Ok((title, source, cues))) => Message::SubtitlesLoaded { title, source, cues: Arc::new(cues) },
+                        Ok(Err(e)) => Message::ShowModal(e.to_string()),
+                        Err(e) => Message::ShowModal(e.to_string()),
+                    });
+                }
+            }
+            Message::SubtitlesLoaded { title, source, cues } => {
+                let (content, times) = crate::import::subtitle::to_document(&cues);
+                let imported = crate::import::save_documents(&mut self.doc_conn, &source, "", false, &[(title.clone(), content)])
+                    .and_then(|imported| {
+                        crate::import::save_cues(&mut self.doc_conn, imported.ids[0], &times)?;
+                        Ok(imported)
+                    });
+                match imported {
+                    Ok(imported) => {
+                        info!("Imported {}: {} cues", title, cues.len());
+                        return self.open_imported(&imported);
+                    }
+                    Err(e) => {
+                        error!("Error importing {}: {}", title, e);
+                        return modal!(e);
+                    }
+                }
+            }
+            jump @ (Message::CuePrev | Message::CueNext) => {
+                let line = self.text.cursor().position.line as i64;
+                let cue = if matches!(jump, Message::CueNext) {
+                    self.cues.iter().find(|c| c.line > line)
+                } else {
+                    self.cues.iter().rev().find(|c| c.line < line)
+                };
+                if let Some(cue) = cue {
+                    let line = cue.line as usize;
+                    let len = self.text.line(line).map(|l| l.text.len()).unwrap_or(0);
+                    self.text.move_to(text_editor::Cursor {
+                        position: Position { line, column: len },
+                        selection: Some(Position { line, column: 0 }),
+                    });
+                    self.position = self.text.cursor().position;
+                }
+            }
+            Message::Library => {
+                self.library_selected.clear();
+                self.library_edit = None;
+                self.load_library();
+                self.state = AppState::Library;
+            }
+            Message::LibrarySort(sort) => {
+                self.library_ascending = self.library_sort != sort || !self.library_ascending;
+                self.library_sort = sort;
+            }
+            Message::LibraryQuery(q) => {
+                self.library_filter.query = q;
+            }
+            Message::LibraryCollectionFilter(c) => {
+                self.library_filter.collection = Some(c);
+            }
+            Message::LibraryTagFilter(t) => {
+                self.library_filter.tag = Some(t);
+            }
+            Message::LibraryClearFilter => {
+                self.library_filter = LibraryFilter::default();
+            }
+            Message::LibrarySelect(id, selected) => {
+                if selected {
+                    self.library_selected.insert(id);
+                } else {
+                    self.library_selected.remove(&id);
+                }
+            }
+            Message::LibrarySelectAll(selected) => {
+                self.library_selected = if selected {
+                    crate::library::view(&self.library, &self.library_filter, self.library_sort, self.library_ascending)
+                        .iter().map(|d| d.id).collect()
+                } else {
+                    HashSet::new()
+                };
+            }
+            Message::LibraryTagChanged(t) => {
+                self.library_tag = t;
+            }
+            Message::LibraryCollectionChanged(c) => {
+                self.library_collection = c;
+            }
+            bulk @ (Message::LibraryAddTag | Message::LibraryRemoveTag | Message::LibraryMove | Message::LibraryDelete) => {
+                let ids: Vec<u32> = self.library_selected.iter().copied().collect();
+                let res = match bulk {
+                    Message::LibraryAddTag if !self.library_tag.trim().is_empty() => crate::library::add_tag(&mut self.doc_conn, &ids, &self.library_tag),
+                    Message::LibraryRemoveTag => crate::library::remove_tag(&mut self.doc_conn, &ids, &self.library_tag),
+                    Message::LibraryMove => crate::library::move_to(&mut self.doc_conn, &ids, &self.library_collection),
+                    Message::LibraryDelete => crate::textbase::delete_documents(&mut self.doc_conn, &ids),
+                    _ => Ok(()),
+                };
+                if let Err(e) = res {
+                    error!("Error updating the library: {}", e);
+                    return modal!(e);
+                }
+                if matches!(bulk, Message::LibraryDelete) {
+                    info!("Deleted {} texts", ids.len());
+                    self.library_selected.clear();
+                    if ids.contains(&self.loaded_text.id) {
+                        self.reading = None;
+                        self.loaded_text = Doc::default();
+                        self.text = text_editor::Content::new();
+                        self.notes.clear();
+                        self.ocr_confidence.clear();
+                        self.cues.clear();
+                        self.note_edited = false;
+                        self.sidebar_notes = text_editor::Content::new();
+                    }
+                }
+                self.load_library();
+            }
+            Message::LibraryEstimate => {
+                let tasks: Vec<_> = self.library_selected.iter()
+                    .filter_map(|id| get_content(&self.doc_conn, *id).ok().flatten().map(|content| (*id, content)))
+                    .map(|(id, content)| self.estimate_difficulty(id, content))
+                    .collect();
+                return iced::Task::batch(tasks);
+            }
+            Message::LibraryEstimated(id, difficulty) => {
+                if let Err(e) = crate::library::set_difficulty(&self.doc_conn, id, difficulty) {
+                    error!("Error saving difficulty: {}", e);
+                }
+                if matches!(self.state, AppState::Library) {
+                    self.load_library();
+                }
+            }
+            Message::LibraryOpen(id) => {
+                let document = self.documents.iter()
+                    .find(|d| d.id == id)
+                    .cloned();
+                if let Some(document) = document {
+                    return self.load_document(document);
+                }
+            }
+            Message::LibraryEdit(id) => {
+                self.library_edit = id
+                    .and_then(|id| self.library.iter().find(|d| d.id == id))
+                    .map(|d| (d.clone(), d.tags.join(", ")));
+            }
+            Message::LibraryEditField(field, value) => {
+                if let Some((info, tags)) = self.library_edit.as_mut() {
+                    match field {
+                        LibraryField::Author => info.author = value,
+                        LibraryField::Url => info.url = value,
+                        LibraryField::Collection => info.collection = value,
+                        LibraryField::Tags => *tags = value,
+                    }
+                }
+            }
+            Message::LibrarySaveInfo => {
+                if let Some((mut info, tags)) = self.library_edit.take() {
+                    info.tags = crate::library::parse_tags(&tags);
+                    if let Err(e) = crate::library::save_info(&mut self.doc_conn, &info) {
+                        error!("Error saving text details: {}", e);
+                        return modal!(e);
+                    }
+                    self.load_library();
+                }
+            }
+            Message::ReadingStats => {
+                self.save_reading();
+                self.reading_document = None;
+                self.state = AppState::ReadingStats;
+                self.load_reading_stats();
+            }
+            Message::ReadingStatsDays(days) => {
+                self.reading_days = days;
+                self.load_reading_stats();
+            }
+            Message::ReadingDocument(document) => {
+                self.reading_document = document;
+            }
+            Message::CloseRequested(id) => {
+                if self.progress_changed.is_some()
+                    && let Err(e) = self.save_progress() {
+                    error!("Error updating progress {}", e);
+                }
+                self.finish_reading();
+                info!("Window {:?} closed", id);
+                return iced::exit();
+            }
+            Message::ReadingTick => {
+                if self.reading.as_ref().is_some_and(|t| t.is_over(chrono::Utc::now())) {
+                    self.finish_reading();
+                } else {
+                    self.save_reading();
+                }
+            }
             Message::AnkiChanged(s) => {
                 self.conf.anki = Some(s);
             }
+            Message::AnkiConnectChanged(field, value) => {
+                self.conf.anki_connect.set(field, value);
+            }
+            Message::AnkiNotesChanged(field, value) => {
+                if let Some(ix) = AnkiNotesField::ALL.iter().position(|f| *f == field) {
+                    self.conf.anki_notes.set(field, value.clone());
+                    self.anki_notes_text[ix] = value;
+                }
+            }
+            Message::AnkiCheck => {
+                if let Some(cedict) = self.cedict.clone()
+                    && let Some(path) = cedict.anki_path() {
+                    let modified = crate::anki::collection::modified(path);
+                    if modified != self.anki_modified {
+                        debug!("Anki collection changed, reloading");
+                        self.anki_modified = modified;
+                        return self.reload_anki(cedict);
+                    }
+                }
+            }
+            Message::AnkiReload => {
+                if let Some(cedict) = self.cedict.clone() {
+                    return self.reload_anki(cedict);
+                }
+            }
+            Message::AnkiReloaded(cedict) => {
+                self.anki_modified = cedict.anki_path().and_then(crate::anki::collection::modified);
+                self.hsk_mature = cedict.count_hsk_mature();
+                self.cedict = Some(cedict);
+            }
+            Message::AnkiAddNote => {
+                if let Some(note) = self.lookup_note() {
+                    let conf = self.conf.anki_connect.clone();
+                    return iced::Task::perform(async move {
+                        crate::anki::connect::AnkiConnect::new(&conf).add_note(&note).await
+                    }, |r| {
+                        match r {
+                            Ok(id) => {
+                                info!("Anki note added: {}", id);
+                                Message::AppendResult(format!("\n\n*{}*\n", t!("anki_added")))
+                            }
+                            Err(e) => Message::ShowModal(e.to_string()),
+                        }
+                    });
+                }
+            }
+            Message::ApkgExport => {
+                self.state = AppState::ApkgExport;
+            }
+            Message::ApkgWordsAction(a) => {
+                self.apkg_words.perform(a);
+            }
+            Message::ApkgDeckChanged(deck) => {
+                self.apkg_deck = deck;
+            }
+            Message::ApkgFillUnknown => {
+                if let Some(cedict) = self.cedict.as_ref() {
+                    let words: Vec<&str> = cedict.unknown_words(self.text.text().as_str()).into_iter()
+                        .map(|e| e.simplified())
+                        .collect();
+                    self.apkg_words = Content::with_text(words.join("\n").as_str());
+                }
+            }
+            Message::ApkgSave => {
+                let notes = self.apkg_notes();
+                if !notes.is_empty()
+                    && let Some(f) = rfd::FileDialog::new()
+                        .add_filter("Anki", &["apkg"])
+                        .set_file_name(format!("{}.apkg", self.apkg_deck))
+                        .save_file() {
+                    match crate::anki::apkg::export_apkg(&f, self.apkg_deck.as_str(), &notes) {
+                        Ok(cards) => {
+                            info!("Exported {} cards to {:?}", cards, f);
+                            self.state = AppState::Default;
+                        }
+                        Err(e) => {
+                            error!("Apkg export error: {}", e);
+                            return modal!(e);
+                        }
+                    }
+                }
+            }
+            Message::Review => {
+                self.state = AppState::Review;
+                return self.review_next();
+            }
+            Message::ReviewShow => {
+                if self.review_shown {
+                    return iced::Task::done(Message::ReviewGrade(crate::srs::Grade::Good));
+                }
+                self.review_shown = self.review_card.is_some();
+            }
+            Message::ReviewGrade(grade) => {
+                if self.review_shown
+                    && let Some(mut card) = self.review_card.take() {
+                    if let Err(e) = crate::srs::save_review(&mut self.doc_conn, &mut card, grade, chrono::Utc::now()) {
+                        error!("Error saving review: {}", e);
+                        return modal!(e);
+                    }
+                    return self.review_next();
+                }
+            }
+            Message::ReviewAddLookup => {
+                if let Some(note) = self.lookup_note() {
+                    return self.add_review_cards(&[note]);
+                }
+            }
+            Message::ReviewAddWords => {
+                // The sidebar is not shown with the word list, the result goes in a modal
+                let notes = self.apkg_notes();
+                match crate::srs::add_cards(&mut self.doc_conn, &notes, chrono::Utc::now()) {
+                    Ok(n) => {
+                        info!("{} review cards added", n);
+                        return modal!(t!("review_added", n = n));
+                    }
+                    Err(e) => {
+                        error!("Error adding review cards: {}", e);
+                        return modal!(e);
+                    }
+                }
+            }
+            Message::ReviewAddNote(note) => {
+                match self.note_data(&note) {
+                    Some(note) => return self.add_review_cards(&[note]),
+                    None => return modal!(t!("review_no_word")),
+                }
+            }
+            Message::ReviewImport => {
+                if let Some(f) = rfd::FileDialog::new()
+                    .add_filter("TSV", &["tsv", "txt"])
+                    .pick_file() {
+                    let res = std::fs::read_to_string(&f)
+                        .map_err(ReaderError::from)
+                        .and_then(|tsv| crate::srs::import_tsv(&mut self.doc_conn, tsv.as_str(), chrono::Utc::now()));
+                    match res {
+                        Ok(n) => info!("Imported {} review cards from {:?}", n, f),
+                        Err(e) => {
+                            error!("Error importing review cards: {}", e);
+                            return modal!(e);
+                        }
+                    }
+                    return self.review_next();
+                }
+            }
+            Message::ReviewExport => {
+                if let Some(f) = rfd::FileDialog::new()
+                    .add_filter("TSV", &["tsv"])
+                    .set_file_name("review_cards.tsv")
+                    .save_file() {
+                    let res = crate::srs::export_tsv(&self.doc_conn)
+                        .and_then(|tsv| std::fs::write(f, tsv).map_err(ReaderError::from));
+                    if let Err(e) = res {
+                        error!("Error exporting review cards: {}", e);
+                        return modal!(e);
+                    }
+                }
+            }
+            Message::ClozeAdd => {
+                if let Some(note) = self.lookup_note() {
+                    match crate::cloze::queue(&self.doc_conn, &note, chrono::Utc::now()) {
+                        Ok(id) => {
+                            let added = iced::Task::done(Message::AppendResult(format!("\n\n*{}*\n", t!("cloze_added"))));
+                            return added.chain(self.translate_cloze(id, note.sentence));
+                        }
+                        Err(e) => {
+                            error!("Error queueing sentence card: {}", e);
+                            return modal!(e);
+                        }
+                    }
+                }
+            }
+            Message::ClozeQueue => {
+                self.load_cloze_cards();
+                self.state = AppState::ClozeQueue;
+            }
+            Message::ClozeTranslate(id) => {
+                if let Some(card) = self.cloze_cards.iter().find(|c| c.id == id) {
+                    let sentence = card.note.sentence.clone();
+                    return self.translate_cloze(id, sentence);
+                }
+            }
+            Message::ClozeTranslated(id, translation) => {
+                self.cloze_edited.remove(&id);
+                if let Err(e) = crate::cloze::set_translation(&self.doc_conn, id, translation.as_str()) {
+                    error!("Error saving translation: {}", e);
+                    return modal!(e);
+                }
+                self.load_cloze_cards();
+            }
+            Message::ClozeTranslationChanged(id, translation) => {
+                if let Some(card) = self.cloze_cards.iter_mut().find(|c| c.id == id) {
+                    card.note.translation = translation;
+                    self.cloze_edited.insert(id);
+                }
+            }
+            Message::ClozeTranslationSubmit => {
+                self.save_cloze_translations();
+            }
+            Message::ClozeRemove(id) => {
+                if let Err(e) = crate::cloze::remove(&mut self.doc_conn, &[id]) {
+                    return modal!(e);
+                }
+                self.load_cloze_cards();
+            }
+            Message::ClozeExport => {
+                if !self.cloze_cards.is_empty()
+                    && let Some(f) = rfd::FileDialog::new()
+                        .add_filter("TSV", &["tsv", "txt"])
+                        .set_file_name("sentence_cards.txt")
+                        .save_file() {
+                    let ids: Vec<i64> = self.cloze_cards.iter().map(|c| c.id).collect();
+                    let res = std::fs::write(&f, crate::cloze::to_tsv(&self.cloze_cards))
+                        .map_err(ReaderError::from)
+                        .and_then(|_| crate::cloze::remove(&mut self.doc_conn, &ids));
+                    if let Err(e) = res {
+                        error!("Error exporting sentence cards: {}", e);
+                        return modal!(e);
+                    }
+                    info!("Exported {} sentence cards to {:?}", ids.len(), f);
+                    self.load_cloze_cards();
+                }
+            }
+            Message::ClozeToAnki => {
+                let cards = self.cloze_cards.clone();
+                let conf = self.conf.anki_connect.clone();
+                return iced::Task::perform(async move {
+                    let anki = crate::anki::connect::AnkiConnect::new(&conf);
+                    let mut sent = vec![];
+                    for card in cards {
+                        if let Err(e) = anki.add_note(&card.note).await {
+                            return (sent, Some(format!("{}: {}", card.note.hanzi, e)));
+                        }
+                        sent.push(card.id);
+                    }
+                    (sent, None)
+                }, |(sent, error)| Message::ClozeSent(sent, error));
+            }
+            Message::ClozeSent(sent, error) => {
+                info!("{} sentence cards added to Anki", sent.len());
+                if let Err(e) = crate::cloze::remove(&mut self.doc_conn, &sent) {
+                    error!("Error removing sent cards: {}", e);
+                }
+                self.load_cloze_cards();
+                if let Some(e) = error {
+                    return modal!(e);
+                }
+            }
             Message::NewText => {
+                self.finish_reading();
                 self.text = Content::new();
                 self.loaded_text = crate::textbase::Document::default();
+                self.ocr_confidence.clear();
+                self.cues.clear();
                 self.documents = get_documents(&self.doc_conn)
                     .unwrap_or_default();
                 self.state = AppState::TextManage(TextOption::New);
@@ -1182,12 +2084,8 @@ impl App {
             }
             Message::TextMode(tm) => {
                 if tm == TextMode::Md {
-                    let text = self.text.text();
                     self.text_md = markdown::Content::new();
-                    for line in text.lines() {
-                        self.text_md.push_str(line);
-                        self.text_md.push_str("\n");
-                    }
+                    self.text_md.push_str(&doc_md(&self.text.text(), &self.notes));
                 }
                 self.text_mode = tm;
             }
@@ -1265,29 +2163,108 @@ impl App {
             }
             Message::NotesExport => {
                 if let Ok(result) = export_notes(&self.doc_conn, self.loaded_text.id)
-                    && let Some(f) = rfd::FileDialog::new().save_file() {
-                    use tokio::io::AsyncWriteExt; 
-                    return iced::Task::perform(async move {
-                        let mut file = tokio::fs::File::create(f).await;
-                        if let Ok(file) = file.as_mut() {
-                            file.write_all(result.as_bytes()).await
-                                .map_err(|e| ReaderError::Io(e.to_string()) )
-                        } else {
-                            Err(ReaderError::Io(file.unwrap_err().to_string()))
-                        }
-                    }, |res| {
-                        match res {
-                            Ok(_) => { Message::Void }
-                            Err(e) => {
-                                error!("{}", e);
-                                Message::ShowModal(e.to_string())
-                            }
-                        }
-                    });
+                    && let Some(f) = save_file_dialog(&self.loaded_text.title, "Markdown", "md") {
+                    return write_file(f, result);
+                }
+            }
+            Message::NoteBrowser => {
+                self.note_selected.clear();
+                self.load_note_entries();
+                self.documents = get_documents(&self.doc_conn)
+                    .unwrap_or_default();
+                self.state = AppState::NoteBrowser;
+            }
+            Message::NoteBrowserQuery(q) => {
+                self.note_hits = match crate::search::note_hits(&self.doc_conn, &q) {
+                    Ok(hits) => hits,
+                    Err(e) => {
+                        error!("Error searching notes: {}", e);
+                        HashSet::new()
+                    }
+                };
+                self.note_filter.query = q;
+            }
+            Message::NoteBrowserDocument(document) => {
+                self.note_filter.document = Some(document.id);
+            }
+            Message::NoteBrowserTagFilter(t) => {
+                self.note_filter.tag = Some(t);
+            }
+            Message::NoteBrowserFrom(d) => {
+                self.note_filter.from = chrono::NaiveDate::parse_from_str(d.trim(), "%Y-%m-%d").ok();
+                self.note_from = d;
+            }
+            Message::NoteBrowserTo(d) => {
+                self.note_filter.to = chrono::NaiveDate::parse_from_str(d.trim(), "%Y-%m-%d").ok();
+                self.note_to = d;
+            }
+            Message::NoteBrowserClearFilter => {
+                self.note_filter = NotesFilter::default();
+                self.note_hits.clear();
+                self.note_from.clear();
+                self.note_to.clear();
+            }
+            Message::NoteBrowserSelect(id, selected) => {
+                if selected {
+                    self.note_selected.insert(id);
+                } else {
+                    self.note_selected.remove(&id);
+                }
+            }
+            Message::NoteBrowserSelectAll(selected) => {
+                self.note_selected = if selected {
+                    crate::notes::view(&self.note_entries, &self.note_filter, &self.note_hits)
+                        .iter().map(|e| e.note.id).collect()
+                } else {
+                    HashSet::new()
+                };
+            }
+            Message::NoteBrowserTagChanged(t) => {
+                self.note_tag = t;
+            }
+            tagging @ (Message::NoteBrowserAddTag | Message::NoteBrowserRemoveTag) => {
+                let ids: Vec<u32> = self.note_selected.iter().copied().collect();
+                let res = match tagging {
+                    Message::NoteBrowserAddTag if !self.note_tag.trim().is_empty() => crate::notes::add_tag(&mut self.doc_conn, &ids, &self.note_tag),
+                    Message::NoteBrowserRemoveTag => crate::notes::remove_tag(&mut self.doc_conn, &ids, &self.note_tag),
+                    _ => Ok(()),
+                };
+                if let Err(e) = res {
+                    error!("Error tagging notes: {}", e);
+                    return modal!(e);
+                }
+                self.load_note_entries();
+            }
+            Message::NoteBrowserExport => {
+                let entries = crate::notes::view(&self.note_entries, &self.note_filter, &self.note_hits);
+                if !entries.is_empty()
+                    && let Some(f) = save_file_dialog("notes", "Markdown", "md") {
+                    return write_file(f, crate::notes::export(&entries));
                 }
             }
             Message::AnkiStats => {
                 self.state = AppState::AnkiStats;
+                self.char_stats = self.cedict.as_ref().map(|cd| cd.char_stats(&self.text.text()));
+                return self.load_stats();
+            }
+            Message::AnkiStatsDays(days) => {
+                self.stats_days = days;
+                return self.load_stats();
+            }
+            Message::AnkiStatsLoaded(stats) => {
+                self.stats_charts = self.stats_charts(&stats);
+                self.stats = Some(stats);
+            }
+            Message::AnkiStatsExport => {
+                if let Some(stats) = self.stats.as_ref()
+                    && let Some(f) = rfd::FileDialog::new()
+                        .add_filter("CSV", &["csv"])
+                        .set_file_name("anki_progress.csv")
+                        .save_file()
+                    && let Err(e) = std::fs::write(f, stats.to_csv()) {
+                    error!("Error exporting statistics: {}", e);
+                    return modal!(e);
+                }
             }
             Message::DeeplAsk => {
                 if let Some(text) = self.text.selection()
@@ -1310,22 +2287,749 @@ impl App {
                     });
                 }
             }
+            Message::Search => {
+                self.state = AppState::Search;
+            }
+            Message::SearchChanged(q) => {
+                self.search_query = q;
+            }
+            Message::SearchSubmit => {
+                match crate::search::search(&self.doc_conn, self.search_query.as_str()) {
+                    Ok(hits) => {
+                        self.search_hits = hits;
+                    }
+                    Err(e) => {
+                        error!("Search error: {}", e);
+                        return modal!(e);
+                    }
+                }
+            }
+            Message::SearchOpen { document, line, character } => {
+                let doc = match get_documents(&self.doc_conn) {
+                    Ok(documents) => documents.into_iter().find(|d| d.id == document),
+                    Err(e) => {
+                        error!("Error loading documents, {e}");
+                        return modal!(e);
+                    }
+                };
+                if let Some(doc) = doc {
+                    let task = self.load_document(doc);
+                    // Hits count characters, the editor counts bytes
+                    let column = self.text.line(line as usize)
+                        .map(|l| byte_column(&l.text, character.max(0) as usize))
+                        .unwrap_or(0);
+                    self.text.move_to(text_editor::Cursor {
+                        position: Position { line: line as usize, column },
+                        selection: None,
+                    });
+                    self.position = self.text.cursor().position;
+                    return task;
+                }
+            }
+            Message::KnowledgeMarks => {
+                self.knowledge_marks = !self.knowledge_marks;
+            }
+            Message::GrammarScan => {
+                self.scan_grammar();
+            }
+            Message::GrammarSelect(ix) => {
+                if let Some(m) = self.grammar_matches.get(ix) {
+                    self.text.move_to(text_editor::Cursor {
+                        position: Position { line: m.line, column: m.end },
+                        selection: Some(Position { line: m.line, column: m.start }),
+                    });
+                    self.position = self.text.cursor().position;
+                }
+            }
+            Message::GrammarPrompt(ix) => {
+                if let Some(m) = self.grammar_matches.get(ix) {
+                    let selection = m.text.clone();
+                    self.answer_raw = String::new();
+                    self.answer_text = markdown::Content::new();
+                    self.sidebar_mode = SidebarMode::AI;
+
+                    let question = t!("prompt_grammar").to_string();
+                    return self.do_prompt_with(question.as_str(), false, Some(selection));
+                }
+            }
             Message::DeeplLangSel(lang) => {
                 self.conf.deepl_lang = Some(lang);
             }
             Message::DeeplKeyChanged(key) => {
                 self.conf.keys.deepl = key;
             }
+            Message::PdfExport => {
+                self.state = AppState::PdfExport;
+            }
+            Message::PdfOptionsChanged(options) => {
+                self.pdf_options = options;
+            }
+            Message::PdfFontChanged(font) => {
+                self.conf.pdf_font = Some(font);
+            }
+            Message::Revisions => {
+                let doc = self.loaded_text.id;
+                let loaded = crate::revisions::revisions(&self.doc_conn, doc)
+                    .and_then(|revisions| Ok((revisions, get_content(&self.doc_conn, doc)?)));
+                match loaded {
+                    Ok((revisions, current)) => {
+                        self.revision_selected = revisions.first().map(|r| r.id);
+                        self.revisions = revisions;
+                        self.revision_current = current.unwrap_or_default();
+                        self.compare_revision();
+                        self.state = AppState::Revisions;
+                    }
+                    Err(e) => {
+                        error!("Error loading revisions: {}", e);
+                        return modal!(e);
+                    }
+                }
+            }
+            Message::RevisionSelect(id) => {
+                self.revision_selected = Some(id);
+                self.compare_revision();
+            }
+            Message::RevisionRestore(id) => {
+                // Edits not saved yet become a revision too
+                let text = self.text.text();
+                let saved = if text != self.revision_current {
+                    save_text(&mut self.doc_conn, self.loaded_text.id, &self.loaded_text.title, &text).map(|_| ())
+                } else {
+                    Ok(())
+                };
+                match saved.and_then(|_| crate::revisions::restore(&mut self.doc_conn, id)) {
+                    Ok(doc) => {
+                        info!("Revision {} of {} restored", id, doc);
+                        return self.load_document(self.loaded_text.clone());
+                    }
+                    Err(e) => {
+                        error!("Error restoring revision: {}", e);
+                        return modal!(e);
+                    }
+                }
+            }
+            Message::HtmlExport => {
+                if !self.loaded_text.is_empty()
+                    && let Some(f) = save_file_dialog(&self.loaded_text.title, "HTML", "html") {
+                    let notes = match get_notes(&self.doc_conn, self.loaded_text.id) {
+                        Ok(notes) => notes,
+                        Err(e) => {
+                            error!("Error loading notes: {}", e);
+                            return modal!(e);
+                        }
+                    };
+                    let html = crate::html::export(&self.loaded_text.title, &self.text.text(), self.cedict.as_deref(), &notes, &t!("html_pinyin"));
+                    return write_file(f, html);
+                }
+            }
+            export @ (Message::PdfSave | Message::PdfSaveNotes) => {
+                let notes = matches!(export, Message::PdfSaveNotes);
+                let name = if notes { format!("{}-notes", self.loaded_text.title) } else { self.loaded_text.title.clone() };
+                if let Some(f) = save_file_dialog(&name, "PDF", "pdf") {
+                    let doc = self.loaded_text.id;
+                    let options = self.pdf_options;
+                    let source = if notes {
+                        crate::pdf::PdfSource::notes(&self.doc_conn, doc)
+                    } else {
+                        crate::pdf::PdfSource::document(&self.doc_conn, doc, options)
+                    };
+                    let source = match source {
+                        Ok(source) => source,
+                        Err(e) => {
+                            error!("PDF export error: {}", e);
+                            return modal!(e);
+                        }
+                    };
+                    // Loading the font and laying out the pages takes a while
+                    let font = self.conf.pdf_font.clone();
+                    let cedict = self.cedict.clone();
+                    return iced::Task::perform(async move {
+                        tokio::task::spawn_blocking(move || {
+                            let font = crate::pdf::load_font(font.as_deref())?;
+                            let pdf = source.render(cedict.as_deref(), &font, options)?;
+                            std::fs::write(f, pdf)?;
+                            Ok::<_, ReaderError>(())
+                        }).await
+                    }, |r| match r {
+                        Ok(Ok(())) => Message::Void,
+                        Ok(Err(e)) => {
+                            error!("PDF export error: {}", e);
+                            Message::ShowModal(e.to_string())
+                        }
+                        Err(e) => Message::ShowModal(e.to_string()),
+                    });
+                }
+            }
             Message::Void => {}
             //_ => {},
         }
         iced::Task::none()
     }
 
+    fn load_document(&mut self, document: Doc) -> iced::Task<Message> {
+        match get_content(&self.doc_conn, document.id) {
+            Ok(Some(cc)) => {
+                if self.progress_changed.is_some()
+                    && let Err(e) = self.save_progress() {
+                    error!("Error updating progress {}", e);
+                }
+                self.finish_reading();
+                self.text = text_editor::Content::with_text(cc.as_str());
+                self.reading_line = None;
+                self.state = AppState::Default;
+                self.loaded_text = document;
+                self.move_to_saved();
+                self.restore_view();
+                match get_notes(&self.doc_conn, self.loaded_text.id) {
+                    Ok(notes) => {
+                        self.notes = notes;
+                        info!("Loaded {} notes", self.notes.len());
+                    }
+                    Err(e) => {
+                        error!("Error fetching notes: {}", e);
+                        return iced::Task::done(Message::ShowModal(format!("Error fetching note\n{}", e)));
+                    }
+                }
+                self.ocr_confidence = crate::import::confidence(&self.doc_conn, self.loaded_text.id)
+                    .unwrap_or_default();
+                self.cues = crate::import::cues(&self.doc_conn, self.loaded_text.id)
+                    .unwrap_or_default();
+                if let Err(e) = crate::library::set_opened(&self.doc_conn, self.loaded_text.id, chrono::Utc::now()) {
+                    error!("Error saving the opening date: {}", e);
+                }
+                self.scan_grammar();
+                if let Ok(None) = crate::library::get_difficulty(&self.doc_conn, self.loaded_text.id) {
+                    return self.estimate_difficulty(self.loaded_text.id, cc);
+                }
+            }
+            Ok(None) => {
+                return iced::Task::done(Message::ShowModal(format!("Text not found!")));
+            }
+            Err(e) => {
+                error!("Error retrieving document: {}", e);
+                return iced::Task::done(Message::ShowModal(format!("Error retrieving text\n{}", e)));
+            }
+        }
+        iced::Task::none()
+    }
+
+    /// Estimate the difficulty of a document in the background
+    fn estimate_difficulty(&self, id: u32, content: String) -> iced::Task<Message> {
+        let Some(cedict) = self.cedict.clone() else {
+            return iced::Task::none();
+        };
+        iced::Task::perform(async move {
+            tokio::task::spawn_blocking(move || cedict.difficulty(&content)).await
+        }, move |r| match r {
+            Ok(difficulty) => Message::LibraryEstimated(id, difficulty),
+            Err(e) => Message::ShowModal(e.to_string()),
+        })
+    }
+
+    /// Lines removed from the selected revision and added in the current text
+    fn compare_revision(&mut self) {
+        let selected = self.revisions.iter().find(|r| Some(r.id) == self.revision_selected);
+        let changes = selected.map(|r| crate::diff::diff(&r.content, &self.revision_current)).unwrap_or_default();
+        self.revision_diff = crate::revisions::with_context(&changes, 2).into_iter()
+            .map(|change| change.map(|c| c.map(|l| l.to_string())))
+            .collect();
+    }
+
+    /// Open the document that was open when the app was closed
+    fn restore_session(&mut self) -> iced::Task<Message> {
+        match crate::library::last_opened(&self.doc_conn) {
+            Ok(Some(id)) => {
+                if let Some(document) = self.documents.iter().find(|d| d.id == id).cloned() {
+                    info!("Restoring document {}", id);
+                    return self.load_document(document);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => error!("Error finding the last document: {}", e),
+        }
+        iced::Task::none()
+    }
+
+    /// Put the cursor where the reading was left, the editor scrolls to it
+    fn move_to_saved(&mut self) {
+        let line = (self.loaded_text.line.max(0) as usize).min(self.text.line_count().saturating_sub(1));
+        // The saved column is a byte column, the line may have changed since
+        let column = self.text.line(line)
+            .map(|l| byte_column(&l.text, char_column(&l.text, self.loaded_text.character.max(0) as usize)))
+            .unwrap_or_default();
+        self.text.move_to(text_editor::Cursor {
+            position: Position { line, column },
+            selection: None,
+        });
+        self.position = self.text.cursor().position;
+        self.last_save = (self.loaded_text.line, self.loaded_text.character);
+        self.progress_changed = None;
+    }
+
+    /// Put back the sidebar mode and the AI conversation the document was left with
+    fn restore_view(&mut self) {
+        let view = match crate::library::get_view(&self.doc_conn, self.loaded_text.id) {
+            Ok(view) => view.unwrap_or_default(),
+            Err(e) => {
+                error!("Error loading the view of the document: {}", e);
+                Default::default()
+            }
+        };
+        if let Some(mode) = SidebarMode::ALL.iter().find(|m| m.as_str() == view.sidebar) {
+            self.sidebar_mode = *mode;
+        }
+        self.chat_history = if view.chat.is_empty() {
+            vec![]
+        } else {
+            serde_json::from_str(&view.chat).unwrap_or_else(|e| {
+                error!("Error reading the conversation: {}", e);
+                vec![]
+            })
+        };
+        self.show_chat();
+    }
+
+    /// Show the conversation the way it was streamed
+    fn show_chat(&mut self) {
+        self.answer_text = markdown::Content::new();
+        self.answer_raw = String::new();
+        for (ix, msg) in self.chat_history.iter().enumerate() {
+            match msg {
+                Rmsg::User { content } => {
+                    for c in content.iter() {
+                        if let UserContent::Text(text) = c {
+                            self.answer_text.push_str(&format!(">{}\n\n", text.text));
+                        }
+                    }
+                }
+                Rmsg::Assistant { content, .. } => {
+                    let answer = content.iter().map(ac_to_str).collect::<String>();
+                    self.answer_text.push_str(&answer);
+                    self.answer_text.push_str(&answer_footer(ix));
+                    self.answer_raw = answer;
+                }
+                Rmsg::System { .. } => {}
+            }
+        }
+    }
+
+    /// Save the position and view after a quiet moment
+    fn schedule_save(&mut self) {
+        if self.loaded_text.id > 0 {
+            self.progress_changed = Some(std::time::Instant::now());
+        }
+    }
+
+    /// Store the cursor position of the open document, with its sidebar mode and conversation
+    fn save_progress(&mut self) -> crate::error::ReaderResult<()> {
+        self.progress_changed = None;
+        if self.loaded_text.id == 0 {
+            return Ok(());
+        }
+        let c = self.text.cursor().position;
+        let line = c.line as i64;
+        let character = c.column as i64;
+
+        self.loaded_text.line = line;
+        self.loaded_text.character = character;
+        if let Some(document) = self.documents.iter_mut().find(|d| d.id == self.loaded_text.id) {
+            document.line = line;
+            document.character = character;
+        }
+        update_progress(&mut self.doc_conn, self.loaded_text.id as i64, character, line)?;
+        self.last_save = (line, character);
+
+        let view = crate::library::ViewState {
+            sidebar: self.sidebar_mode.to_string(),
+            chat: serde_json::to_string(&self.chat_history)?,
+        };
+        crate::library::save_view(&self.doc_conn, self.loaded_text.id, &view)
+    }
+
+    /// Refresh the library and open the first document of an import
+    fn open_imported(&mut self, imported: &crate::import::Imported) -> iced::Task<Message> {
+        self.documents = get_documents(&self.doc_conn)
+            .unwrap_or_default();
+        let done = iced::Task::done(Message::ShowModal(t!("import_done", added = imported.added, updated = imported.updated).to_string()));
+        let estimates: Vec<_> = imported.ids.iter()
+            .filter_map(|id| get_content(&self.doc_conn, *id as u32).ok().flatten().map(|content| (*id as u32, content)))
+            .map(|(id, content)| self.estimate_difficulty(id, content))
+            .collect();
+        let first = imported.ids.first()
+            .and_then(|id| self.documents.iter().find(|d| d.id as i64 == *id))
+            .cloned();
+        let opened = match first {
+            Some(document) => self.load_document(document).chain(done),
+            None => done,
+        };
+        iced::Task::batch([opened, iced::Task::batch(estimates)])
+    }
+
+    /// Sentence at the start of the selection, or at the cursor
+    fn cursor_sentence(&self) -> String {
+        let cursor = self.text.cursor();
+        let start = match cursor.selection {
+            Some(s) if (s.line, s.column) < (cursor.position.line, cursor.position.column) => s,
+            _ => cursor.position,
+        };
+        self.text.line(start.line)
+            .and_then(|l| sentence_at(&l.text, start.column).map(|(_, s)| s.trim().to_string()))
+            .unwrap_or_default()
+    }
+
+    /// Note of the word looked up in the dictionary, with the sentence at the cursor
+    fn lookup_note(&self) -> Option<crate::anki::connect::NoteData> {
+        self.lookup.as_ref().map(|entry| crate::anki::connect::NoteData {
+            hanzi: entry.simplified().to_string(),
+            pinyin: entry.pinyin().to_string(),
+            meaning: entry.gloss(),
+            sentence: self.cursor_sentence(),
+            source: self.loaded_text.title.clone(),
+            ..Default::default()
+        })
+    }
+
+    /// The dictionary word a note is attached to, ending at or covering its position.
+    /// The note text goes with the meaning
+    fn note_data(&self, note: &Note) -> Option<crate::anki::connect::NoteData> {
+        let cedict = self.cedict.as_ref()?;
+        let line = self.text.line(note.line as usize)?;
+        // The end of a highlight on one line, the position of other notes
+        let column = if note.is_range() && note.end_line == note.line { note.end_char } else { note.char };
+        let pos = crate::anchor::byte_offset(&line.text, 0, column as usize);
+        let mut start = 0;
+        let words: Vec<(usize, usize, &Entry)> = cedict.segment(&line.text).into_iter()
+            .filter_map(|(w, e)| {
+                let s = start;
+                start += w.len();
+                e.map(|e| (s, start, e))
+            })
+            .collect();
+        let (_, _, entry) = words.iter().find(|(s, e, _)| *s < pos && pos <= *e)
+            .or_else(|| words.iter().find(|(s, e, _)| *s <= pos && pos < *e))?;
+        let meaning = [entry.gloss(), note.text.trim().to_string()].into_iter()
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+        Some(crate::anki::connect::NoteData {
+            hanzi: entry.simplified().to_string(),
+            pinyin: entry.pinyin().to_string(),
+            meaning,
+            sentence: sentence_at(&line.text, pos.saturating_sub(1)).map(|(_, s)| s.trim().to_string()).unwrap_or_default(),
+            source: self.loaded_text.title.clone(),
+            ..Default::default()
+        })
+    }
+
+    fn add_review_cards(&mut self, notes: &[crate::anki::connect::NoteData]) -> iced::Task<Message> {
+        match crate::srs::add_cards(&mut self.doc_conn, notes, chrono::Utc::now()) {
+            Ok(n) => {
+                info!("{} review cards added", n);
+                iced::Task::done(Message::AppendResult(format!("\n\n*{}*\n", t!("review_added", n = n))))
+            }
+            Err(e) => {
+                error!("Error adding review cards: {}", e);
+                modal!(e)
+            }
+        }
+    }
+
+    /// Line and character column of an editor position, whose column counts bytes
+    fn char_position(&self, position: Position) -> (i64, i64) {
+        let column = self.text.line(position.line)
+            .map(|l| char_column(&l.text, position.column))
+            .unwrap_or(position.column);
+        (position.line as i64, column as i64)
+    }
+
+    /// Characters before the cursor in the text. The lines before the cursor are only
+    /// counted again when it moves to another line or the text is edited
+    fn cursor_offset(&mut self) -> i64 {
+        let position = self.text.cursor().position;
+        let before = match self.reading_line {
+            Some((line, before)) if line == position.line => before,
+            _ => {
+                let before = (0..position.line)
+                    .filter_map(|ix| self.text.line(ix).map(|l| l.text.chars().count() + 1))
+                    .sum();
+                self.reading_line = Some((position.line, before));
+                before
+            }
+        };
+        let column = self.text.line(position.line)
+            .map(|l| char_column(&l.text, position.column))
+            .unwrap_or(0);
+        (before + column) as i64
+    }
+
+    /// Count the activity in the session of the open document, starting a new session
+    /// after a long pause
+    fn track_reading(&mut self) {
+        if self.loaded_text.id == 0 {
+            return;
+        }
+        let now = chrono::Utc::now();
+        let offset = self.cursor_offset();
+        let id = self.loaded_text.id;
+        if let Some(tracker) = self.reading.as_mut()
+            && tracker.session.document == id && !tracker.is_over(now) {
+            tracker.moved(offset, now);
+        } else {
+            self.finish_reading();
+            self.reading = Some(Tracker::new(id, offset, now));
+        }
+    }
+
+    fn save_reading(&mut self) {
+        if let Some(tracker) = self.reading.as_mut()
+            && !tracker.session.is_empty()
+            && let Err(e) = crate::reading::save_session(&self.doc_conn, &mut tracker.session) {
+            error!("Error saving the reading session: {}", e);
+        }
+    }
+
+    fn finish_reading(&mut self) {
+        self.save_reading();
+        self.reading = None;
+    }
+
+    fn load_reading_stats(&mut self) {
+        match crate::reading::sessions(&self.doc_conn) {
+            Ok(sessions) => {
+                let to = chrono::Local::now().date_naive();
+                let from = to - chrono::Days::new(self.reading_days - 1);
+                let stats = ReadingStats::new(&sessions, from, to);
+                self.reading_charts = self.reading_charts(&stats);
+                self.reading_stats = Some(stats);
+                self.reading_sessions = sessions;
+            }
+            Err(e) => error!("Error loading reading sessions: {}", e),
+        }
+    }
+
+    fn reading_charts(&self, stats: &ReadingStats) -> Vec<iced::widget::svg::Handle> {
+        let palette = self.theme().palette();
+        let days: Vec<String> = stats.days.iter().map(|d| d.day.to_string()).collect();
+        let weeks: Vec<String> = stats.weeks.iter().map(|w| w.day.to_string()).collect();
+        let minutes: Vec<f64> = stats.days.iter().map(|d| d.minutes().round()).collect();
+        let week_minutes: Vec<f64> = stats.weeks.iter().map(|w| w.minutes().round()).collect();
+        let chars: Vec<f64> = stats.days.iter().map(|d| d.chars as f64).collect();
+        vec![
+            chart::bars(&t!("reading_minutes_day"), &days, &minutes, palette.primary, palette.text),
+            chart::bars(&t!("reading_minutes_week"), &weeks, &week_minutes, palette.primary, palette.text),
+            chart::bars(&t!("reading_chars_day"), &days, &chars, palette.success, palette.text),
+        ]
+    }
+
+    /// Reload the library and the list of texts after a change
+    fn load_library(&mut self) {
+        match crate::library::get_library(&self.doc_conn) {
+            Ok(library) => {
+                self.library_selected.retain(|id| library.iter().any(|d| d.id == *id));
+                self.library = library;
+            }
+            Err(e) => error!("Error loading the library: {}", e),
+        }
+        self.documents = get_documents(&self.doc_conn)
+            .unwrap_or_default();
+    }
+
+    fn load_note_entries(&mut self) {
+        match crate::notes::get_entries(&self.doc_conn, None) {
+            Ok(entries) => {
+                self.note_selected.retain(|id| entries.iter().any(|e| e.note.id == *id));
+                self.note_entries = entries;
+            }
+            Err(e) => error!("Error loading notes: {}", e),
+        }
+    }
+
+    /// Edited translations are saved before the cards are read again
+    fn load_cloze_cards(&mut self) {
+        self.save_cloze_translations();
+        match crate::cloze::queued(&self.doc_conn) {
+            Ok(cards) => self.cloze_cards = cards,
+            Err(e) => error!("Error loading sentence cards: {}", e),
+        }
+    }
+
+    fn save_cloze_translations(&mut self) {
+        for id in self.cloze_edited.drain() {
+            if let Some(card) = self.cloze_cards.iter().find(|c| c.id == id)
+                && let Err(e) = crate::cloze::set_translation(&self.doc_conn, id, card.note.translation.as_str()) {
+                error!("Error saving translation: {}", e);
+            }
+        }
+    }
+
+    /// Translate the sentence of a queued card with DeepL when it is set up, else with the AI chat
+    fn translate_cloze(&mut self, id: i64, sentence: String) -> iced::Task<Message> {
+        if sentence.is_empty() {
+            return iced::Task::none();
+        }
+        if let Some(lang) = self.conf.deepl_lang.clone()
+            && !self.conf.keys.deepl.is_empty() {
+            let key = self.conf.keys.deepl.clone();
+            return iced::Task::perform(async move {
+                crate::ai::deepl::ask_deepl(sentence.as_str(), lang, key.as_str()).await
+            }, move |r| {
+                match r {
+                    Ok(translation) => Message::ClozeTranslated(id, translation),
+                    Err(e) => Message::ShowModal(e.to_string()),
+                }
+            });
+        }
+        if self.sender.is_none() {
+            return iced::Task::none();
+        }
+        self.cloze_pending = Some(id);
+        self.answer_raw = String::new();
+        self.answer_text = markdown::Content::new();
+        let question = t!("prompt_translate").to_string();
+        self.do_prompt_with(question.as_str(), false, Some(sentence))
+    }
+
+    /// Show the card due the longest and refresh the counts
+    fn review_next(&mut self) -> iced::Task<Message> {
+        let now = chrono::Utc::now();
+        let today = now.with_timezone(&chrono::Local).date_naive()
+            .and_time(chrono::NaiveTime::MIN)
+            .and_local_timezone(chrono::Local)
+            .earliest()
+            .map(|d| d.with_timezone(&chrono::Utc))
+            .unwrap_or(now);
+        let conn = &self.doc_conn;
+        let res = crate::srs::next_due(conn, now).and_then(|card| {
+            Ok((card, crate::srs::count_cards(conn, now)?, crate::srs::count_reviews(conn, today)?,
+                crate::srs::history(conn, REVIEW_HISTORY)?))
+        });
+        match res {
+            Ok((card, counts, today, history)) => {
+                self.review_card = card;
+                self.review_shown = false;
+                self.review_counts = counts;
+                self.review_today = today;
+                self.review_history = history;
+                iced::Task::none()
+            }
+            Err(e) => {
+                error!("Error loading review cards: {}", e);
+                modal!(e)
+            }
+        }
+    }
+
+    /// Notes for the words of the export list, with the first sentence of the text using them
+    fn apkg_notes(&self) -> Vec<crate::anki::connect::NoteData> {
+        let Some(cedict) = self.cedict.as_ref() else {
+            return vec![];
+        };
+        let text = self.text.text();
+        self.apkg_words.text().lines()
+            .map(|w| w.trim())
+            .filter(|w| !w.is_empty())
+            .map(|w| {
+                let entry = cedict.find(w).into_iter().find(|e| e.simplified() == w);
+                let sentence = text.lines()
+                    .flat_map(sentences)
+                    .find(|(_, s)| s.contains(w))
+                    .map(|(_, s)| s.trim().to_string())
+                    .unwrap_or_default();
+                crate::anki::connect::NoteData {
+                    hanzi: w.to_string(),
+                    pinyin: entry.map(|e| e.pinyin().to_string()).unwrap_or_default(),
+                    meaning: entry.map(|e| e.gloss()).unwrap_or_default(),
+                    sentence,
+                    source: self.loaded_text.title.clone(),
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+
+    /// Progress of the last `stats_days` days, read from a snapshot of the collection
+    fn load_stats(&self) -> iced::Task<Message> {
+        let Some(cedict) = self.cedict.clone() else {
+            return iced::Task::none();
+        };
+        let Some(path) = cedict.anki_path().cloned() else {
+            return iced::Task::none();
+        };
+        let anki_conf = self.conf.anki_notes.clone();
+        let days = self.stats_days;
+        iced::Task::perform(async move {
+            tokio::task::spawn_blocking(move || -> crate::error::ReaderResult<Stats> {
+                let snapshot = crate::anki::collection::snapshot(&path)?;
+                let entries = crate::anki::last_n_days(snapshot.conn(), &anki_conf, days)?;
+                let recent: std::collections::HashSet<&str> = entries.iter().map(|e| e.word.as_str()).collect();
+                let before = cedict.anki_words().filter(|w| !recent.contains(w));
+                let to = chrono::Utc::now().date_naive();
+                let from = to - chrono::Days::new(days);
+                Ok(Stats::new(&entries, before, |w| cedict.hsk(w), &crate::cedict::HSK_TOTAL, from, to))
+            }).await?
+        }, |r| {
+            match r {
+                Ok(stats) => Message::AnkiStatsLoaded(Arc::new(stats)),
+                Err(e) => Message::ShowModal(e.to_string()),
+            }
+        })
+    }
+
+    fn stats_charts(&self, stats: &Stats) -> Vec<iced::widget::svg::Handle> {
+        let palette = self.theme().palette();
+        let days: Vec<String> = stats.days.iter().map(|d| d.day.to_string()).collect();
+        let weeks: Vec<String> = stats.weeks.iter().map(|(w, _)| w.to_string()).collect();
+        let per_day: Vec<f64> = stats.days.iter().map(|d| d.words as f64).collect();
+        let per_week: Vec<f64> = stats.weeks.iter().map(|(_, n)| *n as f64).collect();
+        let names: Vec<String> = (1..=crate::anki::stats::HSK_LEVELS).map(|l| format!("HSK{}", l)).collect();
+        let coverage: Vec<chart::Series> = names.iter().enumerate()
+            .map(|(ix, name)| chart::Series {
+                name: name.as_str(),
+                values: stats.days.iter().map(|d| 100.0 * d.hsk[ix] as f64 / crate::cedict::HSK_TOTAL[ix] as f64).collect(),
+                color: chart::COLORS[ix % chart::COLORS.len()],
+            })
+            .collect();
+        let (words, chars) = (t!("stats_words").to_string(), t!("stats_chars").to_string());
+        let known = [
+            chart::Series { name: words.as_str(), values: stats.days.iter().map(|d| d.total_words as f64).collect(), color: palette.primary },
+            chart::Series { name: chars.as_str(), values: stats.days.iter().map(|d| d.total_chars as f64).collect(), color: palette.success },
+        ];
+        vec![
+            chart::bars(&t!("stats_per_day"), &days, &per_day, palette.primary, palette.text),
+            chart::bars(&t!("stats_per_week"), &weeks, &per_week, palette.primary, palette.text),
+            chart::lines(&t!("stats_coverage"), &days, &coverage, palette.text),
+            chart::lines(&t!("stats_known"), &days, &known, palette.text),
+        ]
+    }
+
+    /// Read the Anki collection again in the background
+    fn reload_anki(&self, cedict: Arc<Cedict>) -> iced::Task<Message> {
+        let anki_fname = self.conf.anki.clone();
+        let anki_conf = self.conf.anki_notes.clone();
+        iced::Task::perform(async move {
+            tokio::task::spawn_blocking(move || cedict.reload_anki(&anki_fname, &anki_conf)).await
+        }, |r| {
+            match r {
+                Ok(cedict) => Message::AnkiReloaded(Arc::new(cedict)),
+                Err(e) => Message::ShowModal(e.to_string()),
+            }
+        })
+    }
+
+    fn scan_grammar(&mut self) {
+        self.grammar_matches = self.grammar.find(self.text.text().as_str());
+        debug!("Grammar matches: {}", self.grammar_matches.len());
+    }
+
     fn do_prompt(&mut self, question: &str, with_text: bool) -> iced::Task<Message> {
-        if self.text.selection().is_some() || self.image_include {
+        let selection = self.text.selection();
+        self.do_prompt_with(question, with_text, selection)
+    }
+
+    fn do_prompt_with(&mut self, question: &str, with_text: bool, selection: Option<String>) -> iced::Task<Message> {
+        if selection.is_some() || self.image_include {
             if let Some(sender) = self.sender.clone() {
-                let selection = self.text.selection().unwrap_or_default();
+                let selection = selection.unwrap_or_default();
                 let question = question.replace("@sel", selection.as_str());
                 let prompt = rig::message::Text::from(question.as_str());
                 let line = self.text.cursor().position.line;
@@ -1384,6 +3088,35 @@ impl App {
     }
 }
 
+/// Ask where to save an exported file, `filter` names its type
+fn save_file_dialog(name: &str, filter: &str, extension: &str) -> Option<PathBuf> {
+    rfd::FileDialog::new()
+        .add_filter(filter, &[extension])
+        .set_file_name(format!("{}.{}", name, extension))
+        .save_file()
+}
+
+fn write_file(f: PathBuf, content: impl AsRef<[u8]> + Send + 'static) -> iced::Task<Message> {
+    use tokio::io::AsyncWriteExt;
+    iced::Task::perform(async move {
+        let mut file = tokio::fs::File::create(f).await;
+        if let Ok(file) = file.as_mut() {
+            file.write_all(content.as_ref()).await
+                .map_err(|e| ReaderError::Io(e.to_string()) )
+        } else {
+            Err(ReaderError::Io(file.unwrap_err().to_string()))
+        }
+    }, |res| {
+        match res {
+            Ok(_) => { Message::Void }
+            Err(e) => {
+                error!("{}", e);
+                Message::ShowModal(e.to_string())
+            }
+        }
+    })
+}
+
 fn get_path() -> String {
     match dirs::config_dir() {
         Some(pb) => format!("{}/cnreader/models", pb.to_str().unwrap()),
@@ -1,10 +1,12 @@
 use iced::advanced::text::highlighter::PlainText;
 use iced::widget::{Column, Row, TextEditor, button, checkbox, column, container, markdown, pick_list, progress_bar, row, scrollable, slider, space, table, text, text_editor, text_input, tooltip};
 use iced::{Alignment, Element, Font, Padding, Renderer, Theme};
+use crate::anchor::NoteColor;
 use crate::cedict::HSK_TOTAL;
-use crate::config::{Provider, Window};
+use crate::config::{AnkiConnectField, AnkiNotesField, Provider, Window};
 use crate::utils::get_models;
 use super::message::Message;
+use super::highlight::{Highlight, ReaderHighlighter};
 use super::SidebarMode;
 use super::TextOption;
 use std::path::Path;
@@ -65,8 +67,12 @@ pub fn notes<'a>(app: &'a super::App) -> Column<'a,Message> {
     let columns = [
         table::column(bold(t!("line")), |note: &crate::textbase::Note| text(note.line)  ),
         table::column(bold(t!("column")), |note: &crate::textbase::Note| text(note.char)  ),
-        table::column(bold(t!("text")), |note: &crate::textbase::Note| text(note.text.as_str())  ).width(1000.0),
+        table::column(bold(t!("note_quote")), |note: &crate::textbase::Note| text(
+            if note.is_range() { format!("{} {}", note.color.marker(), note.anchor.quote) } else { String::new() }) ).width(250.0),
+        table::column(bold(t!("text")), |note: &crate::textbase::Note| text(note.text.as_str())  ).width(750.0),
         table::column( "", |note: &crate::textbase::Note| button_nf!("\u{f0c5}").on_press(Message::NotesCopy(note.text.clone())) ),
+        table::column( "", |note: &crate::textbase::Note| tooltip(button_nf!("\u{f0638}").on_press(Message::ReviewAddNote(note.clone())),
+            text(t!("review_add")), tooltip::Position::Bottom) ),
         table::column( "", |note: &crate::textbase::Note| button_nf!("\u{f01b4}").on_press(Message::NotesDelete { document: note.doc, line: note.line , character: note.char } ) ),
     ];
     let idc_list = table(columns ,&app.notes).padding(5.0).separator(1.0);
@@ -101,9 +107,8 @@ pub fn sidebar<'a>(app: &'a super::App) -> Column<'a, Message, Theme> {
 
     match sm {
         SidebarMode::Notes => {
-            let c = app.text.cursor().position;
-            let pos = (c.line as i64, c.column as i64);
-            let note = app.notes.iter().find(|n| n.line == pos.0 && n.char == pos.1);
+            let pos = app.char_position(app.text.cursor().position);
+            let note = app.notes.iter().find(|n| n.contains(pos.0, pos.1));
 
             let a_delete = note.map(|_| Message::RemoveNote);
             let a_save = if pos.0 > 0 || pos.1 > 0 { Some(Message::SaveNote) } else { None };
@@ -111,12 +116,18 @@ pub fn sidebar<'a>(app: &'a super::App) -> Column<'a, Message, Theme> {
             let idc_delete_note = button( text(t!("delete_note"))).on_press_maybe(a_delete);
             let idc_save_note = button( text(t!("save")) ).on_press_maybe(a_save);
             let idr_note = row![ idc_delete_note, idc_save_note ].spacing(win.spacing).padding(win.padding);
+            let current = note.filter(|n| n.is_range()).map(|n| n.color).unwrap_or(app.note_color);
+            let idr_color = NoteColor::ALL.iter().fold(row![text(t!("note_color"))].spacing(win.spacing).align_y(Alignment::Center), |r, c| {
+                r.push(button(text(c.marker()))
+                    .style(if *c == current { button::primary } else { button::text })
+                    .on_press(Message::NoteColor(*c)))
+            });
             let idc_notes: TextEditor<PlainText,Message,Theme> = text_editor( &app.sidebar_notes )
                 .placeholder( t!("notes_placeholder") )
                 .on_action(Message::NotesAction)
                 .height(500.0);
             
-            column![id_mode, idc_notes, space::vertical(), idr_note].spacing(win.spacing).padding(win.padding_frame).align_x(iced::Alignment::Center)
+            column![id_mode, idc_notes, idr_color, space::vertical(), idr_note].spacing(win.spacing).padding(win.padding_frame).align_x(iced::Alignment::Center)
         }
         SidebarMode::AI => {
             let idr_prompts = ai_prompter(app);
@@ -146,10 +157,45 @@ pub fn sidebar<'a>(app: &'a super::App) -> Column<'a, Message, Theme> {
             let idc_to_notes = button(text(t!("to_notes")))
                 .on_press(Message::DictionaryToNotes);
             let idc_dtn = checkbox(app.dtn_append).on_toggle(Message::DictionaryToNotesAppend);
-            let idr_buttons = row![idc_copy, idc_to_notes, idc_dtn, text_nf!("\u{f4d0}")].padding(win.padding).spacing(win.spacing);
+            let idc_to_anki = button(text(t!("to_anki")))
+                .on_press_maybe(app.lookup.as_ref().map(|_| Message::AnkiAddNote));
+            let idc_to_review = tooltip(button_nf!("\u{f0638}").on_press_maybe(app.lookup.as_ref().map(|_| Message::ReviewAddLookup)),
+                text(t!("review_add")), tooltip::Position::Bottom);
+            let idc_to_cloze = tooltip(button_nf!("\u{f0a3c}").on_press_maybe(app.lookup.as_ref().map(|_| Message::ClozeAdd)),
+                container(text(t!("cloze_add"))).padding(5.0).style(container::rounded_box), tooltip::Position::FollowCursor);
+            let idr_buttons = row![idc_copy, idc_to_notes, idc_dtn, text_nf!("\u{f4d0}"), idc_to_anki, idc_to_review, idc_to_cloze].padding(win.padding).spacing(win.spacing);
             
             column![id_mode, idc_result, space::vertical(), idr_buttons].padding(win.padding_frame).align_x(iced::Alignment::Center)
         }
+        SidebarMode::Grammar => {
+            let grammar = &app.grammar;
+            let idc_matches = app.grammar_matches.iter().enumerate()
+                .fold(Column::new().spacing(win.spacing), |col, (ix, m)| {
+                    let (name, description, link) = match grammar.get(m.pattern) {
+                        Some(p) => (p.name.as_str(), p.description.as_str(), p.link.clone()),
+                        None => ("?", "", String::new()),
+                    };
+                    col.push(row![
+                        text(format!("{}: {}", m.line, m.text)).shaping(text::Shaping::Advanced).width(170.0),
+                        tooltip(
+                            text(name).shaping(text::Shaping::Advanced).width(110.0),
+                            container(text(description).shaping(text::Shaping::Advanced)).padding(5.0).style(container::rounded_box),
+                            tooltip::Position::FollowCursor),
+                        button_nf!("\u{f0489}").on_press(Message::GrammarSelect(ix)),
+                        button_nf!("\u{f0339}").on_press_maybe(if link.is_empty() { None } else { Some(Message::LinkClicked(link)) }),
+                        button_nf!("\u{f0eb4}").on_press(Message::GrammarPrompt(ix)),
+                    ].spacing(win.spacing).align_y(Alignment::Center))
+                });
+            let idc_list = scrollable(idc_matches)
+                .height(640.0)
+                .width(420.0);
+            let idc_scan = button(text(t!("grammar_scan")))
+                .on_press(Message::GrammarScan);
+            let idr_buttons = row![idc_scan, text(format!("{}: {}", t!("grammar_matches"), app.grammar_matches.len()))]
+                .padding(win.padding).spacing(win.spacing).align_y(Alignment::Center);
+
+            column![id_mode, idc_list, space::vertical(), idr_buttons].padding(win.padding_frame).align_x(iced::Alignment::Center)
+        }
     }
 }
 
@@ -184,36 +230,61 @@ pub fn default<'a>(app: &'a super::App) -> Row<'a, Message> {
         None
     };
     let idc_anki_db = button_nf!("\u{f1c0}").on_press_maybe(anki_acc);
+    let idc_search = button_nft!("\u{f002}", t!("search"), Search);
+    let idc_apkg = button_nft!("\u{f0e8d}", t!("apkg_export"), ApkgExport);
+    let idc_pdf = button_nft!("\u{f0226}", t!("pdf_export"), PdfExport);
+    let idc_html = button_nft!("\u{f13b}", t!("html_export"), HtmlExport);
+    let idc_revisions = button_nft!("\u{f02da}", t!("revisions"), Revisions);
+    let idc_review = button_nft!("\u{f0638}", t!("review"), Review);
+    let idc_cloze = button_nft!("\u{f0a3c}", t!("cloze_queue"), ClozeQueue);
+    let idc_library = button_nft!("\u{f1ec}", t!("library"), Library);
+    let idc_reading = button_nft!("\u{f0150}", t!("reading_stats"), ReadingStats);
+    let idc_note_browser = button_nft!("\u{f082e}", t!("note_browser"), NoteBrowser);
     let idc_deepl = button_nf!("\u{f05ca}").on_press(Message::DeeplAsk);
     
     let idr_left_top = row![
         idc_settings,
         idc_settings_ai, 
         idc_load, 
+        idc_library,
         idc_save, 
         idc_img_load, 
         idc_img_file, 
         idc_img_clear, 
         idc_ocr,
         idc_notes,
+        idc_note_browser,
         idc_anki_db,
+        idc_search,
+        idc_apkg,
+        idc_pdf,
+        idc_html,
+        idc_revisions,
+        idc_review,
+        idc_cloze,
+        idc_reading,
     ].padding(win.padding_frame).spacing(win.spacing);
     let title = format!("{} | {},{}", app.loaded_text.title, app.loaded_text.line, app.loaded_text.character);
 
     let idc_title = row![
         text(title).align_y(iced::Alignment::Center).shaping(text::Shaping::Advanced),
         space::horizontal(),
-        
+        button_nft!("\u{f0eb}", t!("anki_marks"), KnowledgeMarks),
         button_nf!("\u{f09a8}").on_press(Message::TextMode(crate::gui::TextMode::Raw)),
         button_nf!("\u{f126f}").on_press(Message::TextMode(crate::gui::TextMode::Md)),
     ].padding(win.padding).spacing(win.spacing);
 
+    let highlight = super::highlight::Settings {
+        grammar: if app.sidebar_mode == SidebarMode::Grammar { Some(app.grammar.clone()) } else { None },
+        knowledge: if app.knowledge_marks { app.cedict.clone() } else { None },
+    };
     let idc_text: Element<'_, Message> = match app.text_mode {
             super::TextMode::Raw => text_editor( &app.text )
                 .placeholder("")
                 .on_action(Message::EditAction)
                 .height(win.height*0.70)
                 .size(win.font_size.unwrap_or(18.0))
+                .highlight_with::<ReaderHighlighter>(highlight, Highlight::to_format)
                 .into(),
             super::TextMode::Md => scrollable(markdown::view(app.text_md.items(), app.theme())
                 .map(Message::LinkClicked)
@@ -234,7 +305,21 @@ pub fn default<'a>(app: &'a super::App) -> Row<'a, Message> {
         text(format!("({},{}) | {} | text: {}", line, column,
             if is_img { "img" } else { "" },
             if app.loaded_text.id > 0 { text_id } else { String::new() } )),
+        text(app.ocr_confidence.get(&(line as i64))
+            .map(|c| format!(" | OCR {:.0}%", c * 100.0))
+            .unwrap_or_default()),
     ];
+    let cue = app.cues.iter().rev().find(|c| c.line <= line as i64);
+    let idr_status = if app.cues.is_empty() {
+        idr_status
+    } else {
+        idr_status
+            .push(text(cue.map(|c| format!(" | {} → {} ", crate::import::subtitle::format_time(c.start), crate::import::subtitle::format_time(c.end))).unwrap_or_default()))
+            .push(button_nft!("\u{f0141}", t!("cue_prev"), CuePrev))
+            .push(button_nft!("\u{f0142}", t!("cue_next"), CueNext))
+            .spacing(win.spacing)
+            .align_y(Alignment::Center)
+    };
 
     let idc_sidebar = sidebar(app);
 
@@ -266,10 +351,17 @@ pub fn text_action<'a>(app: &'a super::App, option: TextOption) -> Column<'a, Me
                 .on_press(Message::SaveText);
 
             let idr_buttons = row![idc_load_doc, idc_delete, idc_new, idc_close, idc_save].padding(win.padding).spacing(win.spacing);
+            let idc_epub = button_nft!("\u{f02d}", t!("import_epub"), ImportEpub);
+            let idc_pdf = button_nft!("\u{f0226}", t!("import_pdf"), ImportPdf);
+            let idc_subtitles = button_nft!("\u{f0a16}", t!("import_subtitles"), ImportSubtitles);
+            let idc_txt = button_nft!("\u{f0219}", t!("import_txt"), ImportTxt);
+            let idr_import = row![idc_txt, idc_epub, idc_pdf, idc_subtitles, checkbox(app.import_split).on_toggle(Message::ImportSplit), text(t!("import_chapters"))]
+                .padding(win.padding).spacing(win.spacing).align_y(Alignment::Center);
             column![
                 row![ids_load],
                 idr_select,
                 idr_buttons,
+                idr_import,
             ].padding(conf.window.padding_frame)
                 .align_x(iced::Alignment::Center)
         }
@@ -349,7 +441,43 @@ pub fn settings<'a>(app: &super::App) -> Element<'a, Message> {
     let ids_anki = text("Anki db").width(win.settings_label_w);
     let anki = app.conf.anki.clone().unwrap_or_default();
     let idc_anki = text_input("", anki.as_str()).on_input(Message::AnkiChanged);
-    let idr_anki = row![ids_anki, idc_anki].padding(win.padding).spacing(win.spacing);
+    let idc_profiles = pick_list(app.anki_profiles.clone(), None::<String>, Message::AnkiChanged).placeholder(t!("anki_profiles"));
+    let idc_anki_reload = button_nft!("\u{f0450}", t!("anki_reload"), AnkiReload);
+    let idr_anki = row![ids_anki, idc_anki, idc_profiles, idc_anki_reload].padding(win.padding).spacing(win.spacing);
+    let anki_status = match app.cedict.as_ref() {
+        Some(cd) => match (cd.anki_error(), cd.anki_path()) {
+            (Some(e), _) => e.to_string(),
+            (None, Some(path)) => format!("{:?}: {}", path, cd.anki_len()),
+            (None, None) => t!("anki_none").to_string(),
+        },
+        None => String::new(),
+    };
+    let idr_anki_status = row![text(anki_status).shaping(text::Shaping::Advanced)].padding(win.padding);
+
+    let idc_anki_notes = AnkiNotesField::ALL.iter().zip(app.anki_notes_text.iter())
+        .fold(Column::new(), |col, (field, value)| {
+            let field = *field;
+            col.push(row![
+                text(field.label()).width(win.settings_label_w),
+                text_input(&t!("anki_notes_hint"), value.as_str())
+                    .on_input(move |v| Message::AnkiNotesChanged(field, v)),
+            ].padding(win.padding).spacing(win.spacing))
+        });
+
+    let idc_anki_connect = AnkiConnectField::ALL.iter()
+        .fold(Column::new(), |col, field| {
+            let field = *field;
+            col.push(row![
+                text(field.label()).width(win.settings_label_w),
+                text_input("", app.conf.anki_connect.get(field).as_str())
+                    .on_input(move |v| Message::AnkiConnectChanged(field, v)),
+            ].padding(win.padding).spacing(win.spacing))
+        });
+
+    let ids_pdf_font = text(t!("pdf_font")).width(win.settings_label_w);
+    let idc_pdf_font = text_input(&t!("pdf_font_auto"), app.conf.pdf_font.as_deref().unwrap_or_default())
+        .on_input(Message::PdfFontChanged);
+    let idr_pdf_font = row![ids_pdf_font, idc_pdf_font].padding(win.padding).spacing(win.spacing);
 
     let ids_appdata = text(t!("appdata")).width(win.settings_label_w);
     let idc_appdata = text_input("", app.conf.db.as_ref().unwrap_or(&String::new()).as_str());
@@ -365,17 +493,21 @@ pub fn settings<'a>(app: &super::App) -> Element<'a, Message> {
     let idc_key = text_input("key", &app.conf.keys.deepl).on_input(Message::DeeplKeyChanged);
     let idr_deepl = row![ids_lang, idc_lang, idc_key].spacing(win.spacing).padding(win.padding);
 
-    column![
+    scrollable(column![
         idr_theme, 
         idr_lang, 
         text(""),
         idr_font_size,
         idr_anki,
+        idr_anki_status,
+        idc_anki_notes,
+        idc_anki_connect,
+        idr_pdf_font,
         idr_appdata,
         iced::widget::rule::horizontal(2.0),
         idr_deepl,
         idr_b,
-    ].padding(win.padding_frame).align_x(iced::Alignment::Center).into()
+    ].padding(win.padding_frame).align_x(iced::Alignment::Center)).into()
 }
 
 pub fn display_av<'a>(conf: &'a Window, msg: &'a str) -> Element<'a, Message> {
@@ -493,17 +625,75 @@ pub fn anki_stats<'a>(app: &'a super::App) -> Column<'a, Message> {
             .map(|(a,b)| (*a,*b))
             .collect();
         hsk.sort_by(|a,b| a.0.cmp(&b.0));
+        let mature: Vec<usize> = hsk.iter().map(|(lvl, _)| app.hsk_mature.get(lvl).copied().unwrap_or(0)).collect();
         let total = cd.anki_len();
         debug!("hsk: {:?} / {}", hsk, cd.data_hsk_len());
-        return column![
-            row![text(format!("HSK1: {:5} / {:5}", std::cmp::min(hsk[0].1, HSK_TOTAL[0] as usize), HSK_TOTAL[0] )).width(400.0), progress_bar(0.0..=HSK_TOTAL[0], hsk[0].1 as f32) ].spacing(win.spacing),
-            row![text(format!("HSK2: {:5} / {:5}", std::cmp::min(hsk[1].1, HSK_TOTAL[1] as usize), HSK_TOTAL[1] )).width(400.0), progress_bar(0.0..=HSK_TOTAL[1], hsk[1].1 as f32) ].spacing(win.spacing),
-            row![text(format!("HSK3: {:5} / {:5}", std::cmp::min(hsk[2].1, HSK_TOTAL[2] as usize), HSK_TOTAL[2] )).width(400.0), progress_bar(0.0..=HSK_TOTAL[2], hsk[2].1 as f32) ].spacing(win.spacing),
-            row![text(format!("HSK4: {:5} / {:5}", std::cmp::min(hsk[3].1, HSK_TOTAL[3] as usize), HSK_TOTAL[3] )).width(400.0), progress_bar(0.0..=HSK_TOTAL[3], hsk[3].1 as f32) ].spacing(win.spacing),
-            row![text(format!("HSK5: {:5} / {:5}", std::cmp::min(hsk[4].1, HSK_TOTAL[4] as usize), HSK_TOTAL[4] )).width(400.0), progress_bar(0.0..=HSK_TOTAL[4], hsk[4].1 as f32) ].spacing(win.spacing),
-            row![text(format!("HSK6: {:5} / {:5}", std::cmp::min(hsk[5].1, HSK_TOTAL[5] as usize), HSK_TOTAL[5] )).width(400.0), progress_bar(0.0..=HSK_TOTAL[5], hsk[5].1 as f32) ].spacing(win.spacing),
-            row![text(format!("HSK7: {:5} / {:5}", std::cmp::min(hsk[6].1, HSK_TOTAL[6] as usize), HSK_TOTAL[6] )).width(400.0), progress_bar(0.0..=HSK_TOTAL[6], hsk[6].1 as f32) ].spacing(win.spacing),
+        let knowledge = cd.count_knowledge();
+        let idc_knowledge = Column::with_children(crate::anki::Knowledge::ALL.iter()
+            .map(|k| {
+                let n = knowledge.get(k).copied().unwrap_or(0);
+                row![text(format!("{}: {:5}", k, n)).width(400.0), progress_bar(0.0..=total.max(1) as f32, n as f32)].spacing(win.spacing).into()
+            }))
+            .spacing(win.spacing);
+        let idc_summary = column![
+            row![text(format!("HSK1: {:5} / {:5} ({} {})", std::cmp::min(hsk[0].1, HSK_TOTAL[0] as usize), HSK_TOTAL[0], mature[0], t!("anki_mature") )).width(400.0), progress_bar(0.0..=HSK_TOTAL[0], hsk[0].1 as f32) ].spacing(win.spacing),
+            row![text(format!("HSK2: {:5} / {:5} ({} {})", std::cmp::min(hsk[1].1, HSK_TOTAL[1] as usize), HSK_TOTAL[1], mature[1], t!("anki_mature") )).width(400.0), progress_bar(0.0..=HSK_TOTAL[1], hsk[1].1 as f32) ].spacing(win.spacing),
+            row![text(format!("HSK3: {:5} / {:5} ({} {})", std::cmp::min(hsk[2].1, HSK_TOTAL[2] as usize), HSK_TOTAL[2], mature[2], t!("anki_mature") )).width(400.0), progress_bar(0.0..=HSK_TOTAL[2], hsk[2].1 as f32) ].spacing(win.spacing),
+            row![text(format!("HSK4: {:5} / {:5} ({} {})", std::cmp::min(hsk[3].1, HSK_TOTAL[3] as usize), HSK_TOTAL[3], mature[3], t!("anki_mature") )).width(400.0), progress_bar(0.0..=HSK_TOTAL[3], hsk[3].1 as f32) ].spacing(win.spacing),
+            row![text(format!("HSK5: {:5} / {:5} ({} {})", std::cmp::min(hsk[4].1, HSK_TOTAL[4] as usize), HSK_TOTAL[4], mature[4], t!("anki_mature") )).width(400.0), progress_bar(0.0..=HSK_TOTAL[4], hsk[4].1 as f32) ].spacing(win.spacing),
+            row![text(format!("HSK6: {:5} / {:5} ({} {})", std::cmp::min(hsk[5].1, HSK_TOTAL[5] as usize), HSK_TOTAL[5], mature[5], t!("anki_mature") )).width(400.0), progress_bar(0.0..=HSK_TOTAL[5], hsk[5].1 as f32) ].spacing(win.spacing),
+            row![text(format!("HSK7: {:5} / {:5} ({} {})", std::cmp::min(hsk[6].1, HSK_TOTAL[6] as usize), HSK_TOTAL[6], mature[6], t!("anki_mature") )).width(400.0), progress_bar(0.0..=HSK_TOTAL[6], hsk[6].1 as f32) ].spacing(win.spacing),
             row![text(format!("total anki: {}", total))],
+            idc_knowledge,
+            ].spacing(win.spacing);
+        let idc_chars = app.char_stats.as_ref()
+            .map(|cs| {
+                let new: String = cs.new_in_text.iter().map(|(c, _)| *c).collect();
+                let unlocks = cs.unlocks.iter()
+                    .fold(Column::new(), |col, (c, words)| col.push(text(format!("{} ({}): {}", c, words.len(), words.join(" "))).shaping(text::Shaping::Advanced)))
+                    .spacing(win.spacing);
+                column![
+                    text(t!("chars_known", n = cs.known)),
+                    row![text(format!("{}: {:5} / {:5} ({:.1}%)", t!("chars_hsk"), cs.hsk_known, cs.hsk_total, 100.0 * cs.hsk_known as f32 / cs.hsk_total.max(1) as f32)).width(400.0),
+                        progress_bar(0.0..=cs.hsk_total.max(1) as f32, cs.hsk_known as f32)].spacing(win.spacing),
+                    text(t!("chars_new", n = cs.new_in_text.len())),
+                    text(new).shaping(text::Shaping::Advanced),
+                    text(t!("chars_unlock")),
+                    unlocks,
+                ].spacing(win.spacing)
+            })
+            .unwrap_or_else(Column::new);
+        let idc_summary = scrollable(column![idc_summary, idc_chars].spacing(win.padding_frame))
+            .width(win.width * 0.45)
+            .height(win.height - 150.0);
+
+        let idr_period = super::STATS_PERIODS.iter()
+            .fold(row![text(t!("stats_period"))], |r, days| {
+                r.push(button(text(format!("{}", days)))
+                    .on_press_maybe(if *days == app.stats_days { None } else { Some(Message::AnkiStatsDays(*days)) }))
+            })
+            .push(space::horizontal())
+            .push(button_nft!("\u{eb4a}", t!("stats_csv"), AnkiStatsExport))
+            .spacing(win.spacing).align_y(Alignment::Center);
+        let today = chrono::Utc::now().date_naive();
+        let idc_projections = app.stats.as_ref()
+            .map(|stats| stats.projections.iter()
+                .fold(Column::new().push(text(t!("stats_projection"))), |col, p| {
+                    let finish = match (p.days_left, p.finish(today)) {
+                        (Some(d), _) if d <= 0.0 => t!("stats_done").to_string(),
+                        (_, Some(date)) => date.to_string(),
+                        _ => String::from("-"),
+                    };
+                    col.push(text(format!("HSK{}: {} / {}, {:.1}/{}, {}", p.level, p.known, p.total, p.per_day, t!("stats_day"), finish)))
+                }))
+            .unwrap_or_else(Column::new)
+            .spacing(win.spacing);
+        let idc_charts = app.stats_charts.iter()
+            .fold(column![idr_period, idc_projections], |col, chart| col.push(iced::widget::svg(chart.clone()).width(640.0).height(240.0)))
+            .spacing(win.spacing);
+
+        return column![
+            row![idc_summary, scrollable(idc_charts).height(win.height - 150.0)].spacing(win.padding_frame),
             button_nf!("\u{f015c}").on_press(Message::Close)
             ].padding(win.padding_frame).spacing(win.spacing).align_x(iced::Alignment::Center);
     }
@@ -513,6 +703,511 @@ pub fn anki_stats<'a>(app: &'a super::App) -> Column<'a, Message> {
     ].padding(win.padding_frame).spacing(win.spacing).align_x(iced::Alignment::Center)
 }
 
+pub fn reading_stats<'a>(app: &'a super::App) -> Column<'a, Message> {
+    let win = &app.conf.window;
+    let idc_close = button_nft!("\u{ea76}", t!("cancel"), Close);
+    let Some(stats) = app.reading_stats.as_ref() else {
+        return column![idc_close].padding(win.padding_frame);
+    };
+    let duration = |active: i64| format!("{}:{:02}", active / 3600, active / 60 % 60);
+    let speed = |cpm: Option<f64>| cpm.map(|c| format!("{:.0}", c)).unwrap_or_else(|| String::from("-"));
+    let today = stats.days.last().cloned().unwrap_or_default();
+    let week = stats.weeks.last().cloned().unwrap_or_default();
+    let idc_summary = column![
+        text(format!("{}: {} ({} {})", t!("reading_today"), duration(today.active), today.chars, t!("stats_chars"))),
+        text(format!("{}: {} ({} {})", t!("reading_week"), duration(week.active), week.chars, t!("stats_chars"))),
+        text(format!("{}: {}, {} {}", t!("reading_period"), duration(stats.period.active), stats.period.sessions, t!("reading_sessions"))),
+        text(format!("{}: {}", t!("reading_speed"), speed(stats.period.chars_per_minute()))),
+        text(format!("{}: {}", t!("reading_lookups"), stats.period.lookups)),
+        text(format!("{}: {} ({} {})", t!("reading_streak"), stats.streak, t!("reading_best"), stats.best_streak)),
+    ].spacing(win.spacing);
+
+    let idc_documents = stats.documents.iter()
+        .fold(Column::new().spacing(win.spacing), |col, d| {
+            col.push(row![
+                text(d.title.as_str()).shaping(text::Shaping::Advanced).width(200.0),
+                text(duration(d.total.active)).width(60.0),
+                text(format!("{} {}", d.total.chars, t!("stats_chars"))).width(110.0),
+                text(speed(d.total.chars_per_minute())).width(50.0),
+                button_nf!("\u{f02d7}").on_press(Message::ReadingDocument(if app.reading_document == Some(d.document) { None } else { Some(d.document) })),
+            ].spacing(win.spacing).align_y(Alignment::Center))
+        });
+    let idc_history = app.reading_document
+        .map(|document| app.reading_sessions.iter()
+            .filter(|s| s.document == document)
+            .fold(Column::new().spacing(win.spacing), |col, s| {
+                col.push(text(format!("{}  {}  {} {}  {} {}",
+                    s.start.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M"), duration(s.active),
+                    s.chars, t!("stats_chars"), s.lookups, t!("reading_lookups"))))
+            }))
+        .unwrap_or_else(Column::new);
+    let idc_left = scrollable(column![idc_summary, text(t!("reading_documents")), idc_documents, idc_history].spacing(win.padding_frame))
+        .width(win.width * 0.45)
+        .height(win.height - 150.0);
+
+    let idr_period = super::STATS_PERIODS.iter()
+        .fold(row![text(t!("stats_period"))], |r, days| {
+            r.push(button(text(format!("{}", days)))
+                .on_press_maybe(if *days == app.reading_days { None } else { Some(Message::ReadingStatsDays(*days)) }))
+        })
+        .spacing(win.spacing).align_y(Alignment::Center);
+    let idc_charts = app.reading_charts.iter()
+        .fold(column![idr_period], |col, chart| col.push(iced::widget::svg(chart.clone()).width(640.0).height(240.0)))
+        .spacing(win.spacing);
+
+    column![
+        row![idc_left, scrollable(idc_charts).height(win.height - 150.0)].spacing(win.padding_frame),
+        idc_close,
+    ].padding(win.padding_frame).spacing(win.spacing).align_x(iced::Alignment::Center)
+}
+
+fn hits_table<'a>(hits: &'a [crate::search::SearchHit]) -> table::Table<'a, Message> {
+    let bold = |header| {
+        text(header).font(Font {
+            weight: iced::font::Weight::Bold,
+            ..Font::DEFAULT
+        })
+    };
+
+    let columns = [
+        table::column(bold(t!("title")), |hit: &crate::search::SearchHit| text(hit.title.as_str()).shaping(text::Shaping::Advanced) ).width(200.0),
+        table::column(bold(t!("line")), |hit: &crate::search::SearchHit| text(hit.line) ),
+        table::column(bold(t!("column")), |hit: &crate::search::SearchHit| text(hit.character) ),
+        table::column("", |hit: &crate::search::SearchHit| text_nf!(match hit.kind {
+            crate::search::HitKind::Document => "\u{f0219}",
+            crate::search::HitKind::Note => "\u{f1a7d}",
+        }) ),
+        table::column(bold(t!("text")), |hit: &crate::search::SearchHit| row![
+            text(hit.left.as_str()).shaping(text::Shaping::Advanced),
+            text(hit.hit.as_str()).shaping(text::Shaping::Advanced).font(Font { weight: iced::font::Weight::Bold, ..Font::DEFAULT }),
+            text(hit.right.as_str()).shaping(text::Shaping::Advanced),
+        ] ).width(700.0),
+        table::column("", |hit: &crate::search::SearchHit| button_nf!("\u{f00be}").on_press(Message::SearchOpen { document: hit.doc, line: hit.line, character: hit.character }) ),
+    ];
+    table(columns, hits).padding(5.0).separator(1.0)
+}
+
+pub fn concordance<'a>(app: &'a super::App) -> Column<'a, Message> {
+    let win = &app.conf.window;
+    let idc_close = button_nft!("\u{ea76}", t!("cancel"), Close);
+    let idr_title = row![
+        text(format!("{}: {} ({})", t!("show_in_texts"), app.concordance_word, app.concordance.len())).shaping(text::Shaping::Advanced),
+        space::horizontal(),
+        idc_close,
+    ].padding(win.padding).spacing(win.spacing).align_y(Alignment::Center);
+
+    let idc_list = hits_table(&app.concordance);
+
+    column![
+        idr_title,
+        row![scrollable(idc_list).spacing(5.0)].padding(win.padding).spacing(win.spacing).height(win.height - 150.0),
+    ].padding(win.padding_frame).spacing(win.spacing)
+}
+
+pub fn apkg_export<'a>(app: &'a super::App) -> Column<'a, Message> {
+    let win = &app.conf.window;
+    let idc_close = button_nft!("\u{ea76}", t!("cancel"), Close);
+    let idc_unknown = button(text(t!("apkg_unknown"))).on_press_maybe(app.cedict.as_ref().map(|_| Message::ApkgFillUnknown));
+    let idc_deck = text_input(&t!("apkg_deck"), app.apkg_deck.as_str())
+        .on_input(Message::ApkgDeckChanged)
+        .width(300.0);
+    let idc_save = button_nft!("\u{eb4a}", t!("save"), ApkgSave);
+    let idc_review = button(text(t!("review_add"))).on_press(Message::ReviewAddWords);
+    let idr_top = row![text(t!("apkg_deck")), idc_deck, idc_unknown, space::horizontal(), idc_review, idc_save, idc_close]
+        .padding(win.padding).spacing(win.spacing).align_y(Alignment::Center);
+
+    let idc_words = text_editor(&app.apkg_words)
+        .placeholder(t!("apkg_words"))
+        .on_action(Message::ApkgWordsAction)
+        .height(win.height - 150.0);
+
+    column![
+        idr_top,
+        idc_words,
+    ].padding(win.padding_frame).spacing(win.spacing)
+}
+
+pub fn pdf_export<'a>(app: &'a super::App) -> Column<'a, Message> {
+    use crate::pdf::PdfOptions;
+    let win = &app.conf.window;
+    let options = app.pdf_options;
+    let idc_close = button_nft!("\u{ea76}", t!("cancel"), Close);
+    let loaded = !app.loaded_text.is_empty();
+    let idc_save = button(text(t!("pdf_text"))).on_press_maybe(if loaded { Some(Message::PdfSave) } else { None });
+    let idc_save_notes = button(text(t!("pdf_notes"))).on_press_maybe(if loaded { Some(Message::PdfSaveNotes) } else { None });
+    let idr_top = row![text(t!("pdf_export")), text(app.loaded_text.title.as_str()).shaping(text::Shaping::Advanced),
+        space::horizontal(), idc_save, idc_save_notes, idc_close]
+        .padding(win.padding).spacing(win.spacing).align_y(Alignment::Center);
+
+    // Pinyin and the glossary come from the dictionary
+    let has_dict = app.cedict.is_some();
+    let option = |label: String, checked: bool, dict: bool, set: fn(PdfOptions, bool) -> PdfOptions| row![
+        checkbox(checked).on_toggle_maybe((has_dict || !dict).then_some(move |v| Message::PdfOptionsChanged(set(options, v)))),
+        text(label),
+    ].padding(win.padding).spacing(win.spacing);
+
+    column![
+        idr_top,
+        option(t!("pdf_pinyin").to_string(), options.pinyin, true, |o, v| PdfOptions { pinyin: v, ..o }),
+        option(t!("pdf_footnotes").to_string(), options.notes, false, |o, v| PdfOptions { notes: v, ..o }),
+        option(t!("pdf_glossary").to_string(), options.glossary, true, |o, v| PdfOptions { glossary: v, ..o }),
+    ].padding(win.padding_frame).spacing(win.spacing)
+}
+
+pub fn revisions<'a>(app: &'a super::App) -> Column<'a, Message> {
+    use crate::diff::Change;
+    let win = &app.conf.window;
+    let selected = app.revisions.iter().find(|r| Some(r.id) == app.revision_selected);
+    let idc_close = button_nft!("\u{ea76}", t!("cancel"), Close);
+    let idc_restore = button(text(t!("revision_restore"))).on_press_maybe(selected.map(|r| Message::RevisionRestore(r.id)));
+    let idr_top = row![
+        text(t!("revisions")), text(app.loaded_text.title.as_str()).shaping(text::Shaping::Advanced),
+        text(format!("({})", app.revisions.len())),
+        space::horizontal(), idc_restore, idc_close
+    ].padding(win.padding).spacing(win.spacing).align_y(Alignment::Center);
+
+    let idc_list = app.revisions.iter()
+        .fold(Column::new().spacing(win.spacing), |col, r| {
+            let label = format!("{}  {} {}", r.saved.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S"),
+                r.content.chars().count(), t!("revision_chars"));
+            col.push(button(text(label))
+                .style(if Some(r.id) == app.revision_selected { button::primary } else { button::text })
+                .width(iced::Length::Fill)
+                .on_press(Message::RevisionSelect(r.id)))
+        });
+
+    let idc_diff = app.revision_diff.iter()
+        .fold(Column::new().spacing(win.spacing), |col, change| col.push(match change {
+            Some(Change::Same(l)) => text(format!("  {}", l)).shaping(text::Shaping::Advanced),
+            Some(Change::Removed(l)) => text(format!("- {}", l)).shaping(text::Shaping::Advanced).style(text::danger),
+            Some(Change::Added(l)) => text(format!("+ {}", l)).shaping(text::Shaping::Advanced).style(text::success),
+            None => text("…"),
+        }));
+    let idc_diff: Element<'a, Message> = if app.revisions.is_empty() {
+        text(t!("revision_none")).into()
+    } else {
+        column![text(t!("revision_legend")).style(text::secondary), scrollable(idc_diff)].spacing(win.spacing).into()
+    };
+
+    column![
+        idr_top,
+        row![
+            scrollable(idc_list).width(320.0),
+            idc_diff,
+        ].padding(win.padding).spacing(win.spacing * 4.0).height(win.height - 150.0),
+    ].padding(win.padding_frame).spacing(win.spacing)
+}
+
+pub fn review<'a>(app: &'a super::App) -> Column<'a, Message> {
+    let win = &app.conf.window;
+    let size = win.font_size.unwrap_or(18.0);
+    let (due, total) = app.review_counts;
+    let idc_close = button_nft!("\u{ea76}", t!("cancel"), Close);
+    let idc_import = button_nft!("\u{f0552}", t!("review_import"), ReviewImport);
+    let idc_export = button_nft!("\u{eb4a}", t!("review_export"), ReviewExport);
+    let idr_top = row![
+        text(format!("{}: {} / {} | {}: {}", t!("review_due"), due, total, t!("review_today"), app.review_today)),
+        space::horizontal(), idc_import, idc_export, idc_close
+    ].padding(win.padding).spacing(win.spacing).align_y(Alignment::Center);
+
+    let idc_card = match app.review_card.as_ref() {
+        None => column![text(t!("review_empty"))],
+        Some(card) => {
+            let question = column![
+                text(card.kind.label()),
+                text(card.question()).size(size * 2.0).shaping(text::Shaping::Advanced),
+            ].spacing(win.spacing).align_x(Alignment::Center);
+            if app.review_shown {
+                let idr_grades = crate::srs::Grade::ALL.iter().enumerate()
+                    .fold(Row::new().spacing(win.spacing), |r, (ix, g)| {
+                        r.push(button(text(format!("{} {} ({})", ix + 1, g.label(), crate::srs::interval_label(card.interval_after(*g)))))
+                            .on_press(Message::ReviewGrade(*g)))
+                    });
+                column![
+                    question,
+                    text(card.answer()).size(size * 1.2).shaping(text::Shaping::Advanced),
+                    text(card.source.as_str()),
+                    idr_grades,
+                ]
+            } else {
+                column![question, button(text(t!("review_show"))).on_press(Message::ReviewShow)]
+            }
+        }
+    }.spacing(win.spacing * 2.0).align_x(Alignment::Center).width(iced::Length::Fill);
+
+    let idc_history = app.review_history.iter()
+        .fold(column![text(t!("review_history"))].spacing(win.spacing), |col, r| {
+            col.push(text(format!("{} | {} | {} | {} | {}",
+                r.time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M"), r.word, r.kind.label(), r.grade.label(),
+                crate::srs::interval_label(r.interval))).shaping(text::Shaping::Advanced))
+        });
+
+    column![
+        idr_top,
+        row![idc_card, scrollable(idc_history).width(win.width * 0.35)].spacing(win.padding_frame).height(win.height - 150.0),
+    ].padding(win.padding_frame).spacing(win.spacing)
+}
+
+pub fn cloze_queue<'a>(app: &'a super::App) -> Column<'a, Message> {
+    let win = &app.conf.window;
+    let idc_close = button_nft!("\u{ea76}", t!("cancel"), Close);
+    let idc_export = button_nft!("\u{eb4a}", t!("cloze_export"), ClozeExport);
+    let idc_anki = button(text(t!("to_anki"))).on_press_maybe(if app.cloze_cards.is_empty() { None } else { Some(Message::ClozeToAnki) });
+    let idr_top = row![
+        text(format!("{}: {}", t!("cloze_queue"), app.cloze_cards.len())),
+        space::horizontal(), idc_anki, idc_export, idc_close
+    ].padding(win.padding).spacing(win.spacing).align_y(Alignment::Center);
+
+    let idc_list = app.cloze_cards.iter()
+        .fold(Column::new().spacing(win.spacing), |col, card| {
+            let note = &card.note;
+            let id = card.id;
+            col.push(row![
+                column![
+                    text(format!("{} [{}] {}", note.hanzi, note.pinyin, note.meaning)).shaping(text::Shaping::Advanced),
+                    text(note.cloze()).shaping(text::Shaping::Advanced),
+                    text(note.source.as_str()).size(win.font_size.unwrap_or(18.0) * 0.7),
+                ].width(win.width * 0.45),
+                text_input(&t!("cloze_translation"), note.translation.as_str())
+                    .on_input(move |v| Message::ClozeTranslationChanged(id, v))
+                    .on_submit(Message::ClozeTranslationSubmit),
+                tooltip(button_nf!("\u{f05ca}").on_press(Message::ClozeTranslate(id)),
+                    container(text(t!("cloze_translate"))).padding(5.0).style(container::rounded_box), tooltip::Position::FollowCursor),
+                button_nf!("\u{f01b4}").on_press(Message::ClozeRemove(id)),
+            ].spacing(win.spacing).align_y(Alignment::Center))
+        });
+
+    column![
+        idr_top,
+        row![scrollable(idc_list).spacing(5.0)].padding(win.padding).spacing(win.spacing).height(win.height - 150.0),
+    ].padding(win.padding_frame).spacing(win.spacing)
+}
+
+pub fn txt_import<'a>(app: &'a super::App) -> Column<'a, Message> {
+    let win = &app.conf.window;
+    let idc_close = button_nft!("\u{ea76}", t!("cancel"), Close);
+    let Some(file) = app.txt_file.as_ref() else {
+        return column![idc_close].padding(win.padding_frame);
+    };
+    let idc_title = text_input(&t!("text_name"), file.title.as_str())
+        .on_input(Message::TxtTitleChanged)
+        .width(300.0);
+    let idc_encoding = pick_list(crate::import::txt::TextEncoding::ALL, Some(file.encoding), Message::TxtEncoding);
+    let idc_save = button_nft!("\u{f0cfb}", t!("save"), TxtSave);
+    let idr_top = row![
+        text(t!("text_name")), idc_title,
+        text(t!("txt_encoding", detected = file.detected)), idc_encoding,
+        space::horizontal(), idc_save, idc_close
+    ].padding(win.padding).spacing(win.spacing).align_y(Alignment::Center);
+
+    column![
+        idr_top,
+        row![scrollable(text(file.preview.as_str()).shaping(text::Shaping::Advanced)).spacing(5.0)]
+            .padding(win.padding).spacing(win.spacing).height(win.height - 150.0),
+    ].padding(win.padding_frame).spacing(win.spacing)
+}
+
+pub fn library<'a>(app: &'a super::App) -> Column<'a, Message> {
+    use crate::library::{LibraryField, LibrarySort};
+    let win = &app.conf.window;
+    let docs = crate::library::view(&app.library, &app.library_filter, app.library_sort, app.library_ascending);
+    let idc_close = button_nft!("\u{ea76}", t!("cancel"), Close);
+
+    let idc_query = text_input(&t!("search"), app.library_filter.query.as_str())
+        .on_input(Message::LibraryQuery)
+        .width(300.0);
+    let idc_collection = pick_list(crate::library::collections(&app.library), app.library_filter.collection.clone(), Message::LibraryCollectionFilter)
+        .placeholder(t!("library_collection")).text_shaping(text::Shaping::Advanced);
+    let idc_tag = pick_list(crate::library::tags(&app.library), app.library_filter.tag.clone(), Message::LibraryTagFilter)
+        .placeholder(t!("library_tag")).text_shaping(text::Shaping::Advanced);
+    let idc_clear = button_nf!("\u{f0233}").on_press_maybe(if app.library_filter.is_empty() { None } else { Some(Message::LibraryClearFilter) });
+    let idr_filter = row![
+        idc_query, idc_collection, idc_tag, idc_clear,
+        text(format!("{}: {}/{}", t!("library"), docs.len(), app.library.len())),
+        space::horizontal(), idc_close
+    ].padding(win.padding).spacing(win.spacing).align_y(Alignment::Center);
+
+    let selected = !app.library_selected.is_empty();
+    let idc_tag_name = text_input(&t!("library_tag"), app.library_tag.as_str())
+        .on_input(Message::LibraryTagChanged)
+        .width(150.0);
+    let idc_tag_add = button_nf!("\u{f04fb}").on_press_maybe(if selected { Some(Message::LibraryAddTag) } else { None });
+    let idc_tag_remove = button_nf!("\u{f04fc}").on_press_maybe(if selected { Some(Message::LibraryRemoveTag) } else { None });
+    let idc_collection_name = text_input(&t!("library_collection"), app.library_collection.as_str())
+        .on_input(Message::LibraryCollectionChanged)
+        .width(150.0);
+    let idc_move = tooltip(button_nf!("\u{f024d}").on_press_maybe(if selected { Some(Message::LibraryMove) } else { None }),
+        container(text(t!("library_move"))).padding(5.0).style(container::rounded_box), tooltip::Position::FollowCursor);
+    let idc_estimate = tooltip(button_nf!("\u{f0e6b}").on_press_maybe(if selected && app.cedict.is_some() { Some(Message::LibraryEstimate) } else { None }),
+        container(text(t!("library_estimate"))).padding(5.0).style(container::rounded_box), tooltip::Position::FollowCursor);
+    let idc_delete = tooltip(button_nf!("\u{f01b4}").on_press_maybe(if selected { Some(Message::LibraryDelete) } else { None }),
+        container(text(t!("delete_text"))).padding(5.0).style(container::rounded_box), tooltip::Position::FollowCursor);
+    let idr_bulk = row![
+        checkbox(selected && docs.iter().all(|d| app.library_selected.contains(&d.id))).on_toggle(Message::LibrarySelectAll),
+        text(format!("{}: {}", t!("library_selected"), app.library_selected.len())),
+        idc_tag_name, idc_tag_add, idc_tag_remove,
+        idc_collection_name, idc_move,
+        idc_estimate, idc_delete,
+    ].padding(win.padding).spacing(win.spacing).align_y(Alignment::Center);
+
+    let header = |sort: LibrarySort, label: String, width: f32| {
+        let arrow = match (app.library_sort == sort, app.library_ascending) {
+            (false, _) => "",
+            (true, true) => " ▲",
+            (true, false) => " ▼",
+        };
+        button(text(format!("{}{}", label, arrow))).style(button::text).width(width).on_press(Message::LibrarySort(sort))
+    };
+    let date = |d: Option<chrono::DateTime<chrono::Utc>>| d.map(|d| d.with_timezone(&chrono::Local).format("%Y-%m-%d").to_string()).unwrap_or_default();
+    let idr_header = row![
+        space::horizontal().width(20.0),
+        header(LibrarySort::Title, t!("title").to_string(), 260.0),
+        header(LibrarySort::Collection, t!("library_collection").to_string(), 140.0),
+        header(LibrarySort::Author, t!("library_author").to_string(), 140.0),
+        text(t!("library_tags")).width(160.0),
+        header(LibrarySort::Difficulty, t!("library_difficulty").to_string(), 100.0),
+        header(LibrarySort::Added, t!("library_added").to_string(), 110.0),
+        header(LibrarySort::Opened, t!("library_opened").to_string(), 110.0),
+    ].spacing(win.spacing).align_y(Alignment::Center);
+    let idc_list = docs.into_iter()
+        .fold(Column::new().spacing(win.spacing), |col, doc| {
+            let id = doc.id;
+            col.push(row![
+                checkbox(app.library_selected.contains(&id)).on_toggle(move |v| Message::LibrarySelect(id, v)).width(20.0),
+                text(doc.title.as_str()).shaping(text::Shaping::Advanced).width(260.0),
+                text(doc.collection.as_str()).shaping(text::Shaping::Advanced).width(140.0),
+                text(doc.author.as_str()).shaping(text::Shaping::Advanced).width(140.0),
+                text(doc.tags.join(", ")).shaping(text::Shaping::Advanced).width(160.0),
+                text(doc.difficulty_label()).width(100.0),
+                text(date(doc.added)).width(110.0),
+                text(date(doc.opened)).width(110.0),
+                button_nf!("\u{f00be}").on_press(Message::LibraryOpen(id)),
+                button_nf!("\u{f03eb}").on_press(Message::LibraryEdit(Some(id))),
+            ].spacing(win.spacing).align_y(Alignment::Center))
+        });
+
+    let mut res = column![
+        idr_filter,
+        idr_bulk,
+        idr_header,
+        row![scrollable(idc_list).spacing(5.0)].padding(win.padding).spacing(win.spacing).height(win.height - 260.0),
+    ].padding(win.padding_frame).spacing(win.spacing);
+    if let Some((info, tags)) = &app.library_edit {
+        let field = |f: LibraryField, label: String, value: &'a str| column![
+            text(label),
+            text_input("", value).on_input(move |v| Message::LibraryEditField(f, v)).width(200.0),
+        ];
+        res = res.push(row![
+            text(info.title.as_str()).shaping(text::Shaping::Advanced).width(200.0),
+            field(LibraryField::Author, t!("library_author").to_string(), info.author.as_str()),
+            field(LibraryField::Url, t!("library_url").to_string(), info.url.as_str()),
+            field(LibraryField::Collection, t!("library_collection").to_string(), info.collection.as_str()),
+            field(LibraryField::Tags, t!("library_tags").to_string(), tags.as_str()),
+            button_nf!("\u{f0cfb}").on_press(Message::LibrarySaveInfo),
+            button_nf!("\u{ea76}").on_press(Message::LibraryEdit(None)),
+        ].padding(win.padding).spacing(win.spacing).align_y(Alignment::End));
+    }
+    res
+}
+
+pub fn note_browser<'a>(app: &'a super::App) -> Column<'a, Message> {
+    let win = &app.conf.window;
+    let entries = crate::notes::view(&app.note_entries, &app.note_filter, &app.note_hits);
+    let idc_close = button_nft!("\u{ea76}", t!("cancel"), Close);
+
+    let idc_query = text_input(&t!("search"), app.note_filter.query.as_str())
+        .on_input(Message::NoteBrowserQuery)
+        .width(250.0);
+    let document = app.note_filter.document
+        .and_then(|id| app.documents.iter().find(|d| d.id == id))
+        .cloned();
+    let idc_document = pick_list(app.documents.as_slice(), document, Message::NoteBrowserDocument)
+        .placeholder(t!("title")).text_shaping(text::Shaping::Advanced);
+    let idc_tag = pick_list(crate::notes::tags(&app.note_entries), app.note_filter.tag.clone(), Message::NoteBrowserTagFilter)
+        .placeholder(t!("library_tag")).text_shaping(text::Shaping::Advanced);
+    let idc_from = text_input(&t!("note_from"), app.note_from.as_str())
+        .on_input(Message::NoteBrowserFrom)
+        .width(110.0);
+    let idc_to = text_input(&t!("note_to"), app.note_to.as_str())
+        .on_input(Message::NoteBrowserTo)
+        .width(110.0);
+    let idc_clear = button_nf!("\u{f0233}").on_press_maybe(if app.note_filter.is_empty() && app.note_from.is_empty() && app.note_to.is_empty() { None } else { Some(Message::NoteBrowserClearFilter) });
+    let idc_export = tooltip(button_nf!("\u{eb4a}").on_press_maybe(if entries.is_empty() { None } else { Some(Message::NoteBrowserExport) }),
+        container(text(t!("save"))).padding(5.0).style(container::rounded_box), tooltip::Position::FollowCursor);
+    let idr_filter = row![
+        idc_query, idc_document, idc_tag, idc_from, idc_to, idc_clear,
+        text(format!("{}: {}/{}", t!("note_browser"), entries.len(), app.note_entries.len())),
+        space::horizontal(), idc_export, idc_close
+    ].padding(win.padding).spacing(win.spacing).align_y(Alignment::Center);
+
+    let selected = !app.note_selected.is_empty();
+    let idc_tag_name = text_input(&t!("library_tag"), app.note_tag.as_str())
+        .on_input(Message::NoteBrowserTagChanged)
+        .width(150.0);
+    let idc_tag_add = button_nf!("\u{f04fb}").on_press_maybe(if selected { Some(Message::NoteBrowserAddTag) } else { None });
+    let idc_tag_remove = button_nf!("\u{f04fc}").on_press_maybe(if selected { Some(Message::NoteBrowserRemoveTag) } else { None });
+    let idr_bulk = row![
+        checkbox(selected && entries.iter().all(|e| app.note_selected.contains(&e.note.id))).on_toggle(Message::NoteBrowserSelectAll),
+        text(format!("{}: {}", t!("library_selected"), app.note_selected.len())),
+        idc_tag_name, idc_tag_add, idc_tag_remove,
+    ].padding(win.padding).spacing(win.spacing).align_y(Alignment::Center);
+
+    let idr_header = row![
+        space::horizontal().width(20.0),
+        text(t!("title")).width(160.0),
+        text(t!("note_quote")).width(300.0),
+        text(t!("text")).width(300.0),
+        text(t!("library_tags")).width(140.0),
+        text(t!("library_added")).width(100.0),
+    ].spacing(win.spacing).align_y(Alignment::Center);
+    let idc_list = entries.into_iter()
+        .fold(Column::new().spacing(win.spacing), |col, entry| {
+            let note = &entry.note;
+            let id = note.id;
+            let context = if note.is_range() {
+                format!("{} {}", note.color.marker(), note.anchor.quote)
+            } else {
+                entry.sentence.clone()
+            };
+            col.push(row![
+                checkbox(app.note_selected.contains(&id)).on_toggle(move |v| Message::NoteBrowserSelect(id, v)).width(20.0),
+                text(entry.title.as_str()).shaping(text::Shaping::Advanced).width(160.0),
+                text(context).shaping(text::Shaping::Advanced).width(300.0),
+                text(note.text.as_str()).shaping(text::Shaping::Advanced).width(300.0),
+                text(entry.tags.join(", ")).shaping(text::Shaping::Advanced).width(140.0),
+                text(entry.day().map(|d| d.format("%Y-%m-%d").to_string()).unwrap_or_default()).width(100.0),
+                tooltip(button_nf!("\u{f00be}").on_press(Message::SearchOpen { document: note.doc, line: note.line, character: note.char }),
+                    container(text(t!("note_open"))).padding(5.0).style(container::rounded_box), tooltip::Position::FollowCursor),
+            ].spacing(win.spacing).align_y(Alignment::Center))
+        });
+
+    column![
+        idr_filter,
+        idr_bulk,
+        idr_header,
+        row![scrollable(idc_list).spacing(5.0)].padding(win.padding).spacing(win.spacing).height(win.height - 260.0),
+    ].padding(win.padding_frame).spacing(win.spacing)
+}
+
+pub fn search<'a>(app: &'a super::App) -> Column<'a, Message> {
+    let win = &app.conf.window;
+    let idc_close = button_nft!("\u{ea76}", t!("cancel"), Close);
+    let idc_query = text_input(&t!("search"), app.search_query.as_str())
+        .on_input(Message::SearchChanged)
+        .on_submit(Message::SearchSubmit)
+        .width(500.0);
+    let idc_submit = button_nf!("\u{f002}").on_press(Message::SearchSubmit);
+    let idr_query = row![idc_query, idc_submit, text(format!("{}: {}", t!("search_hits"), app.search_hits.len())), space::horizontal(), idc_close]
+        .padding(win.padding).spacing(win.spacing).align_y(Alignment::Center);
+
+    let idc_list = hits_table(&app.search_hits);
+
+    column![
+        idr_query,
+        row![scrollable(idc_list).spacing(5.0)].padding(win.padding).spacing(win.spacing).height(win.height - 150.0),
+    ].padding(win.padding_frame).spacing(win.spacing)
+}
+
 pub fn files_dl<'a>(app: &'a super::App) -> Column<'a, Message> {
     let conf = &app.conf;
     let win = &conf.window;
@@ -0,0 +1,205 @@
+use std::collections::{BTreeMap, BTreeSet};
+use serde::Serialize;
+use crate::anchor::byte_offset;
+use crate::cedict::Cedict;
+use crate::textbase::Note;
+use crate::utils::is_chinese_char;
+
+const CSS: &str = r#"
+body { max-width: 46em; margin: 2em auto; padding: 0 1em; font-family: sans-serif; font-size: 1.3em; line-height: 2; color: #222; background: #fdfdf8; }
+h1 { font-size: 1.5em; }
+p { margin: 0 0 .6em; min-height: 1em; }
+#py { position: fixed; top: 1em; right: 1em; font-size: .7em; }
+.w { cursor: pointer; border-radius: 3px; }
+.w:hover { background: #e4e8f8; }
+rt { display: none; font-size: .5em; color: #777; }
+body.py rt { display: ruby-text; }
+mark { border-radius: 3px; color: inherit; }
+mark.yellow { background: #fff0a0; }
+mark.green { background: #c8f0c0; }
+mark.blue { background: #c8dcff; }
+mark.red { background: #ffc8c8; }
+mark.purple { background: #e6d0ff; }
+sup.note { color: #c60; cursor: help; }
+#pop, #tip { position: absolute; display: none; z-index: 2; max-width: 24em; padding: .4em .8em; font-size: .75em; line-height: 1.4;
+    background: #fff; border: 1px solid #bbb; border-radius: 6px; box-shadow: 0 2px 8px rgba(0, 0, 0, .2); }
+#pop ol { margin: .2em 0 .4em; padding-left: 1.4em; }
+#tip p { white-space: pre-wrap; margin: .2em 0; }
+"#;
+
+const JS: &str = r#"
+const pop = document.getElementById('pop'), tip = document.getElementById('tip');
+const esc = s => s.replace(/[&<>"]/g, c => ({ '&': '&amp;', '<': '&lt;', '>': '&gt;', '"': '&quot;' })[c]);
+function place(box, el) {
+    const r = el.getBoundingClientRect();
+    box.style.display = 'block';
+    box.style.left = Math.max(0, Math.min(r.left, innerWidth - box.offsetWidth - 8)) + scrollX + 'px';
+    box.style.top = r.bottom + scrollY + 4 + 'px';
+}
+document.addEventListener('click', e => {
+    const w = e.target.closest('.w');
+    if (!w) { pop.style.display = 'none'; return; }
+    const word = w.dataset.w;
+    pop.innerHTML = (DICT[word] || []).map(d => `<div><b>${esc(word)}</b>${d.t && d.t !== word ? ' ' + esc(d.t) : ''} <i>${esc(d.p)}</i>`
+        + `<ol>${d.m.map(m => `<li>${esc(m)}</li>`).join('')}</ol></div>`).join('');
+    place(pop, w);
+});
+document.addEventListener('mouseover', e => {
+    const n = e.target.closest('[data-n]');
+    const texts = n ? n.dataset.n.split(' ').map(i => NOTES[i]).filter(t => t) : [];
+    if (!texts.length) { tip.style.display = 'none'; return; }
+    tip.innerHTML = texts.map(t => `<p>${esc(t)}</p>`).join('');
+    place(tip, n);
+});
+document.getElementById('py').addEventListener('click', () => document.body.classList.toggle('py'));
+"#;
+
+/// A dictionary entry of the popup
+#[derive(Serialize)]
+struct Definition<'a> {
+    t: &'a str,
+    p: &'a str,
+    m: Vec<&'a str>,
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// JSON that can be put in a script element
+fn script_json(value: &impl Serialize) -> String {
+    serde_json::to_string(value).unwrap_or_default().replace("</", "<\\/")
+}
+
+/// The words of a piece of text, with pinyin and a popup for the ones in the dictionary
+fn words<'a>(piece: &'a str, cedict: Option<&Cedict>, dict: &mut BTreeSet<&'a str>) -> String {
+    let Some(cedict) = cedict else {
+        return escape(piece);
+    };
+    cedict.segment(piece).into_iter()
+        .map(|(word, entry)| match entry {
+            Some(e) if word.chars().any(|c| is_chinese_char(&c)) => {
+                dict.insert(word);
+                format!("<span class=\"w\" data-w=\"{0}\"><ruby>{0}<rt>{1}</rt></ruby></span>", escape(word), escape(e.pinyin()))
+            }
+            _ => escape(word),
+        })
+        .collect()
+}
+
+/// One HTML file with the text, a dictionary popup for its words, pinyin that can be
+/// shown and hidden with the `pinyin` button, and the notes as tooltips of their highlights
+pub fn export(title: &str, content: &str, cedict: Option<&Cedict>, notes: &[Note], pinyin: &str) -> String {
+    let mut dict = BTreeSet::new();
+    let mut body = String::new();
+    for (ix, line) in content.lines().enumerate() {
+        let ix = ix as i64;
+        let chars = line.chars().count();
+        let column = |c: i64| (c.max(0) as usize).min(chars);
+        let on_line: Vec<(usize, &Note)> = notes.iter().enumerate()
+            .filter(|(_, n)| n.line <= ix && ix <= n.end().0)
+            .collect();
+
+        // The line is cut where highlights start and end
+        let mut cuts = BTreeSet::from([0, chars]);
+        for (_, note) in on_line.iter() {
+            if note.line == ix {
+                cuts.insert(column(note.char));
+            }
+            if note.end().0 == ix {
+                cuts.insert(column(note.end().1));
+            }
+        }
+        let cuts: Vec<usize> = cuts.into_iter().collect();
+
+        body.push_str("<p>");
+        for (i, from) in cuts.iter().enumerate() {
+            for (n, _) in on_line.iter().filter(|(_, note)| !note.is_range() && note.line == ix && column(note.char) == *from) {
+                body.push_str(&format!("<sup class=\"note\" data-n=\"{}\">*</sup>", n));
+            }
+            let Some(to) = cuts.get(i + 1) else { break };
+            let piece = &line[byte_offset(line, 0, *from)..byte_offset(line, 0, *to)];
+            let words = words(piece, cedict, &mut dict);
+            let covering: Vec<&(usize, &Note)> = on_line.iter()
+                .filter(|(_, note)| note.is_range() && note.pos() <= (ix, *from as i64) && (ix, *to as i64) <= note.end())
+                .collect();
+            match covering.last() {
+                Some((_, note)) => {
+                    let ids: Vec<String> = covering.iter().map(|(n, _)| n.to_string()).collect();
+                    body.push_str(&format!("<mark class=\"{}\" data-n=\"{}\">{}</mark>",
+                        note.color.as_str().to_lowercase(), ids.join(" "), words));
+                }
+                None => body.push_str(&words),
+            }
+        }
+        body.push_str("</p>\n");
+    }
+
+    let definitions: BTreeMap<&str, Vec<Definition>> = dict.into_iter()
+        .filter_map(|word| {
+            let entries = cedict?.find(word);
+            let defs: Vec<Definition> = entries.into_iter()
+                .map(|e| Definition {
+                    t: e.traditional(),
+                    p: e.pinyin(),
+                    m: e.meanings().split('/').map(str::trim).filter(|m| !m.is_empty()).collect(),
+                })
+                .collect();
+            Some((word, defs))
+        })
+        .collect();
+    let note_texts: Vec<&str> = notes.iter().map(|n| n.text.trim()).collect();
+
+    format!(r#"<!DOCTYPE html>
+<html lang="zh">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<style>{CSS}</style>
+</head>
+<body>
+<button id="py">{pinyin}</button>
+<h1>{title}</h1>
+{body}<div id="pop"></div><div id="tip"></div>
+<script>
+const DICT = {dict};
+const NOTES = {notes};
+{JS}</script>
+</body>
+</html>
+"#, title = escape(title), pinyin = escape(pinyin), dict = script_json(&definitions), notes = script_json(&note_texts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::anchor::NoteColor;
+
+    #[test]
+    fn test_export() {
+        let tmp = crate::utils::TempFile::new("db");
+        let conn = rusqlite::Connection::open(&tmp.0).unwrap();
+        conn.execute_batch("
+            CREATE TABLE Cedict (Simplified TEXT, Traditional TEXT, Pinyin TEXT, Meaning TEXT, Hsk INTEGER);
+            INSERT INTO Cedict VALUES ('故乡', '故鄉', 'gùxiāng', '/home/native place/', 4), ('严寒', '嚴寒', 'yánhán', '/bitter cold/', NULL);
+        ").unwrap();
+        let cedict = Cedict::new(tmp.0.to_str().unwrap(), &None, &Default::default()).unwrap();
+
+        let text = "我冒了严寒，回到故乡去。\n<故乡>";
+        let notes = [
+            Note { line: 0, char: 8, end_line: 0, end_char: 10, color: NoteColor::Green, text: String::from("home </script>"), ..Default::default() },
+            Note { line: 0, char: 5, text: String::from("comma"), ..Default::default() },
+        ];
+        let html = export("故乡 & 社戏", text, Some(&cedict), &notes, "拼音");
+        assert!(html.contains("<title>故乡 &amp; 社戏</title>") && html.contains("<button id=\"py\">拼音</button>"));
+        assert!(html.contains("<p>我冒了<span class=\"w\" data-w=\"严寒\"><ruby>严寒<rt>yánhán</rt></ruby></span><sup class=\"note\" data-n=\"1\">*</sup>，回到\
+            <mark class=\"green\" data-n=\"0\"><span class=\"w\" data-w=\"故乡\"><ruby>故乡<rt>gùxiāng</rt></ruby></span></mark>去。</p>"));
+        assert!(html.contains("<p>&lt;<span class=\"w\" data-w=\"故乡\"><ruby>故乡<rt>gùxiāng</rt></ruby></span>&gt;</p>"));
+        assert!(html.contains(r#"const DICT = {"严寒":[{"t":"嚴寒","p":"yánhán","m":["bitter cold"]}],"故乡":[{"t":"故鄉","p":"gùxiāng","m":["home","native place"]}]};"#));
+        assert!(html.contains(r#"const NOTES = ["home <\/script>","comma"];"#));
+
+        let plain = export("", text, None, &[], "Pinyin");
+        assert!(plain.contains("<p>我冒了严寒，回到故乡去。</p>\n<p>&lt;故乡&gt;</p>") && plain.contains("const DICT = {};"));
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use regex::Regex;
+use sha1::{Digest, Sha1};
+use tracing::{debug, warn};
+
+use crate::error::{ReaderError, ReaderResult};
+
+/// Tags that end a paragraph
+const BLOCK_TAGS: &[&str] = &["p", "div", "br", "li", "tr", "blockquote", "section", "article", "h1", "h2", "h3", "h4", "h5", "h6", "hr", "pre", "dd", "dt"];
+/// Tags dropped with their content: ruby annotations would be read as part of the text
+const SKIP_TAGS: &[&str] = &["head", "script", "style", "rt", "rp"];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Chapter {
+    pub title: String,
+    pub paragraphs: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Book {
+    pub title: String,
+    pub author: String,
+    /// dc:identifier of the package, or a hash of its spine when there is none
+    pub identifier: String,
+    pub chapters: Vec<Chapter>,
+}
+
+impl Book {
+    /// Documents to store: one per chapter, or the whole book with a `# title` line
+    /// before each chapter
+    pub fn documents(&self, split: bool) -> Vec<(String, String)> {
+        if split {
+            self.chapters.iter()
+                .map(|c| (format!("{} - {}", self.title, c.title), c.paragraphs.join("\n")))
+                .collect()
+        } else {
+            let content = self.chapters.iter()
+                .map(|c| format!("# {}\n{}", c.title, c.paragraphs.join("\n")))
+                .collect::<Vec<_>>()
+                .join("\n");
+            vec![(self.title.clone(), content)]
+        }
+    }
+}
+
+fn regex(s: &str) -> Regex {
+    Regex::new(s).unwrap()
+}
+
+/// Attributes of every `name` element, namespace prefixes ignored
+fn elements(xml: &str, name: &str) -> Vec<HashMap<String, String>> {
+    let tag = regex(&format!(r"<(?:[\w-]+:)?{}\b([^>]*)>", name));
+    let attr = regex(r#"([\w:-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#);
+    tag.captures_iter(xml)
+        .map(|t| attr.captures_iter(&t[1])
+            .map(|a| {
+                let value = a.get(2).or(a.get(3)).map(|v| v.as_str()).unwrap_or_default();
+                (a[1].to_string(), decode_entities(value))
+            })
+            .collect())
+        .collect()
+}
+
+/// Text of the first `name` element
+fn element_text(xml: &str, name: &str) -> Option<String> {
+    let re = regex(&format!(r"(?s)<(?:[\w-]+:)?{0}\b[^>]*>(.*?)</(?:[\w-]+:)?{0}>", name));
+    re.captures(xml)
+        .map(|c| decode_entities(strip_tags(&c[1]).trim()))
+        .filter(|s| !s.is_empty())
+}
+
+fn strip_tags(s: &str) -> String {
+    regex(r"<[^>]*>").replace_all(s, "").to_string()
+}
+
+fn decode_entities(s: &str) -> String {
+    if !s.contains('&') {
+        return s.to_string();
+    }
+    let re = regex(r"&(#x[0-9a-fA-F]+|#[0-9]+|\w+);");
+    re.replace_all(s, |c: &regex::Captures| {
+        let e = &c[1];
+        let decoded = if let Some(hex) = e.strip_prefix("#x") {
+            u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+        } else if let Some(dec) = e.strip_prefix('#') {
+            dec.parse().ok().and_then(char::from_u32)
+        } else {
+            match e {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                "ensp" | "emsp" | "thinsp" => Some(' '),
+                "mdash" => Some('—'),
+                "ndash" => Some('–'),
+                "hellip" => Some('…'),
+                "middot" => Some('·'),
+                "ldquo" => Some('“'),
+                "rdquo" => Some('”'),
+                "lsquo" => Some('‘'),
+                "rsquo" => Some('’'),
+                _ => None,
+            }
+        };
+        decoded.map(String::from).unwrap_or_else(|| c[0].to_string())
+    }).to_string()
+}
+
+/// Paragraphs of an XHTML file, with the first heading when there is one
+pub fn xhtml_paragraphs(xhtml: &str) -> (Option<String>, Vec<String>) {
+    let body = regex(r"(?is)<body\b[^>]*>(.*)</body>").captures(xhtml)
+        .map(|c| c[1].to_string())
+        .unwrap_or_else(|| xhtml.to_string());
+    let mut text = regex(r"(?s)<!--.*?-->").replace_all(&body, "").to_string();
+    for tag in SKIP_TAGS {
+        text = regex(&format!(r"(?is)<{0}\b[^>]*>.*?</{0}>", tag)).replace_all(&text, "").to_string();
+    }
+    let heading = element_text(&text, "h[1-3]");
+    text = regex(r"(?is)<h[1-3]\b[^>]*>.*?</h[1-3]>").replace(&text, "\n").to_string();
+
+    // Line breaks of the markup are plain spaces, paragraphs come from the tags
+    text = regex(r"\s+").replace_all(&text, " ").to_string();
+    let block = regex(&format!(r"(?i)</?(?:{})\b[^>]*>", BLOCK_TAGS.join("|")));
+    text = block.replace_all(&text, "\n").to_string();
+    let text = decode_entities(&strip_tags(&text));
+    let paragraphs = text.lines()
+        .map(|l| l.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|l| !l.is_empty())
+        .collect();
+    (heading, paragraphs)
+}
+
+/// Path of a link relative to the file it appears in, without its fragment
+fn resolve(base: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or_default();
+    let mut parts: Vec<&str> = base.split('/').collect();
+    parts.pop();
+    for p in href.split('/') {
+        match p {
+            "." | "" => {}
+            ".." => { parts.pop(); }
+            p => parts.push(p),
+        }
+    }
+    percent_decode(&parts.join("/"))
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        if bytes[i] == b'%' && let Some(b) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            res.push(b);
+            i += 3;
+        } else {
+            res.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&res).to_string()
+}
+
+fn read_entry<R: Read + Seek>(zip: &mut zip::ZipArchive<R>, name: &str) -> ReaderResult<String> {
+    let mut file = zip.by_name(name)
+        .map_err(|_| ReaderError::Import(format!("{} missing from the EPUB", name)))?;
+    let mut data = vec![];
+    file.read_to_end(&mut data)?;
+    Ok(String::from_utf8_lossy(&data).to_string())
+}
+
+pub fn read_book<R: Read + Seek>(reader: R) -> ReaderResult<Book> {
+    let mut zip = zip::ZipArchive::new(reader)?;
+    let container = read_entry(&mut zip, "META-INF/container.xml")?;
+    let opf_path = elements(&container, "rootfile").into_iter()
+        .find_map(|a| a.get("full-path").cloned())
+        .ok_or_else(|| ReaderError::Import(String::from("No package in container.xml")))?;
+    let opf = read_entry(&mut zip, &opf_path)?;
+
+    let manifest: HashMap<String, String> = elements(&opf, "item").into_iter()
+        .filter_map(|a| Some((a.get("id")?.clone(), a.get("href")?.clone())))
+        .collect();
+    let spine: Vec<String> = elements(&opf, "itemref").into_iter()
+        .filter(|a| a.get("linear").map(|l| l != "no").unwrap_or(true))
+        .filter_map(|a| manifest.get(a.get("idref")?).map(|href| resolve(&opf_path, href)))
+        .collect();
+    debug!("EPUB {} with {} spine items", opf_path, spine.len());
+
+    let mut chapters = vec![];
+    for path in &spine {
+        let xhtml = match read_entry(&mut zip, path) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Skipping EPUB item: {}", e);
+                continue;
+            }
+        };
+        let (heading, paragraphs) = xhtml_paragraphs(&xhtml);
+        if paragraphs.is_empty() {
+            continue;
+        }
+        let title = heading
+            .or_else(|| element_text(&xhtml, "title"))
+            .unwrap_or_else(|| format!("{}", chapters.len() + 1));
+        chapters.push(Chapter { title, paragraphs });
+    }
+    if chapters.is_empty() {
+        return Err(ReaderError::Import(String::from("The EPUB has no text")));
+    }
+
+    let title = element_text(&opf, "title").unwrap_or_else(|| String::from("EPUB"));
+    let identifier = element_text(&opf, "identifier").unwrap_or_else(|| {
+        let mut hasher = Sha1::new();
+        hasher.update(title.as_bytes());
+        spine.iter().for_each(|p| hasher.update(p.as_bytes()));
+        format!("{:x}", hasher.finalize())
+    });
+    Ok(Book {
+        author: element_text(&opf, "creator").unwrap_or_default(),
+        title,
+        identifier,
+        chapters,
+    })
+}
+
+pub fn read_file(path: impl AsRef<Path>) -> ReaderResult<Book> {
+    read_book(std::fs::File::open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    const OPF: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:title>呐喊</dc:title>
+    <dc:creator id="author">鲁迅</dc:creator>
+    <dc:identifier id="uid">urn:uuid:1234</dc:identifier>
+  </metadata>
+  <manifest>
+    <item id="cover" href="cover.xhtml" media-type="application/xhtml+xml"/>
+    <item href="text/ch%201.xhtml" id="c1" media-type="application/xhtml+xml"/>
+    <item id="c2" href="text/ch2.xhtml" media-type="application/xhtml+xml"/>
+  </manifest>
+  <spine><itemref idref="cover"/><itemref idref="c1"/><itemref idref="c2"/></spine>
+</package>"#;
+
+    fn epub() -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+        let options = SimpleFileOptions::default();
+        let files = [
+            ("mimetype", "application/epub+zip"),
+            ("META-INF/container.xml", r#"<container><rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles></container>"#),
+            ("OEBPS/content.opf", OPF),
+            ("OEBPS/cover.xhtml", r#"<html><body><img src="cover.jpg"/></body></html>"#),
+            ("OEBPS/text/ch 1.xhtml", "<html><head><title>一</title><style>p {}</style></head><body><h2>狂人日记</h2>\n<p>今天晚上，<b>很好</b>的月光。</p><p>我不见他，\n  已是三十多年；</p></body></html>"),
+            ("OEBPS/text/ch2.xhtml", "<html><body><p><ruby>孔<rt>kǒng</rt></ruby>乙己&amp;&#x4E00;&nbsp;个</p><div>第二段<br/>第三段</div></body></html>"),
+        ];
+        for (name, content) in files {
+            zip.start_file(name, options).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_read_book() {
+        let book = read_book(std::io::Cursor::new(epub())).unwrap();
+        assert_eq!((book.title.as_str(), book.author.as_str(), book.identifier.as_str()), ("呐喊", "鲁迅", "urn:uuid:1234"));
+        // The cover has no text
+        assert_eq!(book.chapters.len(), 2);
+        assert_eq!(book.chapters[0], Chapter {
+            title: String::from("狂人日记"),
+            paragraphs: vec![String::from("今天晚上，很好的月光。"), String::from("我不见他， 已是三十多年；")],
+        });
+        assert_eq!(book.chapters[1].title, "2");
+        assert_eq!(book.chapters[1].paragraphs, vec!["孔乙己&一 个", "第二段", "第三段"]);
+
+        let docs = book.documents(true);
+        assert_eq!(docs[0].0, "呐喊 - 狂人日记");
+        let whole = book.documents(false);
+        assert_eq!(whole.len(), 1);
+        assert!(whole[0].1.starts_with("# 狂人日记\n今天晚上"));
+        assert!(whole[0].1.contains("\n# 2\n孔乙己"));
+    }
+}
@@ -0,0 +1,198 @@
+pub mod epub;
+pub mod pdf;
+pub mod subtitle;
+pub mod txt;
+
+use std::collections::HashMap;
+
+use chrono::Utc;
+use rusqlite::{Connection, OptionalExtension, params};
+use tracing::debug;
+
+use crate::error::ReaderResult;
+use subtitle::CueLine;
+
+/// Documents created by an import
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Imported {
+    pub ids: Vec<i64>,
+    pub added: usize,
+    pub updated: usize,
+}
+
+pub fn init_import(conn: &Connection) -> ReaderResult<()> {
+    conn.execute_batch("
+        CREATE TABLE IF NOT EXISTS Imports (
+            Source TEXT NOT NULL,
+            Part INTEGER NOT NULL,
+            Document INTEGER NOT NULL,
+            Author TEXT NOT NULL DEFAULT '',
+            Imported INTEGER NOT NULL,
+            PRIMARY KEY(Source, Part) );
+        CREATE TABLE IF NOT EXISTS OcrLines (
+            Document INTEGER NOT NULL,
+            Line INTEGER NOT NULL,
+            Confidence REAL NOT NULL,
+            PRIMARY KEY(Document, Line) );
+        CREATE TABLE IF NOT EXISTS Cues (
+            Document INTEGER NOT NULL,
+            Line INTEGER NOT NULL,
+            Start INTEGER NOT NULL,
+            End INTEGER NOT NULL,
+            PRIMARY KEY(Document, Line) );
+    ")?;
+    Ok(())
+}
+
+/// A source imported whole and split into chapters gives different documents. Imports
+/// of several parts were split, a single one is taken as whole
+pub fn split_imports(conn: &Connection) -> ReaderResult<()> {
+    conn.execute_batch("
+        CREATE TABLE ImportParts (
+            Source TEXT NOT NULL,
+            Split INTEGER NOT NULL DEFAULT 0,
+            Part INTEGER NOT NULL,
+            Document INTEGER NOT NULL,
+            Author TEXT NOT NULL DEFAULT '',
+            Imported INTEGER NOT NULL,
+            PRIMARY KEY(Source, Split, Part) );
+        INSERT INTO ImportParts (Source, Split, Part, Document, Author, Imported)
+            SELECT Source, (SELECT count(*) FROM Imports P WHERE P.Source = I.Source) > 1, Part, Document, Author, Imported
+            FROM Imports I;
+        DROP TABLE Imports;
+        ALTER TABLE ImportParts RENAME TO Imports;
+    ")?;
+    Ok(())
+}
+
+/// Save the documents of a source, `split` when they are its chapters. Importing the same
+/// source the same way again replaces the content of the documents it created instead
+/// of adding new ones
+pub fn save_documents(conn: &mut Connection, source: &str, author: &str, split: bool, documents: &[(String, String)]) -> ReaderResult<Imported> {
+    let mut res = Imported::default();
+    let tx = conn.transaction()?;
+    for (part, (title, content)) in (0i64..).zip(documents) {
+        let existing: Option<i64> = tx.query_row(
+            "SELECT I.Document FROM Imports I JOIN Documents D ON D.Id = I.Document WHERE I.Source = ?1 AND I.Split = ?2 AND I.Part = ?3",
+            params![source, split, part], |row| row.get(0)).optional()?;
+        let id = crate::textbase::save_text_in(&tx, existing.unwrap_or(0) as u32, title, content)?;
+        tx.execute("INSERT OR REPLACE INTO Imports (Source, Split, Part, Document, Author, Imported) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![source, split, part, id, author, Utc::now().timestamp()])?;
+        if existing.is_some() {
+            res.updated += 1;
+        } else {
+            res.added += 1;
+        }
+        res.ids.push(id);
+    }
+    tx.commit()?;
+    debug!("Imported {}: {} new, {} updated", source, res.added, res.updated);
+    Ok(res)
+}
+
+/// Keep the OCR scores of the lines of a document, replacing the previous ones
+pub fn save_confidence(conn: &mut Connection, document: i64, lines: &[(i64, f32)]) -> ReaderResult<()> {
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM OcrLines WHERE Document = ?1", [document])?;
+    for (line, confidence) in lines {
+        tx.execute("INSERT INTO OcrLines (Document, Line, Confidence) VALUES (?1, ?2, ?3)", params![document, line, confidence])?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// OCR scores of the recognised lines of a document
+pub fn confidence(conn: &Connection, document: u32) -> ReaderResult<HashMap<i64, f32>> {
+    let mut st = conn.prepare("SELECT Line, Confidence FROM OcrLines WHERE Document = ?1")?;
+    let res = st.query_map([document], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<HashMap<_, _>, _>>()?;
+    Ok(res)
+}
+
+/// Keep the subtitle times of the lines of a document, replacing the previous ones
+pub fn save_cues(conn: &mut Connection, document: i64, cues: &[CueLine]) -> ReaderResult<()> {
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM Cues WHERE Document = ?1", [document])?;
+    for c in cues {
+        tx.execute("INSERT INTO Cues (Document, Line, Start, End) VALUES (?1, ?2, ?3, ?4)", params![document, c.line, c.start, c.end])?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Moves the OCR scores and subtitle times of a document to their lines after the
+/// content changed from `old` to `new`, those of removed lines are dropped
+pub fn move_lines(conn: &Connection, document: u32, old: &str, new: &str) -> ReaderResult<()> {
+    let mut map = None;
+    for table in ["OcrLines", "Cues"] {
+        let lines: Vec<i64> = conn.prepare(&format!("SELECT Line FROM {} WHERE Document = ?1 ORDER BY Line", table))?
+            .query_map([document], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        if lines.is_empty() {
+            continue;
+        }
+        let map: &Vec<Option<usize>> = map.get_or_insert_with(|| crate::diff::line_map(old, new));
+        // Rows go through negative lines so they never take the line of a row not moved yet
+        for line in lines {
+            match map.get(line as usize).copied().flatten() {
+                Some(to) => conn.execute(&format!("UPDATE {} SET Line = ?3 WHERE Document = ?1 AND Line = ?2", table),
+                    params![document, line, -1 - to as i64])?,
+                None => conn.execute(&format!("DELETE FROM {} WHERE Document = ?1 AND Line = ?2", table), params![document, line])?,
+            };
+        }
+        conn.execute(&format!("UPDATE {} SET Line = -1 - Line WHERE Document = ?1 AND Line < 0", table), [document])?;
+    }
+    Ok(())
+}
+
+/// Subtitle times of a document, by line
+pub fn cues(conn: &Connection, document: u32) -> ReaderResult<Vec<CueLine>> {
+    let mut st = conn.prepare("SELECT Line, Start, End FROM Cues WHERE Document = ?1 ORDER BY Line")?;
+    let res = st.query_map([document], |row| Ok(CueLine { line: row.get(0)?, start: row.get(1)?, end: row.get(2)? }))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reimport() {
+        let mut conn = crate::scraper::db::init_db(":memory:").unwrap();
+        let docs = vec![(String::from("一"), String::from("第一章")), (String::from("二"), String::from("第二章"))];
+        let first = save_documents(&mut conn, "urn:book", "作者", true, &docs).unwrap();
+        assert_eq!((first.added, first.updated), (2, 0));
+
+        let docs = vec![(String::from("一"), String::from("第一章，改")), (String::from("二"), String::from("第二章"))];
+        let again = save_documents(&mut conn, "urn:book", "作者", true, &docs).unwrap();
+        assert_eq!((again.added, again.updated), (0, 2));
+        assert_eq!(again.ids, first.ids);
+        assert_eq!(crate::textbase::get_documents(&conn).unwrap().len(), 2);
+        assert_eq!(crate::textbase::get_content(&conn, first.ids[0] as u32).unwrap().unwrap(), "第一章，改");
+
+        // The whole book does not replace the first chapter
+        let whole = save_documents(&mut conn, "urn:book", "作者", false, &[(String::from("书"), String::from("第一章，改\n第二章"))]).unwrap();
+        assert_eq!(whole.added, 1);
+        assert_eq!(crate::textbase::get_content(&conn, first.ids[0] as u32).unwrap().unwrap(), "第一章，改");
+
+        save_confidence(&mut conn, first.ids[0], &[(1, 0.5), (2, 0.9)]).unwrap();
+        save_confidence(&mut conn, first.ids[0], &[(2, 0.8)]).unwrap();
+        assert_eq!(confidence(&conn, first.ids[0] as u32).unwrap(), HashMap::from([(2, 0.8)]));
+
+        let times = [CueLine { line: 2, start: 4000, end: 6000 }, CueLine { line: 0, start: 1000, end: 3500 }];
+        save_cues(&mut conn, first.ids[1], &times).unwrap();
+        assert_eq!(cues(&conn, first.ids[1] as u32).unwrap(), vec![times[1], times[0]]);
+
+        // Editing the text keeps the times and scores on their lines
+        let doc = first.ids[1];
+        crate::textbase::save_text(&mut conn, doc as u32, "二", "一\n二\n三").unwrap();
+        save_cues(&mut conn, doc, &times).unwrap();
+        save_confidence(&mut conn, doc, &[(0, 0.5), (1, 0.6)]).unwrap();
+        crate::textbase::save_text(&mut conn, doc as u32, "二", "零\n一\n二\n叁").unwrap();
+        assert_eq!(cues(&conn, doc as u32).unwrap().iter().map(|c| (c.line, c.start)).collect::<Vec<_>>(), vec![(1, 1000), (3, 4000)]);
+        assert_eq!(confidence(&conn, doc as u32).unwrap(), HashMap::from([(1, 0.5), (2, 0.6)]));
+        crate::textbase::save_text(&mut conn, doc as u32, "二", "零").unwrap();
+        assert!(cues(&conn, doc as u32).unwrap().is_empty());
+    }
+}
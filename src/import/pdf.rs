@@ -0,0 +1,153 @@
+use image::{DynamicImage, GrayImage, RgbImage};
+use lopdf::Document;
+use tracing::{debug, warn};
+
+use crate::error::{ReaderError, ReaderResult};
+
+/// A page of text, with the OCR score of each line when it was recognised from a scan
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Page {
+    pub number: u32,
+    pub lines: Vec<String>,
+    pub confidence: Option<Vec<f32>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PdfText {
+    pub pages: Vec<Page>,
+}
+
+/// Line put before the text of every page
+pub fn page_marker(number: u32) -> String {
+    format!("--- {} ---", number)
+}
+
+impl PdfText {
+    /// Text of the document and the OCR score of its recognised lines, by line number
+    pub fn content(&self) -> (String, Vec<(i64, f32)>) {
+        let mut lines = vec![];
+        let mut scores = vec![];
+        for page in &self.pages {
+            lines.push(page_marker(page.number));
+            for (ix, line) in page.lines.iter().enumerate() {
+                if let Some(score) = page.confidence.as_ref().and_then(|c| c.get(ix)) {
+                    scores.push((lines.len() as i64, *score));
+                }
+                lines.push(line.clone());
+            }
+        }
+        (lines.join("\n"), scores)
+    }
+
+    pub fn ocr_pages(&self) -> usize {
+        self.pages.iter().filter(|p| p.confidence.is_some()).count()
+    }
+}
+
+/// Pixels of an image XObject; JPEG scans are decoded as they are, other images must
+/// be 1 or 8 bits per component, gray or RGB
+fn decode_image(doc: &Document, img: &lopdf::xobject::PdfImage) -> ReaderResult<DynamicImage> {
+    let filters = img.filters.clone().unwrap_or_default();
+    if filters.iter().any(|f| f == "DCTDecode") {
+        return Ok(image::load_from_memory(img.content)?);
+    }
+    let data = if filters.is_empty() {
+        img.content.to_vec()
+    } else {
+        doc.get_object(img.id)?.as_stream()?.decompressed_content()?
+    };
+    let (w, h) = (img.width as u32, img.height as u32);
+    let pixels = (w * h) as usize;
+    let unsupported = || ReaderError::Import(format!("Unsupported image {:?} {:?}", filters, img.color_space));
+    match img.bits_per_component {
+        Some(1) => {
+            let row = w.div_ceil(8) as usize;
+            let gray = GrayImage::from_fn(w, h, |x, y| {
+                let byte = data.get(y as usize * row + x as usize / 8).copied().unwrap_or(0xff);
+                image::Luma([if byte & (0x80 >> (x % 8)) != 0 { 255 } else { 0 }])
+            });
+            Ok(DynamicImage::ImageLuma8(gray))
+        }
+        Some(8) if data.len() >= pixels * 3 && img.color_space.as_deref() != Some("DeviceGray") => {
+            RgbImage::from_raw(w, h, data[..pixels * 3].to_vec()).map(DynamicImage::ImageRgb8).ok_or_else(unsupported)
+        }
+        Some(8) if data.len() >= pixels => {
+            GrayImage::from_raw(w, h, data[..pixels].to_vec()).map(DynamicImage::ImageLuma8).ok_or_else(unsupported)
+        }
+        _ => Err(unsupported()),
+    }
+}
+
+/// The scan of a page without text: its largest image
+fn page_image(doc: &Document, page_id: lopdf::ObjectId) -> ReaderResult<Option<DynamicImage>> {
+    let images = doc.get_page_images(page_id)?;
+    images.iter()
+        .max_by_key(|i| i.width * i.height)
+        .map(|i| decode_image(doc, i))
+        .transpose()
+}
+
+/// Text of every page; pages without a text layer go through `ocr`, which returns
+/// the lines of an image with their scores
+pub fn read_pdf(data: &[u8], mut ocr: impl FnMut(&DynamicImage) -> ReaderResult<Vec<(String, f32)>>) -> ReaderResult<PdfText> {
+    let doc = Document::load_mem(data)?;
+    let mut pages = vec![];
+    for (number, page_id) in doc.get_pages() {
+        let text = doc.extract_text(&[number]).unwrap_or_else(|e| {
+            warn!("No text on page {}: {}", number, e);
+            String::new()
+        });
+        let lines: Vec<String> = text.lines()
+            .map(|l| l.trim().to_string())
+            .filter(|l| !l.is_empty())
+            .collect();
+        if !lines.is_empty() {
+            pages.push(Page { number, lines, confidence: None });
+            continue;
+        }
+        match page_image(&doc, page_id)? {
+            Some(img) => {
+                debug!("OCR of page {} ({}x{})", number, img.width(), img.height());
+                let (lines, scores) = ocr(&img)?.into_iter().unzip();
+                pages.push(Page { number, lines, confidence: Some(scores) });
+            }
+            None => pages.push(Page { number, ..Default::default() }),
+        }
+    }
+    Ok(PdfText { pages })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT_PDF: &[u8] = include_bytes!("../../testdata/sample_text.pdf");
+    const SCANNED_PDF: &[u8] = include_bytes!("../../testdata/sample_scanned.pdf");
+
+    #[test]
+    fn test_text_layer() {
+        let pdf = read_pdf(TEXT_PDF, |_| panic!("no page needs OCR")).unwrap();
+        assert_eq!(pdf.pages.len(), 2);
+        assert_eq!(pdf.pages[0].lines.concat(), "你好世界文字");
+        assert_eq!(pdf.pages[1].lines, vec!["Hello PDF"]);
+        let (content, scores) = pdf.content();
+        assert!(content.starts_with("--- 1 ---\n你好"));
+        assert!(content.ends_with("--- 2 ---\nHello PDF"));
+        assert!(scores.is_empty());
+    }
+
+    #[test]
+    fn test_ocr_fallback() {
+        let mut sizes = vec![];
+        let pdf = read_pdf(SCANNED_PDF, |img| {
+            sizes.push((img.width(), img.height()));
+            Ok(vec![(String::from("你好"), 0.9), (String::from("世界"), 0.5)])
+        }).unwrap();
+        // Only the page without text is recognised
+        assert_eq!(sizes, vec![(40, 20)]);
+        assert_eq!(pdf.ocr_pages(), 1);
+        let (content, scores) = pdf.content();
+        assert_eq!(content, "--- 1 ---\nCover\n--- 2 ---\n你好\n世界");
+        assert_eq!(scores, vec![(3, 0.9), (4, 0.5)]);
+    }
+}
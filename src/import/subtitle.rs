@@ -0,0 +1,196 @@
+use regex::Regex;
+
+use crate::error::{ReaderError, ReaderResult};
+use crate::utils::is_chinese_char;
+
+/// A subtitle cue, times in milliseconds; lines without Chinese go to `translation`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Cue {
+    pub start: u32,
+    pub end: u32,
+    pub text: String,
+    pub translation: String,
+}
+
+/// Time of a document line
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CueLine {
+    pub line: i64,
+    pub start: u32,
+    pub end: u32,
+}
+
+/// `h:mm:ss.mmm`
+pub fn format_time(ms: u32) -> String {
+    format!("{}:{:02}:{:02}.{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+}
+
+/// SRT, VTT and ASS times: `01:02:03,456`, `02:03.456` or `1:02:03.45`
+fn parse_time(s: &str) -> Option<u32> {
+    let s = s.trim();
+    let (hms, frac) = s.split_once([',', '.']).unwrap_or((s, "0"));
+    let mut secs = 0;
+    for part in hms.split(':') {
+        secs = secs * 60 + part.trim().parse::<u32>().ok()?;
+    }
+    let frac = frac.get(..3).unwrap_or(frac);
+    let ms = frac.parse::<u32>().ok()? * 10u32.pow(3 - frac.len() as u32);
+    Some(secs * 1000 + ms)
+}
+
+fn cue(start: u32, end: u32, lines: &[String]) -> Cue {
+    let (zh, other): (Vec<&String>, Vec<&String>) = lines.iter()
+        .filter(|l| !l.is_empty())
+        .partition(|l| l.chars().any(|c| is_chinese_char(&c)));
+    let join = |v: Vec<&String>| v.iter().map(|s| s.as_str()).collect::<Vec<_>>().join(" ");
+    Cue { start, end, text: join(zh), translation: join(other) }
+}
+
+/// SRT and VTT blocks: a timing line followed by the text
+fn parse_blocks(content: &str) -> Vec<Cue> {
+    let tags = Regex::new(r"<[^>]*>|\{\\[^}]*\}").unwrap();
+    let mut res = vec![];
+    for block in content.replace("\r\n", "\n").split("\n\n") {
+        let mut lines = block.lines().skip_while(|l| !l.contains("-->"));
+        let Some(timing) = lines.next() else {
+            continue;
+        };
+        let (start, end) = timing.split_once("-->").unwrap_or_default();
+        // VTT settings follow the end time
+        let end = end.split_whitespace().next().unwrap_or_default();
+        let (Some(start), Some(end)) = (parse_time(start), parse_time(end)) else {
+            continue;
+        };
+        let text: Vec<String> = lines
+            .map(|l| tags.replace_all(l, "").trim().to_string())
+            .collect();
+        res.push(cue(start, end, &text));
+    }
+    res
+}
+
+/// Dialogue lines of the `[Events]` section, with the override tags removed
+fn parse_ass(content: &str) -> Vec<Cue> {
+    let tags = Regex::new(r"\{[^}]*\}").unwrap();
+    let mut format: Vec<String> = vec![];
+    let mut in_events = false;
+    let mut res = vec![];
+    for line in content.lines().map(|l| l.trim()) {
+        if line.starts_with('[') {
+            in_events = line.eq_ignore_ascii_case("[events]");
+        } else if !in_events {
+            continue;
+        } else if let Some(f) = line.strip_prefix("Format:") {
+            format = f.split(',').map(|s| s.trim().to_lowercase()).collect();
+        } else if let Some(d) = line.strip_prefix("Dialogue:") {
+            let fields: Vec<&str> = d.splitn(format.len().max(1), ',').collect();
+            let field = |name: &str| format.iter().position(|f| f == name).and_then(|ix| fields.get(ix)).copied();
+            let (Some(start), Some(end), Some(text)) = (field("start").and_then(parse_time), field("end").and_then(parse_time), field("text")) else {
+                continue;
+            };
+            let text = tags.replace_all(text, "")
+                .replace("\\N", "\n")
+                .replace("\\n", "\n")
+                .replace("\\h", " ");
+            let lines: Vec<String> = text.lines().map(|l| l.trim().to_string()).collect();
+            res.push(cue(start, end, &lines));
+        }
+    }
+    res
+}
+
+/// Cues of an SRT, VTT or ASS/SSA file, by start time
+pub fn parse(content: &str) -> Vec<Cue> {
+    let content = content.trim_start_matches('\u{feff}');
+    let mut res = if content.contains("[Events]") || content.contains("[events]") {
+        parse_ass(content)
+    } else {
+        parse_blocks(content)
+    };
+    res.sort_by_key(|c| (c.start, c.end));
+    res
+}
+
+/// Cues of `main` with the text of the `other` track cues whose middle falls in them
+/// as translation
+pub fn merge(main: &[Cue], other: &[Cue]) -> Vec<Cue> {
+    main.iter()
+        .map(|m| {
+            let translation = other.iter()
+                .filter(|o| (m.start..m.end).contains(&((o.start + o.end) / 2)))
+                .map(|o| if o.translation.is_empty() { o.text.as_str() } else { o.translation.as_str() })
+                .filter(|t| !t.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ");
+            Cue { translation: if m.translation.is_empty() { translation } else { m.translation.clone() }, ..m.clone() }
+        })
+        .collect()
+}
+
+/// Merged cues of one or two subtitle files; the track with the most Chinese is the main one
+pub fn from_tracks(tracks: &[String]) -> ReaderResult<Vec<Cue>> {
+    let mut parsed: Vec<Vec<Cue>> = tracks.iter().map(|t| parse(t)).collect();
+    parsed.sort_by_key(|cues| std::cmp::Reverse(cues.iter().filter(|c| !c.text.is_empty()).count()));
+    let mut tracks = parsed.into_iter();
+    let main = tracks.next().unwrap_or_default();
+    let merged = tracks.fold(main, |cues, other| merge(&cues, &other));
+    let res: Vec<Cue> = merged.into_iter().filter(|c| !c.text.is_empty()).collect();
+    if res.is_empty() {
+        return Err(ReaderError::Import(String::from("No Chinese subtitles found")));
+    }
+    Ok(res)
+}
+
+/// Text with a line for every cue, followed by its translation, and the time of the cue lines
+pub fn to_document(cues: &[Cue]) -> (String, Vec<CueLine>) {
+    let mut lines = vec![];
+    let mut times = vec![];
+    for c in cues {
+        times.push(CueLine { line: lines.len() as i64, start: c.start, end: c.end });
+        lines.push(c.text.clone());
+        if !c.translation.is_empty() {
+            lines.push(c.translation.clone());
+        }
+    }
+    (lines.join("\n"), times)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRT: &str = "1\r\n00:00:01,000 --> 00:00:03,500\r\n<i>你好！</i>\r\nHello!\r\n\r\n2\r\n00:00:04,000 --> 00:00:06,000\r\n{\\an8}我们走吧。\r\n\r\n3\r\n00:00:07,000 --> 00:00:08,000\r\n♪\r\n";
+    const VTT: &str = "WEBVTT\n\nNOTE translation\n\nintro\n00:01.100 --> 00:03.000 align:start\nHi there!\n\n00:04.200 --> 00:05.900\n<c.yellow>Let's go.</c>\n";
+    const ASS: &str = "[Script Info]\nTitle: test\n\n[V4+ Styles]\nFormat: Name, Fontname\n\n[Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\nDialogue: 0,0:00:01.00,0:00:03.50,Default,,0,0,0,,{\\fs20\\c&H00FFFF&}你好，朋友！\\N{\\fs12}Hello, friend!\nComment: 0,0:00:02.00,0:00:03.00,Default,,0,0,0,,注释\n";
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_time("01:02:03,456"), Some(3_723_456));
+        assert_eq!(parse_time("0:00:01.5"), Some(1500));
+        assert_eq!(format_time(3_723_456), "1:02:03.456");
+
+        let srt = parse(SRT);
+        assert_eq!(srt[0], Cue { start: 1000, end: 3500, text: String::from("你好！"), translation: String::from("Hello!") });
+        assert_eq!(srt[1].text, "我们走吧。");
+        assert_eq!((srt[2].text.as_str(), srt[2].translation.as_str()), ("", "♪"));
+
+        let vtt = parse(VTT);
+        assert_eq!(vtt.len(), 2);
+        assert_eq!((vtt[0].start, vtt[0].end, vtt[1].translation.as_str()), (1100, 3000, "Let's go."));
+
+        let ass = parse(ASS);
+        assert_eq!(ass, vec![Cue { start: 1000, end: 3500, text: String::from("你好，朋友！"), translation: String::from("Hello, friend!") }]);
+    }
+
+    #[test]
+    fn test_tracks() {
+        let chinese = "1\n00:00:01,000 --> 00:00:03,500\n你好！\n\n2\n00:00:04,000 --> 00:00:06,000\n我们走吧。\n";
+        let cues = from_tracks(&[VTT.to_string(), chinese.to_string()]).unwrap();
+        assert_eq!(cues.iter().map(|c| c.translation.as_str()).collect::<Vec<_>>(), vec!["Hi there!", "Let's go."]);
+        assert!(from_tracks(&[VTT.to_string()]).is_err());
+
+        let (text, times) = to_document(&cues);
+        assert_eq!(text, "你好！\nHi there!\n我们走吧。\nLet's go.");
+        assert_eq!(times[1], CueLine { line: 2, start: 4000, end: 6000 });
+    }
+}
@@ -0,0 +1,160 @@
+use std::path::Path;
+
+use chardetng::EncodingDetector;
+use encoding_rs::Encoding;
+
+use crate::error::ReaderResult;
+use crate::make_enum;
+use crate::utils::is_chinese_char;
+
+make_enum!(TextEncoding, [Utf8, Utf16Le, Utf16Be, Gb18030, Big5]);
+
+/// Characters shown in the import preview
+pub const PREVIEW_CHARS: usize = 2000;
+
+impl TextEncoding {
+    pub fn encoding(&self) -> &'static Encoding {
+        match self {
+            Self::Utf8 => encoding_rs::UTF_8,
+            Self::Utf16Le => encoding_rs::UTF_16LE,
+            Self::Utf16Be => encoding_rs::UTF_16BE,
+            Self::Gb18030 => encoding_rs::GB18030,
+            Self::Big5 => encoding_rs::BIG5,
+        }
+    }
+}
+
+/// Encoding of a text file: the BOM when there is one, otherwise UTF-16 when there are
+/// zero bytes, UTF-8 when it is valid, and GB18030 or Big5 as guessed from the content
+pub fn detect(data: &[u8]) -> TextEncoding {
+    match data {
+        [0xef, 0xbb, 0xbf, ..] => return TextEncoding::Utf8,
+        [0xff, 0xfe, ..] => return TextEncoding::Utf16Le,
+        [0xfe, 0xff, ..] => return TextEncoding::Utf16Be,
+        _ => {}
+    }
+    // ASCII characters of UTF-16, such as line breaks, have a zero byte; the other
+    // encodings never do. The byte order is the one that reads as Chinese text
+    let sample = &data[..data.len().min(4096) & !1];
+    if sample.contains(&0) {
+        let le = text_score(&decode(sample, TextEncoding::Utf16Le));
+        let be = text_score(&decode(sample, TextEncoding::Utf16Be));
+        return if le >= be { TextEncoding::Utf16Le } else { TextEncoding::Utf16Be };
+    }
+    if std::str::from_utf8(data).is_ok() {
+        return TextEncoding::Utf8;
+    }
+    let mut detector = EncodingDetector::new();
+    detector.feed(data, true);
+    if detector.guess(None, false) == encoding_rs::BIG5 {
+        TextEncoding::Big5
+    } else {
+        TextEncoding::Gb18030
+    }
+}
+
+/// Characters expected in Chinese text: Hanzi, ASCII and CJK or full-width punctuation
+fn text_score(text: &str) -> usize {
+    text.chars()
+        .filter(|c| is_chinese_char(c) || c.is_ascii_graphic() || c.is_ascii_whitespace()
+            || ('\u{3000}'..='\u{303f}').contains(c) || ('\u{ff00}'..='\u{ffef}').contains(c))
+        .count()
+}
+
+pub fn decode(data: &[u8], encoding: TextEncoding) -> String {
+    let (text, _) = encoding.encoding().decode_with_bom_removal(data);
+    text.to_string()
+}
+
+/// Unix line breaks, no empty lines and no indentation; full-width and non-breaking
+/// spaces inside lines become plain spaces
+pub fn normalise(text: &str) -> String {
+    text.replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .lines()
+        .map(|l| l.trim().replace(['\u{3000}', '\u{a0}'], " "))
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Start of the text as it would be imported
+pub fn preview(data: &[u8], encoding: TextEncoding) -> String {
+    // No character takes more than four bytes in these encodings
+    let sample = &data[..data.len().min(PREVIEW_CHARS * 4)];
+    normalise(&decode(sample, encoding)).chars().take(PREVIEW_CHARS).collect()
+}
+
+/// A text file waiting to be imported
+#[derive(Debug, Clone, Default)]
+pub struct TxtFile {
+    pub title: String,
+    pub data: Vec<u8>,
+    pub detected: TextEncoding,
+    pub encoding: TextEncoding,
+    pub preview: String,
+}
+
+impl TxtFile {
+    pub fn new(title: &str, data: Vec<u8>) -> Self {
+        let detected = detect(&data);
+        Self {
+            title: title.to_string(),
+            preview: preview(&data, detected),
+            data,
+            detected,
+            encoding: detected,
+        }
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> ReaderResult<Self> {
+        let path = path.as_ref();
+        let title = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        Ok(Self::new(&title, std::fs::read(path)?))
+    }
+
+    pub fn set_encoding(&mut self, encoding: TextEncoding) {
+        self.encoding = encoding;
+        self.preview = preview(&self.data, encoding);
+    }
+
+    pub fn text(&self) -> String {
+        normalise(&decode(&self.data, self.encoding))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "\u{3000}\u{3000}第一章　開始\r\n\r\n\u{3000}\u{3000}他說：「你好，我們走吧。」這是一個很長的故事。\r\n";
+
+    fn encode(text: &str, encoding: &'static Encoding) -> Vec<u8> {
+        encoding.encode(text).0.to_vec()
+    }
+
+    #[test]
+    fn test_detect() {
+        let utf16le: Vec<u8> = TEXT.encode_utf16().flat_map(|u| u.to_le_bytes()).collect();
+        let utf16be: Vec<u8> = TEXT.encode_utf16().flat_map(|u| u.to_be_bytes()).collect();
+        let cases = [
+            (TEXT.as_bytes().to_vec(), TextEncoding::Utf8),
+            ([&[0xef, 0xbb, 0xbf], TEXT.as_bytes()].concat(), TextEncoding::Utf8),
+            ([&[0xff, 0xfe], utf16le.as_slice()].concat(), TextEncoding::Utf16Le),
+            (utf16le, TextEncoding::Utf16Le),
+            (utf16be, TextEncoding::Utf16Be),
+            (encode(TEXT, encoding_rs::GB18030), TextEncoding::Gb18030),
+            (encode(TEXT, encoding_rs::BIG5), TextEncoding::Big5),
+        ];
+        for (data, encoding) in cases {
+            assert_eq!(detect(&data), encoding);
+            assert_eq!(normalise(&decode(&data, encoding)), "第一章 開始\n他說：「你好，我們走吧。」這是一個很長的故事。");
+        }
+        let gbk = encode("第一章 开始\n他说：“你好，我们走吧。”这是一个很长的故事。", encoding_rs::GBK);
+        assert_eq!(detect(&gbk), TextEncoding::Gb18030);
+        let mut file = TxtFile::new("gbk", gbk);
+        assert_eq!((file.detected, file.preview.lines().next()), (TextEncoding::Gb18030, Some("第一章 开始")));
+        file.set_encoding(TextEncoding::Big5);
+        assert_ne!(file.text().lines().next(), Some("第一章 开始"));
+    }
+}
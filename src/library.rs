@@ -0,0 +1,358 @@
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, OptionalExtension, Row, params};
+use tracing::debug;
+
+use crate::error::ReaderResult;
+use crate::make_enum;
+
+make_enum!(LibrarySort, [Title, Author, Collection, Added, Opened, Difficulty]);
+make_enum!(LibraryField, [Author, Url, Collection, Tags]);
+
+/// Share of the words of a text a reader must know for it to be readable
+const COVERAGE: f32 = 0.9;
+/// Difficulty of texts that need words beyond the last HSK level
+pub const BEYOND_HSK: u32 = 8;
+/// Separator of the tags in the library query
+pub(crate) const TAG_SEP: char = '\u{1f}';
+
+/// A document of the library with its metadata
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DocInfo {
+    pub id: u32,
+    pub title: String,
+    pub author: String,
+    pub url: String,
+    pub collection: String,
+    pub tags: Vec<String>,
+    pub added: Option<DateTime<Utc>>,
+    pub opened: Option<DateTime<Utc>>,
+    /// HSK level needed to know most of the words, `BEYOND_HSK` past the last level
+    pub difficulty: Option<u32>,
+}
+
+impl DocInfo {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let tags: Option<String> = row.get(8)?;
+        Ok(Self {
+            id: row.get(0)?,
+            title: row.get(1)?,
+            author: row.get(2)?,
+            url: row.get(3)?,
+            collection: row.get(4)?,
+            added: row.get::<_, Option<i64>>(5)?.and_then(|t| DateTime::from_timestamp(t, 0)),
+            opened: row.get::<_, Option<i64>>(6)?.and_then(|t| DateTime::from_timestamp(t, 0)),
+            difficulty: row.get(7)?,
+            tags: tags.map(|t| t.split(TAG_SEP).map(|s| s.to_string()).collect()).unwrap_or_default(),
+        })
+    }
+
+    pub fn difficulty_label(&self) -> String {
+        match self.difficulty {
+            Some(BEYOND_HSK) => format!("HSK{}+", BEYOND_HSK - 1),
+            Some(d) => format!("HSK{}", d),
+            None => String::new(),
+        }
+    }
+
+    fn cmp_by(&self, other: &Self, sort: LibrarySort) -> Ordering {
+        let text = |a: &str, b: &str| a.to_lowercase().cmp(&b.to_lowercase());
+        match sort {
+            LibrarySort::Title => text(&self.title, &other.title),
+            LibrarySort::Author => text(&self.author, &other.author),
+            LibrarySort::Collection => text(&self.collection, &other.collection),
+            LibrarySort::Added => self.added.cmp(&other.added),
+            LibrarySort::Opened => self.opened.cmp(&other.opened),
+            LibrarySort::Difficulty => self.difficulty.cmp(&other.difficulty),
+        }
+    }
+}
+
+/// Documents shown in the library: the query is matched against the title, author and
+/// source URL, the collection and tag must match exactly
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LibraryFilter {
+    pub query: String,
+    pub collection: Option<String>,
+    pub tag: Option<String>,
+}
+
+impl LibraryFilter {
+    pub fn matches(&self, doc: &DocInfo) -> bool {
+        let query = self.query.trim().to_lowercase();
+        (query.is_empty() || [&doc.title, &doc.author, &doc.url].iter().any(|s| s.to_lowercase().contains(&query)))
+            && self.collection.as_ref().is_none_or(|c| &doc.collection == c)
+            && self.tag.as_ref().is_none_or(|t| doc.tags.contains(t))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+pub fn init_library(conn: &Connection) -> ReaderResult<()> {
+    conn.execute_batch("
+        CREATE TABLE IF NOT EXISTS DocumentInfo (
+            Document INTEGER NOT NULL,
+            Author TEXT NOT NULL DEFAULT '',
+            Url TEXT NOT NULL DEFAULT '',
+            Collection TEXT NOT NULL DEFAULT '',
+            Added INTEGER,
+            Opened INTEGER,
+            Difficulty INTEGER,
+            PRIMARY KEY(Document) );
+        CREATE TABLE IF NOT EXISTS DocumentTags (
+            Document INTEGER NOT NULL,
+            Tag TEXT NOT NULL,
+            PRIMARY KEY(Document, Tag) );
+    ")?;
+    Ok(())
+}
+
+/// Record the date a new document was added
+pub fn set_added(conn: &Connection, document: i64, now: DateTime<Utc>) -> ReaderResult<()> {
+    conn.execute("INSERT INTO DocumentInfo (Document, Added) VALUES (?1, ?2) ON CONFLICT(Document) DO UPDATE SET Added = excluded.Added",
+        params![document, now.timestamp()])?;
+    Ok(())
+}
+
+pub fn set_opened(conn: &Connection, document: u32, now: DateTime<Utc>) -> ReaderResult<()> {
+    conn.execute("INSERT INTO DocumentInfo (Document, Opened) VALUES (?1, ?2) ON CONFLICT(Document) DO UPDATE SET Opened = excluded.Opened",
+        params![document, now.timestamp()])?;
+    Ok(())
+}
+
+pub fn set_difficulty(conn: &Connection, document: u32, difficulty: Option<u32>) -> ReaderResult<()> {
+    conn.execute("INSERT INTO DocumentInfo (Document, Difficulty) VALUES (?1, ?2) ON CONFLICT(Document) DO UPDATE SET Difficulty = excluded.Difficulty",
+        params![document, difficulty])?;
+    Ok(())
+}
+
+/// Estimated difficulty of a document, None when it was not estimated yet
+pub fn get_difficulty(conn: &Connection, document: u32) -> ReaderResult<Option<u32>> {
+    let res: Option<Option<u32>> = conn.query_row("SELECT Difficulty FROM DocumentInfo WHERE Document = ?1", [document],
+        |row| row.get(0)).optional()?;
+    Ok(res.flatten())
+}
+
+/// The document opened last, to be restored on launch
+pub fn last_opened(conn: &Connection) -> ReaderResult<Option<u32>> {
+    let res = conn.query_row("
+        SELECT I.Document FROM DocumentInfo I JOIN Documents D ON D.Id = I.Document
+        WHERE I.Opened IS NOT NULL ORDER BY I.Opened DESC LIMIT 1", [], |row| row.get(0))
+        .optional()?;
+    Ok(res)
+}
+
+/// How a document was left: the sidebar mode and the AI conversation as JSON
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ViewState {
+    pub sidebar: String,
+    pub chat: String,
+}
+
+pub fn init_view_state(conn: &Connection) -> ReaderResult<()> {
+    conn.execute_batch("
+        CREATE TABLE IF NOT EXISTS DocumentViews (
+            Document INTEGER NOT NULL,
+            Sidebar TEXT NOT NULL DEFAULT '',
+            Chat TEXT NOT NULL DEFAULT '',
+            PRIMARY KEY(Document) );
+    ")?;
+    Ok(())
+}
+
+pub fn save_view(conn: &Connection, document: u32, view: &ViewState) -> ReaderResult<()> {
+    conn.execute("INSERT INTO DocumentViews (Document, Sidebar, Chat) VALUES (?1, ?2, ?3)
+        ON CONFLICT(Document) DO UPDATE SET Sidebar = excluded.Sidebar, Chat = excluded.Chat",
+        params![document, view.sidebar, view.chat])?;
+    Ok(())
+}
+
+pub fn get_view(conn: &Connection, document: u32) -> ReaderResult<Option<ViewState>> {
+    let res = conn.query_row("SELECT Sidebar, Chat FROM DocumentViews WHERE Document = ?1", [document],
+        |row| Ok(ViewState { sidebar: row.get(0)?, chat: row.get(1)? }))
+        .optional()?;
+    Ok(res)
+}
+
+/// All documents with their metadata; without an author of its own, a document has the
+/// author of the book it was imported from
+pub fn get_library(conn: &Connection) -> ReaderResult<Vec<DocInfo>> {
+    let mut st = conn.prepare(&format!("
+        SELECT D.Id, D.Title,
+            COALESCE(NULLIF(I.Author, ''), (SELECT Author FROM Imports WHERE Document = D.Id AND Author <> '' LIMIT 1), ''),
+            COALESCE(I.Url, ''), COALESCE(I.Collection, ''), I.Added, I.Opened, I.Difficulty,
+            (SELECT group_concat(Tag, char({})) FROM (SELECT Tag FROM DocumentTags WHERE Document = D.Id ORDER BY Tag))
+        FROM Documents D LEFT JOIN DocumentInfo I ON I.Document = D.Id
+        ORDER BY D.Title", TAG_SEP as u32))?;
+    let res = st.query_map([], DocInfo::from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(res)
+}
+
+/// Save the author, URL, collection and tags of a document
+pub fn save_info(conn: &mut Connection, info: &DocInfo) -> ReaderResult<()> {
+    let tx = conn.transaction()?;
+    tx.execute("INSERT INTO DocumentInfo (Document, Author, Url, Collection) VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT(Document) DO UPDATE SET Author = excluded.Author, Url = excluded.Url, Collection = excluded.Collection",
+        params![info.id, info.author.trim(), info.url.trim(), info.collection.trim()])?;
+    tx.execute("DELETE FROM DocumentTags WHERE Document = ?1", [info.id])?;
+    for tag in &info.tags {
+        tx.execute("INSERT OR IGNORE INTO DocumentTags (Document, Tag) VALUES (?1, ?2)", params![info.id, tag])?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+pub fn add_tag(conn: &mut Connection, documents: &[u32], tag: &str) -> ReaderResult<()> {
+    let tx = conn.transaction()?;
+    for id in documents {
+        tx.execute("INSERT OR IGNORE INTO DocumentTags (Document, Tag) VALUES (?1, ?2)", params![id, tag.trim()])?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+pub fn remove_tag(conn: &mut Connection, documents: &[u32], tag: &str) -> ReaderResult<()> {
+    let tx = conn.transaction()?;
+    for id in documents {
+        tx.execute("DELETE FROM DocumentTags WHERE Document = ?1 AND Tag = ?2", params![id, tag.trim()])?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Move documents to a collection, the empty name takes them out of any
+pub fn move_to(conn: &mut Connection, documents: &[u32], collection: &str) -> ReaderResult<()> {
+    debug!("Move {} documents to {}", documents.len(), collection);
+    let tx = conn.transaction()?;
+    for id in documents {
+        tx.execute("INSERT INTO DocumentInfo (Document, Collection) VALUES (?1, ?2) ON CONFLICT(Document) DO UPDATE SET Collection = excluded.Collection",
+            params![id, collection.trim()])?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Tags written as a comma separated list
+pub fn parse_tags(s: &str) -> Vec<String> {
+    let mut res: Vec<String> = vec![];
+    for tag in s.split([',', '，']).map(|t| t.trim()).filter(|t| !t.is_empty()) {
+        if !res.iter().any(|t| t == tag) {
+            res.push(tag.to_string());
+        }
+    }
+    res
+}
+
+pub fn collections(docs: &[DocInfo]) -> Vec<String> {
+    docs.iter()
+        .filter(|d| !d.collection.is_empty())
+        .map(|d| d.collection.clone())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+pub fn tags(docs: &[DocInfo]) -> Vec<String> {
+    docs.iter()
+        .flat_map(|d| d.tags.iter().cloned())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// Documents passing the filter, sorted; documents without a value come last
+pub fn view<'a>(docs: &'a [DocInfo], filter: &LibraryFilter, sort: LibrarySort, ascending: bool) -> Vec<&'a DocInfo> {
+    let mut res: Vec<&DocInfo> = docs.iter().filter(|d| filter.matches(d)).collect();
+    let missing = |d: &DocInfo| match sort {
+        LibrarySort::Author => d.author.is_empty(),
+        LibrarySort::Collection => d.collection.is_empty(),
+        LibrarySort::Added => d.added.is_none(),
+        LibrarySort::Opened => d.opened.is_none(),
+        LibrarySort::Difficulty => d.difficulty.is_none(),
+        LibrarySort::Title => false,
+    };
+    res.sort_by(|a, b| {
+        missing(a).cmp(&missing(b)).then_with(|| {
+            let ord = a.cmp_by(b, sort);
+            if ascending { ord } else { ord.reverse() }
+        })
+    });
+    res
+}
+
+/// The lowest HSK level covering `COVERAGE` of the words, given the HSK level of every
+/// Chinese word of a text, `None` for words outside HSK
+pub fn difficulty(levels: impl Iterator<Item = Option<u32>>) -> Option<u32> {
+    let mut levels: Vec<u32> = levels.map(|l| l.unwrap_or(BEYOND_HSK)).collect();
+    if levels.is_empty() {
+        return None;
+    }
+    levels.sort_unstable();
+    let ix = ((levels.len() as f32 * COVERAGE).ceil() as usize).clamp(1, levels.len()) - 1;
+    Some(levels[ix])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_library() {
+        let mut conn = crate::scraper::db::init_db(":memory:").unwrap();
+        let a = crate::textbase::save_text(&mut conn, 0, "乙", "你好").unwrap() as u32;
+        let b = crate::textbase::save_text(&mut conn, 0, "甲", "再见").unwrap() as u32;
+        let book = crate::import::save_documents(&mut conn, "urn:book", "鲁迅", false, &[(String::from("丙"), String::from("故乡"))]).unwrap();
+        let c = book.ids[0] as u32;
+
+        save_info(&mut conn, &DocInfo { id: a, author: String::from("老舍"), url: String::from("https://example.com"),
+            tags: parse_tags("小说, 短篇，小说"), ..Default::default() }).unwrap();
+        add_tag(&mut conn, &[b, c], "短篇").unwrap();
+        remove_tag(&mut conn, &[a], "短篇").unwrap();
+        move_to(&mut conn, &[a, c], "书架").unwrap();
+        assert_eq!(get_difficulty(&conn, b).unwrap(), None);
+        set_difficulty(&conn, b, Some(3)).unwrap();
+        assert_eq!(get_difficulty(&conn, b).unwrap(), Some(3));
+
+        let docs = get_library(&conn).unwrap();
+        let doc = |id| docs.iter().find(|d| d.id == id).unwrap();
+        assert_eq!((doc(a).author.as_str(), doc(a).tags.clone()), ("老舍", vec![String::from("小说")]));
+        assert_eq!((doc(c).author.as_str(), doc(c).collection.as_str()), ("鲁迅", "书架"));
+        assert!(doc(a).added.is_some() && doc(a).opened.is_none());
+        assert_eq!((doc(b).difficulty_label(), collections(&docs), tags(&docs)), (String::from("HSK3"), vec![String::from("书架")], vec![String::from("小说"), String::from("短篇")]));
+
+        let titles = |v: Vec<&DocInfo>| v.iter().map(|d| d.title.clone()).collect::<Vec<_>>();
+        let shelf = LibraryFilter { collection: Some(String::from("书架")), ..Default::default() };
+        assert_eq!(titles(view(&docs, &shelf, LibrarySort::Title, true)), vec!["丙", "乙"]);
+        let short = LibraryFilter { tag: Some(String::from("短篇")), query: String::from("甲"), ..Default::default() };
+        assert_eq!(titles(view(&docs, &short, LibrarySort::Title, true)), vec!["甲"]);
+        // Documents without a difficulty come last in both directions
+        assert_eq!(titles(view(&docs, &LibraryFilter::default(), LibrarySort::Difficulty, false))[0], "甲");
+
+        assert_eq!(last_opened(&conn).unwrap(), None);
+        set_opened(&conn, a, DateTime::from_timestamp(100, 0).unwrap()).unwrap();
+        set_opened(&conn, c, DateTime::from_timestamp(200, 0).unwrap()).unwrap();
+        assert_eq!(last_opened(&conn).unwrap(), Some(c));
+        let state = ViewState { sidebar: String::from("AI"), chat: String::from("[]") };
+        save_view(&conn, c, &ViewState::default()).unwrap();
+        save_view(&conn, c, &state).unwrap();
+        assert_eq!((get_view(&conn, c).unwrap(), get_view(&conn, a).unwrap()), (Some(state), None));
+
+        crate::textbase::delete_documents(&mut conn, &[a, c]).unwrap();
+        assert_eq!((last_opened(&conn).unwrap(), get_view(&conn, c).unwrap()), (None, None));
+        assert_eq!(titles(get_library(&conn).unwrap().iter().collect()), vec!["甲"]);
+    }
+
+    #[test]
+    fn test_difficulty() {
+        assert_eq!(difficulty(std::iter::empty()), None);
+        let levels = [Some(1); 9].into_iter().chain([None]);
+        assert_eq!(difficulty(levels), Some(1));
+        let levels = [Some(1); 8].into_iter().chain([Some(4), None]);
+        assert_eq!(difficulty(levels), Some(4));
+    }
+}
@@ -11,6 +11,19 @@ mod error;
 mod ai;
 mod scraper;
 mod anki;
+mod grammar;
+mod search;
+mod srs;
+mod cloze;
+mod import;
+mod library;
+mod reading;
+mod anchor;
+mod notes;
+mod pdf;
+mod html;
+mod diff;
+mod revisions;
 
 #[cfg(debug_assertions)]
 use tracing::Level;
@@ -39,19 +52,28 @@ pub fn run(theme: &str) -> Result<(), iced::Error> {
         if theme.to_string() == e.to_string() {
             return iced::application(gui::App::new, gui::App::update, gui::App::view)
                 .theme(gui::App::theme)
-                .window_size(iced::Size::new(1500.0,850.0))
+                .window(window_settings())
                 .subscription(gui::App::subscription)
                 .font(FONT)
                 .run();
         }
     }
-    iced::application(gui::App::default, gui::App::update, gui::App::view)
+    iced::application(gui::App::new, gui::App::update, gui::App::view)
         .subscription(gui::App::subscription)
-        .window_size(iced::Size::new(1500.0,850.0))
+        .window(window_settings())
         .font(FONT)
         .run()
 }
 
+/// The app saves the reading position before it closes the window
+fn window_settings() -> iced::window::Settings {
+    iced::window::Settings {
+        size: iced::Size::new(1500.0, 850.0),
+        exit_on_close_request: false,
+        ..Default::default()
+    }
+}
+
 fn main() {
     #[cfg(debug_assertions)]
     let env_rust_log = Level::DEBUG;
@@ -0,0 +1,226 @@
+use std::collections::{BTreeSet, HashMap, HashSet, hash_map::Entry};
+
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use rusqlite::{Connection, params};
+
+use crate::anchor::byte_offset;
+use crate::error::ReaderResult;
+use crate::library::TAG_SEP;
+use crate::textbase::{Note, get_content, sentence_at};
+
+/// A note of any document, as listed in the notes browser
+#[derive(Debug, Clone, Default)]
+pub struct NoteEntry {
+    pub note: Note,
+    pub title: String,
+    pub tags: Vec<String>,
+    /// Unknown for notes saved before the date was kept
+    pub created: Option<DateTime<Utc>>,
+    /// Sentence of the document the note is anchored to
+    pub sentence: String,
+}
+
+impl NoteEntry {
+    pub fn day(&self) -> Option<NaiveDate> {
+        self.created.map(|c| c.with_timezone(&Local).date_naive())
+    }
+}
+
+/// Notes shown in the browser: the query is looked up in the note text through the
+/// full-text index and matched against the highlighted text, sentence, title and tags
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NotesFilter {
+    pub query: String,
+    pub document: Option<u32>,
+    pub tag: Option<String>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+impl NotesFilter {
+    /// `hits` are the notes found by `search::note_hits` for the query
+    pub fn matches(&self, entry: &NoteEntry, hits: &HashSet<u32>) -> bool {
+        let query = self.query.trim().to_lowercase();
+        (query.is_empty() || hits.contains(&entry.note.id)
+            || [&entry.note.anchor.quote, &entry.sentence, &entry.title].into_iter().chain(entry.tags.iter())
+                .any(|s| s.to_lowercase().contains(&query)))
+            && self.document.is_none_or(|d| entry.note.doc == d)
+            && self.tag.as_ref().is_none_or(|t| entry.tags.contains(t))
+            && self.from.is_none_or(|from| entry.day().is_some_and(|d| d >= from))
+            && self.to.is_none_or(|to| entry.day().is_some_and(|d| d <= to))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+pub fn view<'a>(entries: &'a [NoteEntry], filter: &NotesFilter, hits: &HashSet<u32>) -> Vec<&'a NoteEntry> {
+    entries.iter().filter(|e| filter.matches(e, hits)).collect()
+}
+
+/// Notes get tags and the date they were written
+pub fn init_note_tags(conn: &Connection) -> ReaderResult<()> {
+    conn.execute_batch("
+        ALTER TABLE Notes ADD COLUMN Created INTEGER;
+        CREATE TABLE IF NOT EXISTS NoteTags (
+            Note INTEGER NOT NULL,
+            Tag TEXT NOT NULL,
+            PRIMARY KEY(Note, Tag) );
+    ")?;
+    Ok(())
+}
+
+/// Sentence at the start of a note; a note at the end of a line belongs to the last one
+fn anchored_sentence(content: &str, note: &Note) -> String {
+    let line = content.lines().nth(note.line as usize).unwrap_or_default();
+    let pos = byte_offset(line, 0, note.char as usize);
+    sentence_at(line, pos)
+        .or_else(|| sentence_at(line, pos.saturating_sub(1)))
+        .map(|(_, s)| s.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// Notes of every document, or of one, ordered by document title and position
+pub fn get_entries(conn: &Connection, document: Option<u32>) -> ReaderResult<Vec<NoteEntry>> {
+    let mut st = conn.prepare(&format!("
+        SELECT N.Id, N.Line, N.Character, N.Document, N.Content, N.EndLine, N.EndCharacter, N.Color, N.Quote, N.Prefix, N.Suffix,
+            D.Title, N.Created,
+            (SELECT group_concat(Tag, char({})) FROM (SELECT Tag FROM NoteTags WHERE Note = N.Id ORDER BY Tag))
+        FROM Notes N JOIN Documents D ON D.Id = N.Document
+        WHERE ?1 IS NULL OR N.Document = ?1
+        ORDER BY D.Title, N.Document, N.Line, N.Character", TAG_SEP as u32))?;
+    let mut entries = st.query_map([document], |row| {
+        let tags: Option<String> = row.get(13)?;
+        Ok(NoteEntry {
+            note: Note::from_row(row)?,
+            title: row.get(11)?,
+            created: row.get::<_, Option<i64>>(12)?.and_then(|t| DateTime::from_timestamp(t, 0)),
+            tags: tags.map(|t| t.split(TAG_SEP).map(|s| s.to_string()).collect()).unwrap_or_default(),
+            sentence: String::new(),
+        })
+    })?.collect::<Result<Vec<_>, _>>()?;
+
+    let mut contents: HashMap<u32, String> = HashMap::new();
+    for entry in entries.iter_mut() {
+        let content = match contents.entry(entry.note.doc) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(get_content(conn, entry.note.doc)?.unwrap_or_default()),
+        };
+        entry.sentence = anchored_sentence(content, &entry.note);
+    }
+    Ok(entries)
+}
+
+pub fn add_tag(conn: &mut Connection, notes: &[u32], tag: &str) -> ReaderResult<()> {
+    let tx = conn.transaction()?;
+    for id in notes {
+        tx.execute("INSERT OR IGNORE INTO NoteTags (Note, Tag) VALUES (?1, ?2)", params![id, tag.trim()])?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+pub fn remove_tag(conn: &mut Connection, notes: &[u32], tag: &str) -> ReaderResult<()> {
+    let tx = conn.transaction()?;
+    for id in notes {
+        tx.execute("DELETE FROM NoteTags WHERE Note = ?1 AND Tag = ?2", params![id, tag.trim()])?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+pub fn tags(entries: &[NoteEntry]) -> Vec<String> {
+    entries.iter()
+        .flat_map(|e| e.tags.iter().cloned())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// Markdown with a section per document: every note comes after the sentence it is
+/// anchored to, with the highlighted words in bold, and ends with its tags and date
+pub fn export(entries: &[&NoteEntry]) -> String {
+    let mut documents: Vec<(u32, &str, Vec<&NoteEntry>)> = vec![];
+    for entry in entries {
+        match documents.iter_mut().find(|(id, ..)| *id == entry.note.doc) {
+            Some((.., notes)) => notes.push(entry),
+            None => documents.push((entry.note.doc, entry.title.as_str(), vec![entry])),
+        }
+    }
+
+    let mut res = String::new();
+    for (_, title, notes) in documents {
+        res.push_str(&format!("## {}\n\n", title));
+        for entry in notes {
+            let quote = entry.note.anchor.quote.trim();
+            let context = if !entry.note.is_range() || quote.is_empty() {
+                entry.sentence.clone()
+            } else if entry.sentence.contains(quote) {
+                entry.sentence.replacen(quote, &format!("**{}**", quote), 1)
+            } else {
+                format!("**{}**", quote)
+            };
+            if !context.is_empty() {
+                res.push_str(&format!("> {}\n\n", context));
+            }
+            if !entry.note.text.trim().is_empty() {
+                res.push_str(&format!("{}\n\n", entry.note.text.trim()));
+            }
+            let footer: Vec<String> = entry.tags.iter().map(|t| format!("#{}", t))
+                .chain(entry.day().map(|d| d.format("%Y-%m-%d").to_string()))
+                .collect();
+            if !footer.is_empty() {
+                res.push_str(&format!("*{}*\n\n", footer.join(" ")));
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::textbase::{save_note, save_text};
+
+    #[test]
+    fn test_notes() {
+        let mut conn = crate::scraper::db::init_db(":memory:").unwrap();
+        let text = "我冒了严寒，回到相隔二千余里的故乡去。时候既然是深冬。\n渐近故乡时，天气又阴晦了。";
+        let a = save_text(&mut conn, 0, "故乡", text).unwrap() as u32;
+        let b = save_text(&mut conn, 0, "社戏", "我在倒数上去的二十年中，只看过两回中国戏。").unwrap() as u32;
+        let notes = [
+            Note { doc: a, line: 0, char: 15, end_line: 0, end_char: 17, text: String::from("hometown"), ..Default::default() }.anchored(text),
+            Note { doc: a, line: 1, char: 13, text: String::from("gloomy"), ..Default::default() },
+            Note { doc: b, line: 0, char: 2, text: String::from("Beijing opera"), ..Default::default() },
+        ];
+        let ids: Vec<u32> = notes.iter().map(|n| save_note(&mut conn, n).unwrap() as u32).collect();
+        add_tag(&mut conn, &[ids[0], ids[2]], "鲁迅").unwrap();
+        add_tag(&mut conn, &[ids[0]], "词").unwrap();
+        remove_tag(&mut conn, &[ids[0]], "词").unwrap();
+
+        let entries = get_entries(&conn, None).unwrap();
+        assert_eq!(entries.iter().map(|e| e.note.id).collect::<Vec<_>>(), vec![ids[0], ids[1], ids[2]]);
+        assert_eq!((entries[0].sentence.as_str(), entries[0].tags.clone()), ("我冒了严寒，回到相隔二千余里的故乡去。", vec![String::from("鲁迅")]));
+        assert_eq!(entries[1].sentence, "渐近故乡时，天气又阴晦了。");
+        assert_eq!(tags(&entries), vec![String::from("鲁迅")]);
+
+        let today = Local::now().date_naive();
+        let shown = |filter: &NotesFilter| {
+            let hits = crate::search::note_hits(&conn, &filter.query).unwrap();
+            view(&entries, filter, &hits).iter().map(|e| e.note.id).collect::<Vec<_>>()
+        };
+        assert_eq!(shown(&NotesFilter { query: String::from("GLOOM"), ..Default::default() }), vec![ids[1]]);
+        assert_eq!(shown(&NotesFilter { query: String::from("社戏"), ..Default::default() }), vec![ids[2]]);
+        assert_eq!(shown(&NotesFilter { document: Some(a), tag: Some(String::from("鲁迅")), ..Default::default() }), vec![ids[0]]);
+        assert!(shown(&NotesFilter { from: today.succ_opt(), ..Default::default() }).is_empty());
+        assert_eq!(shown(&NotesFilter { from: Some(today), to: Some(today), ..Default::default() }).len(), 3);
+
+        let md = export(&entries.iter().take(2).collect::<Vec<_>>());
+        assert_eq!(md, format!("## 故乡\n\n> 我冒了严寒，回到相隔二千余里的**故乡**去。\n\nhometown\n\n*#鲁迅 {0}*\n\n> 渐近故乡时，天气又阴晦了。\n\ngloomy\n\n*{0}*\n\n", today.format("%Y-%m-%d")));
+
+        crate::textbase::delete_documents(&mut conn, &[a]).unwrap();
+        let left: i64 = conn.query_row("SELECT count(*) FROM NoteTags", [], |row| row.get(0)).unwrap();
+        assert_eq!((get_entries(&conn, None).unwrap().len(), left), (1, 1));
+    }
+}
@@ -18,6 +18,27 @@ pub const REC_FILE: &str = "rec.onnx";//"model.onnx";
 pub const DET_FILE: &str = "det.onnx";//"PP-OCRv5_server_det_infer.onnx";
 pub const INDEX: &str = "dict 2.txt";
 
+/// Detection and recognition models of a model directory
+pub fn load_models(model_path: &str) -> ReaderResult<(Det, Rec)> {
+    let det = Det::from_file(model_path.to_owned() + DET_FILE)?;
+    let rec = Rec::from_file(model_path.to_owned() + REC_FILE, model_path.to_owned() + INDEX)?.with_min_score(REC_MIN);
+    Ok((det, rec))
+}
+
+/// Text lines of an image with the mean score of their characters
+pub fn ocr_lines(det: &mut Det, rec: &mut Rec, img: &image::DynamicImage) -> ReaderResult<Vec<(String, f32)>> {
+    let mut res = vec![];
+    for sub in det.find_text_img(img)? {
+        let chars = rec.predict_char_score(&sub)?;
+        if chars.is_empty() {
+            continue;
+        }
+        let score = chars.iter().map(|(_, s)| s).sum::<f32>() / chars.len() as f32;
+        res.push((chars.into_iter().map(|(c, _)| c).collect(), score));
+    }
+    Ok(res)
+}
+
 pub async fn ocr_i(model_path: &str, content: Arc<RwLock<Vec<u8>>>) -> ReaderResult<String> {
     let det_path = model_path.to_owned()+DET_FILE;
     let keys = model_path.to_owned() + INDEX;
@@ -0,0 +1,334 @@
+use crate::error::{ReaderError, ReaderResult};
+use crate::utils::is_chinese_char;
+
+/// A4 in points
+pub const PAGE_WIDTH: f32 = 595.0;
+pub const PAGE_HEIGHT: f32 = 842.0;
+pub const MARGIN: f32 = 50.0;
+
+pub const TEXT_SIZE: f32 = 12.0;
+pub const TITLE_SIZE: f32 = 18.0;
+pub const NOTE_SIZE: f32 = 9.0;
+const LINE_SPACING: f32 = 1.5;
+/// Pinyin and footnote numbers, relative to the size of the text
+pub const RUBY_SCALE: f32 = 0.5;
+pub const MARK_SCALE: f32 = 0.6;
+
+/// Space between the text and the footnotes of a page
+const NOTES_GAP: f32 = 12.0;
+
+/// Characters that should not start a line
+const CLOSING: &str = "，。、；：？！）》」』】”’…,.;:?!)]";
+
+fn is_wide(c: char) -> bool {
+    is_chinese_char(&c)
+        || ('\u{3000}'..='\u{303f}').contains(&c)
+        || ('\u{ff00}'..='\u{ffef}').contains(&c)
+        || ('\u{2018}'..='\u{201f}').contains(&c)
+        || c == '…' || c == '—'
+}
+
+/// Estimated width of a character in ems: Chinese characters are square,
+/// other characters of a proportional font take about half as much
+fn estimated_width(c: char) -> f32 {
+    if is_wide(c) {
+        1.0
+    } else if c.is_whitespace() {
+        0.3
+    } else {
+        0.55
+    }
+}
+
+/// Advance widths of the embedded font. Without a font, or for characters
+/// the font lacks, the widths are estimated
+#[derive(Default)]
+pub struct Metrics<'a> {
+    face: Option<ttf_parser::Face<'a>>,
+}
+
+impl<'a> Metrics<'a> {
+    pub fn new(font: &'a [u8]) -> ReaderResult<Self> {
+        let face = ttf_parser::Face::parse(font, 0)
+            .map_err(|e| ReaderError::Other(format!("Cannot read the PDF font: {}", e)))?;
+        Ok(Self { face: Some(face) })
+    }
+
+    /// Width of a character in ems
+    pub fn char_width(&self, c: char) -> f32 {
+        self.face.as_ref()
+            .and_then(|face| {
+                let advance = face.glyph_hor_advance(face.glyph_index(c)?)?;
+                Some(advance as f32 / face.units_per_em() as f32)
+            })
+            .unwrap_or_else(|| estimated_width(c))
+    }
+
+    pub fn text_width(&self, s: &str, size: f32) -> f32 {
+        s.chars().map(|c| self.char_width(c)).sum::<f32>() * size
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    /// Text with the pinyin shown above it
+    Text(String, Option<String>),
+    /// Number of a footnote, raised after the text
+    Mark(usize),
+}
+
+impl Token {
+    pub fn text(s: &str) -> Self {
+        Self::Text(s.to_string(), None)
+    }
+
+    pub fn width(&self, size: f32, metrics: &Metrics) -> f32 {
+        match self {
+            Self::Text(t, ruby) => {
+                let ruby_size = size * RUBY_SCALE;
+                let ruby = ruby.as_ref().map(|r| metrics.text_width(r, ruby_size) + ruby_size).unwrap_or_default();
+                metrics.text_width(t, size).max(ruby)
+            }
+            Self::Mark(n) => metrics.text_width(&n.to_string(), size * MARK_SCALE),
+        }
+    }
+
+    fn is_space(&self) -> bool {
+        matches!(self, Self::Text(t, _) if t.chars().all(char::is_whitespace))
+    }
+
+    fn is_closing(&self) -> bool {
+        match self {
+            Self::Text(t, _) => t.chars().next().is_some_and(|c| CLOSING.contains(c)),
+            Self::Mark(_) => true,
+        }
+    }
+}
+
+/// Tokens a line can be broken between: each Chinese character,
+/// and runs of spaces or of other characters
+pub fn tokens(text: &str) -> Vec<Token> {
+    let mut res: Vec<Token> = vec![];
+    let mut run = String::new();
+    for c in text.chars() {
+        let joins = !is_wide(c) && run.chars().last().is_some_and(|l| !is_wide(l) && l.is_whitespace() == c.is_whitespace());
+        if !joins && !run.is_empty() {
+            res.push(Token::Text(std::mem::take(&mut run), None));
+        }
+        run.push(c);
+    }
+    if !run.is_empty() {
+        res.push(Token::Text(run, None));
+    }
+    res
+}
+
+/// Breaks tokens into lines of `width`, giving each its distance from the start of the line.
+/// Closing punctuation and footnote numbers stay at the end of a line even if it overflows
+pub fn wrap(tokens: Vec<Token>, size: f32, width: f32, metrics: &Metrics) -> Vec<Vec<(f32, Token)>> {
+    let tokens = tokens.into_iter().flat_map(|token| match token {
+        // A word longer than a line is cut anywhere
+        Token::Text(t, None) if metrics.text_width(&t, size) > width => t.chars().map(|c| Token::Text(c.to_string(), None)).collect(),
+        token => vec![token],
+    });
+    let mut lines = vec![];
+    let mut line: Vec<(f32, Token)> = vec![];
+    let mut x = 0.0;
+    for token in tokens {
+        let w = token.width(size, metrics);
+        if x + w > width && !line.is_empty() && !token.is_closing() {
+            lines.push(std::mem::take(&mut line));
+            x = 0.0;
+            if token.is_space() {
+                continue;
+            }
+        }
+        line.push((x, token));
+        x += w;
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// A paragraph
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub tokens: Vec<Token>,
+    pub size: f32,
+    pub gray: bool,
+}
+
+impl Block {
+    pub fn new(tokens: Vec<Token>, size: f32) -> Self {
+        Self { tokens, size, gray: false }
+    }
+
+    pub fn gray(self) -> Self {
+        Self { gray: true, ..self }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Line {
+    pub items: Vec<(f32, Token)>,
+    pub size: f32,
+    pub gray: bool,
+}
+
+impl Line {
+    fn has_ruby(&self) -> bool {
+        self.items.iter().any(|(_, t)| matches!(t, Token::Text(_, Some(_))))
+    }
+
+    /// Space above the text taken by pinyin
+    pub fn ruby_height(&self) -> f32 {
+        if self.has_ruby() { self.size * RUBY_SCALE * 1.2 } else { 0.0 }
+    }
+
+    pub fn height(&self) -> f32 {
+        self.size * LINE_SPACING + self.ruby_height()
+    }
+
+    fn marks(&self) -> impl Iterator<Item = usize> + '_ {
+        self.items.iter().filter_map(|(_, t)| match t {
+            Token::Mark(n) => Some(*n),
+            _ => None,
+        })
+    }
+}
+
+/// Lines with the height of their baseline from the bottom of the page
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Page {
+    pub lines: Vec<(f32, Line)>,
+}
+
+fn lines(tokens: Vec<Token>, size: f32, gray: bool, metrics: &Metrics) -> Vec<Line> {
+    let width = PAGE_WIDTH - 2.0 * MARGIN;
+    let mut res: Vec<Line> = wrap(tokens, size, width, metrics).into_iter()
+        .map(|items| Line { items, size, gray })
+        .collect();
+    // An empty paragraph still takes a line
+    if res.is_empty() {
+        res.push(Line { size, gray, ..Default::default() });
+    }
+    res
+}
+
+/// Places the blocks on pages. A footnote goes at the bottom of the page with its number,
+/// `footnote` gives the text of a number
+pub fn paginate(blocks: Vec<Block>, metrics: &Metrics, footnote: impl Fn(usize) -> Option<String>) -> Vec<Page> {
+    let mut pages = vec![];
+    let mut page = Page::default();
+    let mut notes: Vec<Line> = vec![];
+    let mut top = PAGE_HEIGHT - MARGIN;
+
+    let finish = |page: &mut Page, notes: &mut Vec<Line>| {
+        let mut y = MARGIN + notes.iter().map(|l| l.height()).sum::<f32>();
+        for line in notes.drain(..) {
+            y -= line.height();
+            page.lines.push((y + line.size * (LINE_SPACING - 1.0), line));
+        }
+        std::mem::take(page)
+    };
+
+    for block in blocks {
+        let gap = block.size * 0.4;
+        for line in lines(block.tokens, block.size, block.gray, metrics) {
+            let new_notes: Vec<Line> = line.marks()
+                .filter_map(|n| footnote(n).map(|text| (n, text)))
+                .flat_map(|(n, text)| {
+                    let tokens = std::iter::once(Token::Mark(n)).chain(self::tokens(&text.replace('\n', " "))).collect();
+                    lines(tokens, NOTE_SIZE, false, metrics)
+                })
+                .collect();
+            let notes_height = |notes: &[Line]| {
+                let h: f32 = notes.iter().map(|l| l.height()).sum();
+                if h > 0.0 { h + NOTES_GAP } else { 0.0 }
+            };
+            let needed = line.height() + notes_height(&[notes.as_slice(), new_notes.as_slice()].concat());
+            if top - needed < MARGIN && !page.lines.is_empty() {
+                pages.push(finish(&mut page, &mut notes));
+                top = PAGE_HEIGHT - MARGIN;
+            }
+            top -= line.height();
+            page.lines.push((top + line.size * (LINE_SPACING - 1.0), line));
+            notes.extend(new_notes);
+        }
+        top -= gap;
+    }
+    if !page.lines.is_empty() || !notes.is_empty() {
+        pages.push(finish(&mut page, &mut notes));
+    }
+    if pages.is_empty() {
+        pages.push(Page::default());
+    }
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(line: &[(f32, Token)]) -> String {
+        line.iter().map(|(_, t)| match t {
+            Token::Text(t, _) => t.clone(),
+            Token::Mark(n) => format!("[{}]", n),
+        }).collect()
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(tokens("你好 hello  world!"), ["你", "好", " ", "hello", "  ", "world!"].map(Token::text).to_vec());
+
+        let metrics = Metrics::default();
+        let lines = wrap(tokens("我冒了严寒，回到相隔二千余里"), 10.0, 50.0, &metrics);
+        assert_eq!(lines.iter().map(|l| text(l)).collect::<Vec<_>>(), vec!["我冒了严寒，", "回到相隔二", "千余里"]);
+        assert_eq!(lines[1].iter().map(|(x, _)| *x).collect::<Vec<_>>(), vec![0.0, 10.0, 20.0, 30.0, 40.0]);
+
+        // Spaces are dropped at line breaks, words too long for a line are cut
+        let lines = wrap(tokens("one two threefourfive"), 10.0, 40.0, &metrics);
+        assert_eq!(lines.iter().map(|l| text(l)).collect::<Vec<_>>(), vec!["one two ", "threefo", "urfive"]);
+
+        let ruby = vec![Token::Text(String::from("故乡"), Some(String::from("gùxiāng"))), Token::Mark(1)];
+        assert_eq!(ruby[0].width(10.0, &metrics), 5.0 * 7.0 * 0.55 + 5.0);
+        assert_eq!(wrap(ruby, 10.0, 20.0, &metrics).len(), 1);
+    }
+
+    #[test]
+    fn test_metrics() {
+        let font = std::fs::read("SymbolsNerdFont-Regular.ttf").unwrap();
+        let metrics = Metrics::new(&font).unwrap();
+        let face = ttf_parser::Face::parse(&font, 0).unwrap();
+        let advance = face.glyph_hor_advance(face.glyph_index('\u{f00be}').unwrap()).unwrap();
+        assert_eq!(metrics.char_width('\u{f00be}'), advance as f32 / face.units_per_em() as f32);
+        // Characters missing from the font are estimated
+        assert_eq!(metrics.text_width("我们", 10.0), 20.0);
+        assert!(Metrics::new(b"not a font").is_err());
+    }
+
+    #[test]
+    fn test_paginate() {
+        let long = "我".repeat(3000);
+        let blocks = vec![
+            Block::new(tokens("故乡"), TITLE_SIZE),
+            Block::new([tokens("回到"), vec![Token::Mark(1)], tokens(&long)].concat(), TEXT_SIZE),
+            Block::new(vec![], TEXT_SIZE),
+            Block::new(vec![Token::text("完"), Token::Mark(2)], TEXT_SIZE),
+        ];
+        let pages = paginate(blocks, &Metrics::default(), |n| Some(format!("note {}", n)));
+        assert_eq!(pages.len(), 2);
+        for page in &pages {
+            assert!(page.lines.iter().all(|(y, _)| *y >= MARGIN && *y < PAGE_HEIGHT - MARGIN));
+        }
+        // The footnote is on the page of its number, below the text
+        let footnotes = |page: &Page| page.lines.iter().filter(|(_, l)| l.size == NOTE_SIZE).map(|(_, l)| text(&l.items)).collect::<Vec<_>>();
+        assert_eq!(footnotes(&pages[0]), vec!["[1]note 1"]);
+        assert_eq!(footnotes(&pages[1]), vec!["[2]note 2"]);
+        let last = &pages[1].lines;
+        assert!(last[last.len() - 1].0 < last[last.len() - 2].0);
+        assert_eq!(text(&last[last.len() - 2].1.items), "完[2]");
+    }
+}
@@ -0,0 +1,239 @@
+mod layout;
+
+use std::path::PathBuf;
+use printpdf::*;
+use rusqlite::Connection;
+use crate::cedict::Cedict;
+use crate::notes::NoteEntry;
+use crate::textbase::{Note, get_notes};
+use crate::error::{ReaderError, ReaderResult};
+use layout::{Block, Metrics, Token, MARGIN, PAGE_HEIGHT, PAGE_WIDTH, TEXT_SIZE, TITLE_SIZE, NOTE_SIZE, RUBY_SCALE, MARK_SCALE};
+
+const BLACK: printpdf::Op = Op::SetFillColor { col: Color::Rgb(Rgb { r: 0.0, g: 0.0, b: 0.0, icc_profile: None }) };
+const GRAY: printpdf::Op = Op::SetFillColor { col: Color::Rgb(Rgb { r: 0.4, g: 0.4, b: 0.4, icc_profile: None }) };
+
+/// Fonts with Chinese characters, used when none is set in the config
+const FONTS: [&str; 9] = [
+    "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/google-noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/truetype/wqy/wqy-microhei.ttc",
+    "/usr/share/fonts/wenquanyi/wqy-microhei/wqy-microhei.ttc",
+    "/System/Library/Fonts/STHeiti Light.ttc",
+    "/Library/Fonts/Arial Unicode.ttf",
+    "C:\\Windows\\Fonts\\msyh.ttc",
+    "C:\\Windows\\Fonts\\simsun.ttc",
+];
+
+/// What the PDF of a document has besides its text
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PdfOptions {
+    /// Pinyin above the words
+    pub pinyin: bool,
+    /// Notes as footnotes
+    pub notes: bool,
+    /// Words of the text not in Anki, at the end
+    pub glossary: bool,
+}
+
+/// Font embedded in the PDF, `path` from the config or the first system font found
+pub fn load_font(path: Option<&str>) -> ReaderResult<Vec<u8>> {
+    let path = match path.filter(|p| !p.trim().is_empty()) {
+        Some(p) => PathBuf::from(p),
+        None => FONTS.iter()
+            .map(PathBuf::from)
+            .find(|p| p.exists())
+            .ok_or(ReaderError::other("No font with Chinese characters found, set pdf_font in app.toml"))?,
+    };
+    Ok(std::fs::read(path)?)
+}
+
+/// Tokens of a line, with the footnote numbers after the characters they follow
+fn line_tokens(line: &str, marks: &[(usize, usize)], cedict: Option<&Cedict>) -> Vec<Token> {
+    let segment = |piece: &str| -> Vec<Token> {
+        match cedict {
+            Some(cedict) => cedict.segment(piece).into_iter()
+                .flat_map(|(word, entry)| match entry {
+                    Some(e) if word.chars().any(|c| crate::utils::is_chinese_char(&c)) =>
+                        vec![Token::Text(word.to_string(), Some(e.pinyin().to_string()))],
+                    _ => layout::tokens(word),
+                })
+                .collect(),
+            None => layout::tokens(piece),
+        }
+    };
+    let mut res = vec![];
+    let mut start = 0;
+    for (column, number) in marks {
+        let end = crate::anchor::byte_offset(line, 0, *column).max(start);
+        res.extend(segment(&line[start..end]));
+        res.push(Token::Mark(*number));
+        start = end;
+    }
+    res.extend(segment(&line[start..]));
+    res
+}
+
+/// What a PDF is made of, read from the database so the layout can run on another thread
+pub enum PdfSource {
+    Document { title: String, content: String, notes: Vec<Note> },
+    Notes { title: String, entries: Vec<NoteEntry> },
+}
+
+impl PdfSource {
+    pub fn document(conn: &Connection, doc_id: u32, options: PdfOptions) -> ReaderResult<Self> {
+        let (title, content): (String, String) = conn.query_row("SELECT Title, Content FROM Documents WHERE Id = ?1", [doc_id],
+            |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let mut notes: Vec<Note> = if options.notes { get_notes(conn, doc_id)? } else { vec![] };
+        notes.retain(|n| !n.text.trim().is_empty());
+        // Numbers go after highlights, or where the note is
+        notes.sort_by_key(|n| n.end());
+        Ok(Self::Document { title, content, notes })
+    }
+
+    pub fn notes(conn: &Connection, doc_id: u32) -> ReaderResult<Self> {
+        let title: String = conn.query_row("SELECT Title FROM Documents WHERE Id = ?1", [doc_id], |row| row.get(0))?;
+        Ok(Self::Notes { title, entries: crate::notes::get_entries(conn, Some(doc_id))? })
+    }
+
+    pub fn render(&self, cedict: Option<&Cedict>, font: &[u8], options: PdfOptions) -> ReaderResult<Vec<u8>> {
+        let metrics = Metrics::new(font)?;
+        match self {
+            Self::Document { title, content, notes } => {
+                let pages = document_pages(title, content, notes, cedict, options, &metrics);
+                render(title, &pages, font, &metrics)
+            }
+            Self::Notes { title, entries } => {
+                let pages = notes_pages(title, entries, &metrics);
+                render(&format!("{}: {}", t!("to_notes"), title), &pages, font, &metrics)
+            }
+        }
+    }
+}
+
+fn document_pages(title: &str, content: &str, notes: &[Note], cedict: Option<&Cedict>, options: PdfOptions, metrics: &Metrics) -> Vec<layout::Page> {
+    let ruby = if options.pinyin { cedict } else { None };
+    let mut blocks = vec![Block::new(layout::tokens(title), TITLE_SIZE)];
+    for (ix, line) in content.lines().enumerate() {
+        let marks: Vec<(usize, usize)> = notes.iter().enumerate()
+            .filter(|(_, n)| n.end().0 as usize == ix)
+            .map(|(number, n)| (n.end().1 as usize, number + 1))
+            .collect();
+        blocks.push(Block::new(line_tokens(line, &marks, ruby), TEXT_SIZE));
+    }
+
+    if options.glossary && let Some(cedict) = cedict {
+        let words = cedict.unknown_words(content);
+        if !words.is_empty() {
+            blocks.push(Block::new(vec![], TEXT_SIZE));
+            blocks.push(Block::new(layout::tokens(&t!("pdf_glossary")), TITLE_SIZE));
+            blocks.extend(words.into_iter().map(|e| Block::new(
+                layout::tokens(&format!("{} {} — {}", e.simplified(), e.pinyin(), e.gloss())), NOTE_SIZE)));
+        }
+    }
+
+    layout::paginate(blocks, metrics, |number| notes.get(number - 1).map(|n| n.text.trim().to_string()))
+}
+
+fn notes_pages(title: &str, entries: &[NoteEntry], metrics: &Metrics) -> Vec<layout::Page> {
+    let mut blocks = vec![Block::new(layout::tokens(title), TITLE_SIZE)];
+    for entry in entries {
+        let quote = entry.note.anchor.quote.trim();
+        if !entry.sentence.is_empty() {
+            blocks.push(Block::new(layout::tokens(&entry.sentence), TEXT_SIZE).gray());
+        }
+        if entry.note.is_range() && !quote.is_empty() && !entry.sentence.contains(quote) {
+            blocks.push(Block::new(layout::tokens(quote), TEXT_SIZE).gray());
+        }
+        blocks.extend(entry.note.text.trim().lines().map(|line| Block::new(layout::tokens(line), TEXT_SIZE)));
+        let footer: Vec<String> = entry.tags.iter().map(|t| format!("#{}", t))
+            .chain(entry.day().map(|d| d.format("%Y-%m-%d").to_string()))
+            .collect();
+        if !footer.is_empty() {
+            blocks.push(Block::new(layout::tokens(&footer.join(" ")), NOTE_SIZE).gray());
+        }
+        blocks.push(Block::new(vec![], NOTE_SIZE));
+    }
+
+    layout::paginate(blocks, metrics, |_| None)
+}
+
+fn write_text(ops: &mut Vec<Op>, font: &FontId, x: f32, y: f32, size: f32, text: String) {
+    ops.extend([
+        Op::StartTextSection,
+        Op::SetTextCursor { pos: Point { x: Pt(x), y: Pt(y) } },
+        Op::SetFontSize { size: Pt(size), font: font.clone() },
+        Op::WriteText { items: vec![TextItem::Text(text)], font: font.clone() },
+        Op::EndTextSection,
+    ]);
+}
+
+fn render(title: &str, pages: &[layout::Page], font: &[u8], metrics: &Metrics) -> ReaderResult<Vec<u8>> {
+    let mut pdf = PdfDocument::new(title);
+    let mut warnings = vec![];
+    let font = ParsedFont::from_bytes(font, 0, &mut warnings)
+        .ok_or(ReaderError::other("Cannot read the PDF font"))?;
+    let font = pdf.add_font(&font);
+
+    let pages = pages.iter().map(|page| {
+        let mut ops = vec![];
+        for (y, line) in page.lines.iter() {
+            ops.push(if line.gray { GRAY } else { BLACK });
+            // Text without pinyin is written in runs, so the font spaces its characters
+            let mut run: Option<(f32, String)> = None;
+            for (x, token) in line.items.iter() {
+                let x = MARGIN + x;
+                match token {
+                    Token::Text(t, None) => run.get_or_insert_with(|| (x, String::new())).1.push_str(t),
+                    Token::Text(t, Some(ruby)) => {
+                        if let Some((rx, s)) = run.take() {
+                            write_text(&mut ops, &font, rx, *y, line.size, s);
+                        }
+                        let width = token.width(line.size, metrics);
+                        let ruby_size = line.size * RUBY_SCALE;
+                        write_text(&mut ops, &font, x + (width - metrics.text_width(t, line.size)) / 2.0, *y, line.size, t.clone());
+                        write_text(&mut ops, &font, x + (width - metrics.text_width(ruby, ruby_size)) / 2.0,
+                            y + line.size + ruby_size * 0.2, ruby_size, ruby.clone());
+                    }
+                    Token::Mark(n) => {
+                        if let Some((rx, s)) = run.take() {
+                            write_text(&mut ops, &font, rx, *y, line.size, s);
+                        }
+                        write_text(&mut ops, &font, x, y + line.size * 0.4, line.size * MARK_SCALE, n.to_string());
+                    }
+                }
+            }
+            if let Some((rx, s)) = run.take() {
+                write_text(&mut ops, &font, rx, *y, line.size, s);
+            }
+        }
+        PdfPage::new(Mm::from(Pt(PAGE_WIDTH)), Mm::from(Pt(PAGE_HEIGHT)), ops)
+    }).collect();
+
+    Ok(pdf.with_pages(pages).save(&PdfSaveOptions::default(), &mut warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut conn = crate::scraper::db::init_db(":memory:").unwrap();
+        let text = format!("我冒了严寒，回到相隔二千余里的故乡去。\n{}\n完", "我".repeat(2000));
+        let id = crate::textbase::save_text(&mut conn, 0, "故乡", &text).unwrap() as u32;
+        // The text needs a font with Chinese characters, machines without one skip the test
+        let Ok(font) = load_font(None) else {
+            eprintln!("No font with Chinese characters, skipping");
+            return;
+        };
+        let options = PdfOptions::default();
+        let pdf = PdfSource::document(&conn, id, options).unwrap().render(None, &font, options).unwrap();
+
+        let doc = lopdf::Document::load_mem(&pdf).unwrap();
+        assert_eq!(doc.get_pages().len(), 2);
+        let first = doc.extract_text(&[1]).unwrap();
+        assert!(first.contains("故乡") && first.contains("我冒了严寒"));
+        assert!(doc.extract_text(&[2]).unwrap().contains('完'));
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Datelike, Days, Local, NaiveDate, Utc};
+use rusqlite::{Connection, Row, params};
+use tracing::debug;
+
+use crate::error::ReaderResult;
+
+/// Pauses longer than this are not counted as reading
+pub const IDLE_SECS: i64 = 120;
+/// A pause this long ends the session
+pub const SESSION_GAP_SECS: i64 = 30 * 60;
+/// Reading needed for a day to count in a streak
+const STREAK_SECS: i64 = 60;
+
+/// Time spent reading a document in one sitting
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Session {
+    pub id: i64,
+    pub document: u32,
+    pub title: String,
+    pub start: DateTime<Utc>,
+    /// Time of the last activity
+    pub end: DateTime<Utc>,
+    /// Seconds of activity, without the pauses
+    pub active: i64,
+    /// Characters from the starting position to the furthest one reached
+    pub chars: i64,
+    pub lookups: i64,
+}
+
+impl Session {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            document: row.get(1)?,
+            title: row.get(2)?,
+            start: row.get(3)?,
+            end: row.get(4)?,
+            active: row.get(5)?,
+            chars: row.get(6)?,
+            lookups: row.get(7)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.active == 0 && self.chars == 0 && self.lookups == 0
+    }
+
+    pub fn day(&self) -> NaiveDate {
+        self.start.with_timezone(&Local).date_naive()
+    }
+}
+
+/// The session of the open document, fed with the reader's activity
+#[derive(Debug, Clone)]
+pub struct Tracker {
+    pub session: Session,
+    start_offset: i64,
+    last_lookup: String,
+}
+
+impl Tracker {
+    /// Start reading at the character `offset` of a document
+    pub fn new(document: u32, offset: i64, now: DateTime<Utc>) -> Self {
+        Self {
+            session: Session { document, start: now, end: now, ..Default::default() },
+            start_offset: offset,
+            last_lookup: String::new(),
+        }
+    }
+
+    /// Count the time since the last activity, unless the reader was away
+    pub fn activity(&mut self, now: DateTime<Utc>) {
+        let gap = (now - self.session.end).num_seconds();
+        if (0..=IDLE_SECS).contains(&gap) {
+            self.session.active += gap;
+        }
+        self.session.end = self.session.end.max(now);
+    }
+
+    /// The cursor moved to the character `offset`
+    pub fn moved(&mut self, offset: i64, now: DateTime<Utc>) {
+        self.activity(now);
+        self.session.chars = self.session.chars.max(offset - self.start_offset);
+    }
+
+    /// A word was looked up; selecting the same word again is not another lookup
+    pub fn lookup(&mut self, word: &str, now: DateTime<Utc>) {
+        self.activity(now);
+        if word != self.last_lookup {
+            self.session.lookups += 1;
+            self.last_lookup = word.to_string();
+        }
+    }
+
+    pub fn is_over(&self, now: DateTime<Utc>) -> bool {
+        (now - self.session.end).num_seconds() > SESSION_GAP_SECS
+    }
+}
+
+pub fn init_reading(conn: &Connection) -> ReaderResult<()> {
+    conn.execute_batch("
+        CREATE TABLE IF NOT EXISTS ReadingSessions (
+            Id INTEGER,
+            Document INTEGER NOT NULL,
+            Start INTEGER NOT NULL,
+            End INTEGER NOT NULL,
+            Active INTEGER NOT NULL DEFAULT 0,
+            Chars INTEGER NOT NULL DEFAULT 0,
+            Lookups INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY(Id AUTOINCREMENT) );
+        CREATE INDEX IF NOT EXISTS ReadingSessionsDocument ON ReadingSessions (Document);
+    ")?;
+    Ok(())
+}
+
+/// Store a session, the first time it gets its id
+pub fn save_session(conn: &Connection, session: &mut Session) -> ReaderResult<()> {
+    if session.id == 0 {
+        conn.execute("INSERT INTO ReadingSessions (Document, Start, End, Active, Chars, Lookups) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![session.document, session.start, session.end, session.active, session.chars, session.lookups])?;
+        session.id = conn.last_insert_rowid();
+        debug!("Reading session {} of document {}", session.id, session.document);
+    } else {
+        conn.execute("UPDATE ReadingSessions SET End = ?2, Active = ?3, Chars = ?4, Lookups = ?5 WHERE Id = ?1",
+            params![session.id, session.end, session.active, session.chars, session.lookups])?;
+    }
+    Ok(())
+}
+
+/// Every session, the latest first
+pub fn sessions(conn: &Connection) -> ReaderResult<Vec<Session>> {
+    let mut st = conn.prepare("
+        SELECT S.Id, S.Document, COALESCE(D.Title, ''), S.Start, S.End, S.Active, S.Chars, S.Lookups
+        FROM ReadingSessions S LEFT JOIN Documents D ON D.Id = S.Document
+        ORDER BY S.Start DESC")?;
+    let res = st.query_map([], Session::from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(res)
+}
+
+/// Reading of a day, or of the week starting on that Monday
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Total {
+    pub day: NaiveDate,
+    pub active: i64,
+    pub chars: i64,
+    pub lookups: i64,
+    pub sessions: usize,
+}
+
+impl Total {
+    fn add(&mut self, s: &Session) {
+        self.active += s.active;
+        self.chars += s.chars;
+        self.lookups += s.lookups;
+        self.sessions += 1;
+    }
+
+    pub fn minutes(&self) -> f64 {
+        self.active as f64 / 60.0
+    }
+
+    /// Reading speed, when there was enough reading to tell
+    pub fn chars_per_minute(&self) -> Option<f64> {
+        if self.active < 60 { None } else { Some(self.chars as f64 / self.minutes()) }
+    }
+}
+
+/// All the reading of a document
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DocumentTotal {
+    pub document: u32,
+    pub title: String,
+    pub total: Total,
+    pub last: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReadingStats {
+    /// Every day of the period, days without reading included
+    pub days: Vec<Total>,
+    pub weeks: Vec<Total>,
+    pub period: Total,
+    /// Days in a row up to today, or up to yesterday when there is no reading today yet
+    pub streak: usize,
+    pub best_streak: usize,
+    /// Documents read in the period, the most read first
+    pub documents: Vec<DocumentTotal>,
+}
+
+impl ReadingStats {
+    /// Reading from `from` to `to`; streaks are counted over all the sessions
+    pub fn new(sessions: &[Session], from: NaiveDate, to: NaiveDate) -> Self {
+        let mut by_day = BTreeMap::<NaiveDate, Total>::new();
+        for s in sessions {
+            by_day.entry(s.day()).or_insert_with(|| Total { day: s.day(), ..Default::default() }).add(s);
+        }
+
+        let days: Vec<Total> = from.iter_days()
+            .take_while(|d| *d <= to)
+            .map(|day| by_day.get(&day).cloned().unwrap_or(Total { day, ..Default::default() }))
+            .collect();
+        let mut weeks = BTreeMap::<NaiveDate, Total>::new();
+        let mut period = Total { day: from, ..Default::default() };
+        for d in &days {
+            let monday = d.day - Days::new(d.day.weekday().num_days_from_monday() as u64);
+            let week = weeks.entry(monday).or_insert_with(|| Total { day: monday, ..Default::default() });
+            for t in [&mut *week, &mut period] {
+                t.active += d.active;
+                t.chars += d.chars;
+                t.lookups += d.lookups;
+                t.sessions += d.sessions;
+            }
+        }
+
+        let read: Vec<NaiveDate> = by_day.values()
+            .filter(|t| t.active >= STREAK_SECS)
+            .map(|t| t.day)
+            .collect();
+        let mut best_streak = 0;
+        let mut run = 0;
+        let mut last: Option<NaiveDate> = None;
+        for day in &read {
+            run = if last.and_then(|l| l.succ_opt()) == Some(*day) { run + 1 } else { 1 };
+            best_streak = best_streak.max(run);
+            last = Some(*day);
+        }
+        let current = match last {
+            Some(l) if l == to || l.succ_opt() == Some(to) => run,
+            _ => 0,
+        };
+
+        let mut documents = BTreeMap::<u32, DocumentTotal>::new();
+        for s in sessions.iter().filter(|s| (from..=to).contains(&s.day())) {
+            let doc = documents.entry(s.document).or_insert_with(|| DocumentTotal {
+                document: s.document,
+                title: s.title.clone(),
+                last: s.end,
+                ..Default::default()
+            });
+            doc.total.add(s);
+            doc.last = doc.last.max(s.end);
+        }
+        let mut documents: Vec<DocumentTotal> = documents.into_values().collect();
+        documents.sort_by_key(|d| std::cmp::Reverse(d.total.active));
+
+        Self { days, weeks: weeks.into_values().collect(), period, streak: current, best_streak, documents }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(day: u32, hour: u32, min: u32, sec: u32) -> DateTime<Utc> {
+        Local.with_ymd_and_hms(2024, 1, day, hour, min, sec).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_tracker() {
+        let mut t = Tracker::new(1, 100, at(1, 12, 0, 0));
+        t.moved(150, at(1, 12, 1, 0));
+        t.lookup("你好", at(1, 12, 1, 30));
+        t.lookup("你好", at(1, 12, 1, 40));
+        // Going back does not take away what was read, the pause is not reading
+        t.moved(120, at(1, 12, 10, 0));
+        t.moved(400, at(1, 12, 11, 0));
+        assert_eq!((t.session.active, t.session.chars, t.session.lookups), (160, 300, 1));
+        assert!(!t.is_over(at(1, 12, 40, 0)));
+        assert!(t.is_over(at(1, 12, 42, 0)));
+
+        let mut conn = crate::scraper::db::init_db(":memory:").unwrap();
+        t.session.document = crate::textbase::save_text(&mut conn, 0, "故乡", "我冒了严寒").unwrap() as u32;
+        save_session(&conn, &mut t.session).unwrap();
+        t.moved(500, at(1, 12, 12, 0));
+        save_session(&conn, &mut t.session).unwrap();
+        let saved = sessions(&conn).unwrap();
+        assert_eq!(saved.len(), 1);
+        assert_eq!((saved[0].title.as_str(), saved[0].end, saved[0].active, saved[0].chars), ("故乡", at(1, 12, 12, 0), 220, 400));
+    }
+
+    #[test]
+    fn test_stats() {
+        let session = |document, day, active, chars| Session {
+            document,
+            title: format!("{}", document),
+            start: at(day, 12, 0, 0),
+            end: at(day, 13, 0, 0),
+            active,
+            chars,
+            ..Default::default()
+        };
+        let sessions = [
+            session(1, 1, 600, 1000),
+            session(1, 2, 300, 500),
+            session(2, 2, 300, 100),
+            session(2, 3, 30, 10),
+            session(1, 5, 120, 200),
+            session(2, 6, 600, 900),
+        ];
+        let from = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let to = NaiveDate::from_ymd_opt(2024, 1, 7).unwrap();
+        let stats = ReadingStats::new(&sessions, from, to);
+        assert_eq!(stats.days.len(), 7);
+        assert_eq!((stats.days[1].active, stats.days[1].sessions, stats.days[3].active), (600, 2, 0));
+        assert_eq!(stats.weeks.len(), 1);
+        assert_eq!(stats.period.chars_per_minute(), Some(2710.0 / 32.5));
+        // Day 3 has too little reading, the streak goes on from day 5 up to yesterday
+        assert_eq!((stats.best_streak, stats.streak), (2, 2));
+        assert_eq!(stats.documents.iter().map(|d| (d.document, d.total.active)).collect::<Vec<_>>(), vec![(1, 1020), (2, 930)]);
+
+        let later = ReadingStats::new(&sessions, from, NaiveDate::from_ymd_opt(2024, 1, 9).unwrap());
+        assert_eq!(later.streak, 0);
+    }
+}
@@ -0,0 +1,133 @@
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, OptionalExtension, params};
+use tracing::debug;
+
+use crate::diff::Change;
+use crate::error::ReaderResult;
+
+/// Revisions kept for each document, the oldest are dropped
+const MAX_REVISIONS: usize = 50;
+
+/// Content a document had before it was saved
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Revision {
+    pub id: i64,
+    pub document: u32,
+    /// When the content was replaced
+    pub saved: DateTime<Utc>,
+    pub content: String,
+}
+
+pub fn init_revisions(conn: &Connection) -> ReaderResult<()> {
+    conn.execute_batch("
+        CREATE TABLE IF NOT EXISTS Revisions (
+            Id INTEGER PRIMARY KEY,
+            Document INTEGER NOT NULL,
+            Content TEXT NOT NULL,
+            Saved TEXT NOT NULL );
+        CREATE INDEX IF NOT EXISTS RevisionsDocument ON Revisions (Document);
+    ")?;
+    Ok(())
+}
+
+/// Keeps the content of a document that is about to become `content`, nothing if it stays the same
+pub fn record(conn: &Connection, document: u32, content: &str) -> ReaderResult<bool> {
+    let old: Option<String> = conn.query_row("SELECT Content FROM Documents WHERE Id = ?1", [document], |row| row.get(0))
+        .optional()?;
+    let Some(old) = old.filter(|old| old != content) else {
+        return Ok(false);
+    };
+    conn.execute("INSERT INTO Revisions (Document, Content, Saved) VALUES (?1, ?2, ?3)", params![document, old, Utc::now()])?;
+    let dropped = conn.execute("DELETE FROM Revisions WHERE Document = ?1 AND Id NOT IN
+        (SELECT Id FROM Revisions WHERE Document = ?1 ORDER BY Id DESC LIMIT ?2)", params![document, MAX_REVISIONS as i64])?;
+    debug!("Revision of {} saved, {} dropped", document, dropped);
+    Ok(true)
+}
+
+/// Revisions of a document, the latest first
+pub fn revisions(conn: &Connection, document: u32) -> ReaderResult<Vec<Revision>> {
+    let mut st = conn.prepare("SELECT Id, Document, Saved, Content FROM Revisions WHERE Document = ?1 ORDER BY Id DESC")?;
+    let res = st.query_map([document], |row| Ok(Revision {
+        id: row.get(0)?,
+        document: row.get(1)?,
+        saved: row.get(2)?,
+        content: row.get(3)?,
+    }))?.collect::<Result<Vec<_>, _>>()?;
+    Ok(res)
+}
+
+/// Makes a revision the content of its document again. The replaced content becomes
+/// a revision and the notes are anchored to the restored text
+pub fn restore(conn: &mut Connection, revision: i64) -> ReaderResult<u32> {
+    let (document, title, content): (u32, String, String) = conn.query_row("
+        SELECT R.Document, D.Title, R.Content FROM Revisions R JOIN Documents D ON D.Id = R.Document
+        WHERE R.Id = ?1", [revision], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    crate::textbase::save_text(conn, document, &title, &content)?;
+    Ok(document)
+}
+
+/// The changes with `context` unchanged lines around each, None where lines are left out
+pub fn with_context<L>(changes: &[Change<L>], context: usize) -> Vec<Option<&Change<L>>> {
+    let mut shown = vec![false; changes.len()];
+    for (ix, _) in changes.iter().enumerate().filter(|(_, c)| !c.is_same()) {
+        let end = (ix + context + 1).min(changes.len());
+        shown[ix.saturating_sub(context)..end].iter_mut().for_each(|s| *s = true);
+    }
+    let mut res = vec![];
+    for (change, shown) in changes.iter().zip(shown) {
+        if shown {
+            res.push(Some(change));
+        } else if res.last().is_none_or(|c| c.is_some()) {
+            res.push(None);
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::diff;
+    use crate::textbase::{Note, get_content, get_notes, save_note, save_text};
+
+    #[test]
+    fn test_context() {
+        let old = "一\n二\n三\n四\n五\n六";
+        let changes = diff(old, "一\n二\n3\n四\n五\n六\n七");
+        let shown = with_context(&changes, 1);
+        assert_eq!(shown, vec![None, Some(&Change::Same("二")), Some(&Change::Removed("三")), Some(&Change::Added("3")),
+            Some(&Change::Same("四")), None, Some(&Change::Same("六")), Some(&Change::Added("七"))]);
+        assert!(with_context(&diff(old, old), 2) == vec![None]);
+    }
+
+    #[test]
+    fn test_revisions() {
+        let mut conn = crate::scraper::db::init_db(":memory:").unwrap();
+        let first = "我冒了严寒，回到相隔二千余里的故乡去。";
+        let doc = save_text(&mut conn, 0, "故乡", first).unwrap() as u32;
+        save_note(&mut conn, &Note { doc, line: 0, char: 15, end_line: 0, end_char: 17, text: String::from("hometown"), ..Default::default() }.anchored(first)).unwrap();
+        assert!(revisions(&conn, doc).unwrap().is_empty());
+
+        // A bad paste replaces the text, saving it again changes nothing
+        let pasted = "粘贴错了";
+        save_text(&mut conn, doc, "故乡", pasted).unwrap();
+        save_text(&mut conn, doc, "故乡", pasted).unwrap();
+        let saved = revisions(&conn, doc).unwrap();
+        assert_eq!(saved.iter().map(|r| r.content.as_str()).collect::<Vec<_>>(), vec![first]);
+
+        let edited = format!("时候既然是深冬。\n{}", first);
+        save_text(&mut conn, doc, "故乡", &edited).unwrap();
+        assert_eq!(restore(&mut conn, saved[0].id).unwrap(), doc);
+        assert_eq!(get_content(&conn, doc).unwrap().as_deref(), Some(first));
+        assert_eq!(revisions(&conn, doc).unwrap().iter().map(|r| r.content.as_str()).collect::<Vec<_>>(), vec![edited.as_str(), pasted, first]);
+
+        // The note follows the text it belongs to
+        let restore_edited = revisions(&conn, doc).unwrap()[0].id;
+        restore(&mut conn, restore_edited).unwrap();
+        let note = &get_notes(&conn, doc).unwrap()[0];
+        assert_eq!((note.line, note.char, note.end_line, note.end_char), (1, 15, 1, 17));
+
+        crate::textbase::delete_documents(&mut conn, &[doc]).unwrap();
+        assert!(revisions(&conn, doc).unwrap().is_empty());
+    }
+}
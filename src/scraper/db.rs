@@ -1,36 +1,192 @@
+use std::path::Path;
+
 use rusqlite::Connection;
-use crate::error::ReaderResult;
-
-pub fn init_db(file_name: impl AsRef<std::path::Path>) -> ReaderResult<Connection> {
-    let conn = Connection::open(file_name)?;
-
-    let exists: i32 = conn.query_row(
-        "SELECT count(*) FROM sqlite_master WHERE type='table' AND name=?1",
-        &[&"Documents"],
-        |row| row.get(0)
-    )?;
-
-    if exists == 0 {
-        conn.execute(
-            "CREATE TABLE Documents (
-                Id INTEGER, 
-                Title TEXT NOT NULL,
-                Content BLOB,
-                Line INTEGER DEFAULT 0,
-                Character INTEGER DEFAULT 0,
-                PRIMARY KEY(Id AUTOINCREMENT) )", 
-            ())?;
-
-        conn.execute("
-            CREATE TABLE Notes (
+use tracing::{debug, info};
+
+use crate::error::{ReaderError, ReaderResult};
+
+/// A schema change, run in its own transaction
+type Migration = fn(&Connection) -> ReaderResult<()>;
+
+/// Schema changes in order: a database at version n has gone through the first n.
+/// Databases made before versioning are at 0, so every step must accept tables that
+/// already exist; new changes are only ever appended
+const MIGRATIONS: &[Migration] = &[
+    create_texts,
+    fix_texts,
+    crate::search::init_fts,
+    crate::srs::init_srs,
+    crate::cloze::init_cloze,
+    crate::import::init_import,
+    crate::library::init_library,
+    crate::reading::init_reading,
+    crate::library::init_view_state,
+    crate::textbase::anchor_notes,
+    crate::notes::init_note_tags,
+    crate::revisions::init_revisions,
+    crate::import::split_imports,
+];
+
+/// Version of the current schema
+pub const SCHEMA_VERSION: usize = MIGRATIONS.len();
+
+pub fn init_db(file_name: impl AsRef<Path>) -> ReaderResult<Connection> {
+    let mut conn = Connection::open(file_name.as_ref())?;
+    migrate(&mut conn, Some(file_name.as_ref()))?;
+    Ok(conn)
+}
+
+pub fn schema_version(conn: &Connection) -> ReaderResult<usize> {
+    let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    Ok(version as usize)
+}
+
+/// Where the database is copied before it is migrated from `version`
+pub fn backup_path(file_name: &Path, version: usize) -> std::path::PathBuf {
+    let mut name = file_name.as_os_str().to_owned();
+    name.push(format!(".v{}.bak", version));
+    name.into()
+}
+
+/// Bring the schema up to date, returns the number of migrations run. A database that
+/// has tables is first copied next to `file_name`
+pub fn migrate(conn: &mut Connection, file_name: Option<&Path>) -> ReaderResult<usize> {
+    let version = schema_version(conn)?;
+    if version > SCHEMA_VERSION {
+        return Err(ReaderError::Sqlite(format!("The database is at version {}, this version of the app only knows up to {}", version, SCHEMA_VERSION)));
+    }
+    if version == SCHEMA_VERSION {
+        return Ok(0);
+    }
+    let tables: i64 = conn.query_row("SELECT count(*) FROM sqlite_master WHERE type = 'table'", [], |row| row.get(0))?;
+    if let Some(file_name) = file_name
+        && tables > 0 {
+        let backup = backup_path(file_name, version);
+        info!("Backing up the database to {:?} before migrating", backup);
+        if backup.exists() {
+            std::fs::remove_file(&backup)?;
+        }
+        conn.execute("VACUUM INTO ?1", [backup.to_string_lossy()])?;
+    }
+    for (ix, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        debug!("Migrating the database to version {}", ix + 1);
+        let tx = conn.transaction()?;
+        migration(&tx)?;
+        tx.pragma_update(None, "user_version", ix as i64 + 1)?;
+        tx.commit()?;
+    }
+    Ok(SCHEMA_VERSION - version)
+}
+
+fn create_texts(conn: &Connection) -> ReaderResult<()> {
+    conn.execute_batch("
+        CREATE TABLE IF NOT EXISTS Documents (
+            Id INTEGER,
+            Title TEXT NOT NULL,
+            Content BLOB,
+            Line INTEGER DEFAULT 0,
+            Character INTEGER DEFAULT 0,
+            PRIMARY KEY(Id AUTOINCREMENT) );
+        CREATE TABLE IF NOT EXISTS Notes (
+            Id INTEGER,
+            Line INTEGER NOT NULL,
+            Character INTEGER,
+            Document INTEGER NOT NULL,
+            Content TEXT,
+            PRIMARY KEY(Id AUTOINCREMENT) );
+    ")?;
+    Ok(())
+}
+
+fn columns(conn: &Connection, table: &str) -> ReaderResult<Vec<String>> {
+    let mut st = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let res = st.query_map([], |row| row.get(1))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(res)
+}
+
+/// The first databases had no reading position, and a foreign key from the notes to
+/// themselves instead of to their document
+fn fix_texts(conn: &Connection) -> ReaderResult<()> {
+    let documents = columns(conn, "Documents")?;
+    for column in ["Line", "Character"] {
+        if !documents.iter().any(|c| c == column) {
+            debug!("Adding Documents.{}", column);
+            conn.execute(&format!("ALTER TABLE Documents ADD COLUMN {} INTEGER DEFAULT 0", column), [])?;
+        }
+    }
+    let notes: String = conn.query_row("SELECT sql FROM sqlite_master WHERE type = 'table' AND name = 'Notes'", [], |row| row.get(0))?;
+    if notes.contains("REFERENCES \"Notes\"") {
+        debug!("Recreating Notes without the foreign key");
+        conn.execute_batch("
+            CREATE TABLE NotesNew (
                 Id INTEGER,
                 Line INTEGER NOT NULL,
                 Character INTEGER,
                 Document INTEGER NOT NULL,
                 Content TEXT,
-                PRIMARY KEY(Id AUTOINCREMENT) )", 
-            ())?;
+                PRIMARY KEY(Id AUTOINCREMENT) );
+            INSERT INTO NotesNew (Id, Line, Character, Document, Content) SELECT Id, Line, Character, Document, Content FROM Notes;
+            DROP TABLE Notes;
+            ALTER TABLE NotesNew RENAME TO Notes;
+        ")?;
     }
-    Ok(conn)
+    Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD_SCHEMA: &str = include_str!("../../appdata.db.sql");
+
+    #[test]
+    fn test_migrate() {
+        let mut tmp = std::env::temp_dir();
+        tmp.push(format!("test-{}.db", crate::utils::random_name()));
+        let old = Connection::open(&tmp).unwrap();
+        old.execute_batch(OLD_SCHEMA).unwrap();
+        drop(old);
+
+        let mut conn = init_db(&tmp).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+        let backup = backup_path(&tmp, 0);
+        let saved: String = Connection::open(&backup).unwrap()
+            .query_row("SELECT Content FROM Notes", [], |row| row.get(0)).unwrap();
+        assert_eq!(saved, "Test annotation");
+
+        // The old rows are kept and read with the current schema
+        let docs = crate::textbase::get_documents(&conn).unwrap();
+        assert_eq!((docs[0].title.as_str(), docs[0].line, docs[0].character), ("Test", 0, 0));
+        let notes = crate::textbase::get_notes(&conn, 1).unwrap();
+        assert_eq!((notes[0].text.as_str(), notes[0].end(), notes[0].anchor.prefix.as_str()), ("Test annotation", (1, 3), "Test document"));
+        // The old app saved the byte column of the editor
+        let notes = crate::textbase::get_notes(&conn, 2).unwrap();
+        assert_eq!((notes[0].pos(), notes[0].end()), ((0, 15), (0, 15)));
+        assert_eq!((notes[0].anchor.prefix.as_str(), notes[0].anchor.suffix.as_str()), ("我冒了严寒，回到相隔二千余里的", "故乡去。"));
+        conn.pragma_update(None, "foreign_keys", true).unwrap();
+        crate::textbase::save_note(&mut conn, &crate::textbase::Note { doc: 1, char: 1, text: String::from("新的"), ..Default::default() }).unwrap();
+        assert!(!crate::search::search(&conn, "Test").unwrap().is_empty());
+        assert!(crate::library::get_library(&conn).is_ok());
+        assert_eq!(migrate(&mut conn, Some(&tmp)).unwrap(), 0);
+
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION as i64 + 1).unwrap();
+        assert!(migrate(&mut conn, Some(&tmp)).is_err());
+        drop(conn);
+        std::fs::remove_file(backup).unwrap();
+        std::fs::remove_file(tmp).unwrap();
+    }
+
+    #[test]
+    fn test_unversioned() {
+        // Databases made before versioning have every table the migrations create
+        let mut conn = Connection::open_in_memory().unwrap();
+        create_texts(&conn).unwrap();
+        crate::search::init_fts(&conn).unwrap();
+        crate::srs::init_srs(&conn).unwrap();
+        conn.execute("INSERT INTO Documents (Title, Content) VALUES ('标题', '内容')", []).unwrap();
+        assert_eq!(migrate(&mut conn, None).unwrap(), SCHEMA_VERSION);
+        assert_eq!(crate::textbase::get_content(&conn, 1).unwrap().unwrap(), "内容");
+        assert_eq!(crate::library::get_library(&conn).unwrap().len(), 1);
+    }
+}
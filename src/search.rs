@@ -0,0 +1,267 @@
+use std::collections::HashSet;
+
+use rusqlite::{Connection, params};
+use tracing::debug;
+
+use crate::error::ReaderResult;
+use crate::textbase::sentences;
+
+/// Characters of context on each side of a hit
+const KWIC_CONTEXT: usize = 12;
+/// Maximum hits returned for a single search
+const MAX_HITS: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HitKind {
+    Document,
+    Note,
+}
+
+/// Keyword in context; `line` and `character` are the position of the hit, counted in characters
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub doc: u32,
+    pub title: String,
+    pub kind: HitKind,
+    pub line: i64,
+    pub character: i64,
+    pub left: String,
+    pub hit: String,
+    pub right: String,
+}
+
+/// Create the full-text index tables, filling them when they are new
+pub fn init_fts(conn: &Connection) -> ReaderResult<()> {
+    let exists: i32 = conn.query_row(
+        "SELECT count(*) FROM sqlite_master WHERE type='table' AND name=?1",
+        [&"DocumentsFts"],
+        |row| row.get(0)
+    )?;
+
+    if exists == 0 {
+        debug!("Creating full-text index");
+        conn.execute("CREATE VIRTUAL TABLE DocumentsFts USING fts5(Title, Content, tokenize='trigram')", ())?;
+        conn.execute("CREATE VIRTUAL TABLE NotesFts USING fts5(Content, Document UNINDEXED, tokenize='trigram')", ())?;
+        rebuild_fts(conn)?;
+    }
+    Ok(())
+}
+
+pub fn rebuild_fts(conn: &Connection) -> ReaderResult<()> {
+    conn.execute("DELETE FROM DocumentsFts", ())?;
+    conn.execute("DELETE FROM NotesFts", ())?;
+    conn.execute("INSERT INTO DocumentsFts (rowid, Title, Content) SELECT Id, Title, Content FROM Documents", ())?;
+    conn.execute("INSERT INTO NotesFts (rowid, Content, Document) SELECT Id, Content, Document FROM Notes", ())?;
+    Ok(())
+}
+
+pub fn index_document(conn: &Connection, id: i64, title: &str, content: &str) -> ReaderResult<()> {
+    conn.execute("DELETE FROM DocumentsFts WHERE rowid = ?1", [id])?;
+    conn.execute("INSERT INTO DocumentsFts (rowid, Title, Content) VALUES (?1, ?2, ?3)", params![id, title, content])?;
+    Ok(())
+}
+
+pub fn index_note(conn: &Connection, id: i64, document: u32, content: &str) -> ReaderResult<()> {
+    conn.execute("DELETE FROM NotesFts WHERE rowid = ?1", [id])?;
+    conn.execute("INSERT INTO NotesFts (rowid, Content, Document) VALUES (?1, ?2, ?3)", params![id, content, document])?;
+    Ok(())
+}
+
+pub fn unindex_document(conn: &Connection, id: i64) -> ReaderResult<()> {
+    conn.execute("DELETE FROM DocumentsFts WHERE rowid = ?1", [id])?;
+    conn.execute("DELETE FROM NotesFts WHERE Document = ?1", [id])?;
+    Ok(())
+}
+
+pub fn unindex_notes(conn: &Connection, ids: &[i64]) -> ReaderResult<()> {
+    for id in ids {
+        conn.execute("DELETE FROM NotesFts WHERE rowid = ?1", [id])?;
+    }
+    Ok(())
+}
+
+fn like_pattern(query: &str) -> String {
+    let escaped = query.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+/// Every occurrence of `query` in `text` as (line, character column, left, right)
+pub fn kwic(text: &str, query: &str) -> Vec<(i64, i64, String, String)> {
+    if query.is_empty() {
+        return vec![];
+    }
+    let mut res = vec![];
+    for (line_ix, line) in text.lines().enumerate() {
+        for (pos, _) in line.match_indices(query) {
+            let left: String = line[..pos].chars().rev().take(KWIC_CONTEXT).collect::<Vec<_>>()
+                .into_iter().rev().collect();
+            let right: String = line[pos + query.len()..].chars().take(KWIC_CONTEXT).collect();
+            res.push((line_ix as i64, line[..pos].chars().count() as i64, left, right));
+        }
+    }
+    res
+}
+
+/// Search the text of all documents and notes. The trigram index only helps queries of
+/// three characters or more, shorter ones scan every row, so both queries are limited
+pub fn search(conn: &Connection, query: &str) -> ReaderResult<Vec<SearchHit>> {
+    let query = query.trim();
+    if query.is_empty() {
+        return Ok(vec![]);
+    }
+    let pattern = like_pattern(query);
+    let mut hits = vec![];
+
+    let mut st = conn.prepare(
+        r#"
+        SELECT F.rowid, F.Title, F.Content
+        FROM DocumentsFts F
+        WHERE F.Content LIKE ?1 ESCAPE '\'
+        ORDER BY F.Title
+        LIMIT ?2
+        "#)?;
+    let rows = st.query_map(params![pattern, MAX_HITS as i64], |row| {
+        Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+    })?;
+    for row in rows {
+        let (doc, title, content) = row?;
+        for (line, character, left, right) in kwic(content.as_str(), query) {
+            hits.push(SearchHit { doc, title: title.clone(), kind: HitKind::Document, line, character, left, hit: query.to_string(), right });
+        }
+        if hits.len() >= MAX_HITS {
+            hits.truncate(MAX_HITS);
+            return Ok(hits);
+        }
+    }
+
+    let mut st = conn.prepare(
+        r#"
+        SELECT N.Document, D.Title, N.Line, N.Character, F.Content
+        FROM NotesFts F
+        JOIN Notes N ON N.Id = F.rowid
+        JOIN Documents D ON D.Id = N.Document
+        WHERE F.Content LIKE ?1 ESCAPE '\'
+        ORDER BY D.Title, N.Line, N.Character
+        LIMIT ?2
+        "#)?;
+    let rows = st.query_map(params![pattern, (MAX_HITS - hits.len()) as i64], |row| {
+        Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?, row.get::<_, Option<i64>>(3)?, row.get::<_, String>(4)?))
+    })?;
+    for row in rows {
+        let (doc, title, line, character, content) = row?;
+        if let Some((_, _, left, right)) = kwic(content.as_str(), query).into_iter().next() {
+            hits.push(SearchHit { doc, title, kind: HitKind::Note, line, character: character.unwrap_or(0), left, hit: query.to_string(), right });
+        }
+    }
+    hits.truncate(MAX_HITS);
+    debug!("Search {}: {} hits", query, hits.len());
+    Ok(hits)
+}
+
+/// Ids of the notes whose text contains `query`
+pub fn note_hits(conn: &Connection, query: &str) -> ReaderResult<HashSet<u32>> {
+    let query = query.trim();
+    if query.is_empty() {
+        return Ok(HashSet::new());
+    }
+    let mut st = conn.prepare(r#"SELECT rowid FROM NotesFts WHERE Content LIKE ?1 ESCAPE '\'"#)?;
+    let res = st.query_map([like_pattern(query)], |row| row.get(0))?
+        .collect::<Result<HashSet<_>, _>>()?;
+    Ok(res)
+}
+
+/// Every sentence in the library containing `word`, sorted by document
+pub fn concordance(conn: &Connection, word: &str) -> ReaderResult<Vec<SearchHit>> {
+    let word = word.trim();
+    if word.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut st = conn.prepare(
+        r#"
+        SELECT F.rowid, F.Title, F.Content
+        FROM DocumentsFts F
+        WHERE F.Content LIKE ?1 ESCAPE '\'
+        ORDER BY F.Title, F.rowid
+        "#)?;
+    let rows = st.query_map([like_pattern(word)], |row| {
+        Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+    })?;
+
+    let mut hits = vec![];
+    for row in rows {
+        let (doc, title, content) = row?;
+        for (line_ix, line) in content.lines().enumerate() {
+            for (start, sentence) in sentences(line) {
+                if let Some(pos) = sentence.find(word) {
+                    hits.push(SearchHit {
+                        doc,
+                        title: title.clone(),
+                        kind: HitKind::Document,
+                        line: line_ix as i64,
+                        character: line[..start + pos].chars().count() as i64,
+                        left: sentence[..pos].trim_start().to_string(),
+                        hit: word.to_string(),
+                        right: sentence[pos + word.len()..].trim_end().to_string(),
+                    });
+                }
+            }
+        }
+    }
+    debug!("Concordance {}: {} sentences", word, hits.len());
+    Ok(hits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::textbase::{Note, save_note, save_text};
+
+    #[test]
+    fn test_concordance() {
+        let mut conn = crate::scraper::db::init_db(":memory:").unwrap();
+        save_text(&mut conn, 0, "B", "我喜欢看书。你呢？\n他说：“我也喜欢！”").unwrap();
+        save_text(&mut conn, 0, "A", "喜欢就好").unwrap();
+        save_text(&mut conn, 0, "C", "没有").unwrap();
+
+        let c = concordance(&conn, "喜欢").unwrap();
+        assert_eq!(c.len(), 3);
+        assert_eq!(c[0].title, "A");
+        assert_eq!((c[1].title.as_str(), c[1].line, c[1].character), ("B", 0, "我".chars().count() as i64));
+        assert_eq!(c[1].right, "看书。");
+        assert_eq!(c[2].left, "他说：“我也");
+        assert_eq!(c[2].right, "！”");
+    }
+
+    #[test]
+    fn test_kwic() {
+        let r = kwic("第一行\n我们在学习中文。中文很难", "中文");
+        assert_eq!(r.len(), 2);
+        assert_eq!(r[0], (1, "我们在学习".chars().count() as i64, String::from("我们在学习"), String::from("。中文很难")));
+        assert_eq!(r[1].0, 1);
+        assert_eq!(r[1].3, String::from("很难"));
+    }
+
+    #[test]
+    fn test_search() {
+        let mut conn = crate::scraper::db::init_db(":memory:").unwrap();
+        let id = save_text(&mut conn, 0, "课文", "今天天气很好。\n我们去公园_散步吧。").unwrap();
+        save_note(&mut conn, &Note { doc: id as u32, line: 1, char: 3, text: String::from("公园 = park"), ..Default::default() }).unwrap();
+
+        let hits = search(&conn, "公园").unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].kind, HitKind::Document);
+        assert_eq!(hits[0].line, 1);
+        assert_eq!(hits[0].character, "我们去".chars().count() as i64);
+        assert_eq!(hits[1].kind, HitKind::Note);
+        assert_eq!((hits[1].line, hits[1].character), (1, 3));
+
+        // Single character and LIKE wildcards
+        assert_eq!(search(&conn, "天").unwrap().len(), 2);
+        assert_eq!(search(&conn, "_").unwrap().len(), 1);
+
+        save_text(&mut conn, id as u32, "课文", "没有了").unwrap();
+        assert_eq!(search(&conn, "天气").unwrap().len(), 0);
+    }
+}
@@ -0,0 +1,414 @@
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{Connection, OptionalExtension, Row, params};
+use tracing::debug;
+
+use crate::anki::connect::NoteData;
+use crate::error::{ReaderError, ReaderResult};
+use crate::make_enum;
+
+make_enum!(CardKind, [Meaning, Pinyin, Sentence]);
+make_enum!(Grade, [Again, Hard, Good, Easy]);
+
+/// SM-2 ease of a new card, and the lowest it can drop to
+const EASE_START: f64 = 2.5;
+const EASE_MIN: f64 = 1.3;
+/// Ease lost when a card is forgotten
+const EASE_LAPSE: f64 = 0.2;
+/// Interval growth of a hard answer, and the extra growth of an easy one
+const HARD_FACTOR: f64 = 1.2;
+const EASY_BONUS: f64 = 1.3;
+/// First interval of a new card answered easy
+const EASY_FIRST: f64 = 4.0;
+/// Second interval of a card answered good
+const GOOD_SECOND: f64 = 6.0;
+/// Forgotten cards come back after this delay
+const RELEARN_MINUTES: i64 = 10;
+/// Blank shown in place of the word on sentence cards
+const CLOZE: &str = "[…]";
+
+const TSV_HEADER: &str = "word\tkind\tpinyin\tmeaning\tsentence\tsource\tdue\tinterval\tease\treps\tlapses";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Card {
+    pub id: i64,
+    pub word: String,
+    pub kind: CardKind,
+    pub pinyin: String,
+    pub meaning: String,
+    pub sentence: String,
+    pub source: String,
+    pub due: DateTime<Utc>,
+    /// Days to the next review, 0 for new and forgotten cards
+    pub interval: i64,
+    pub ease: f64,
+    /// Successful reviews in a row
+    pub reps: i64,
+    pub lapses: i64,
+}
+
+/// One answer of the review history
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReviewLog {
+    pub word: String,
+    pub kind: CardKind,
+    pub time: DateTime<Utc>,
+    pub grade: Grade,
+    pub interval: i64,
+}
+
+impl CardKind {
+    pub fn label(&self) -> String {
+        match self {
+            Self::Meaning => t!("review_meaning").to_string(),
+            Self::Pinyin => t!("review_pinyin").to_string(),
+            Self::Sentence => t!("review_sentence").to_string(),
+        }
+    }
+}
+
+impl Grade {
+    pub fn label(&self) -> String {
+        match self {
+            Self::Again => t!("review_again").to_string(),
+            Self::Hard => t!("review_hard").to_string(),
+            Self::Good => t!("review_good").to_string(),
+            Self::Easy => t!("review_easy").to_string(),
+        }
+    }
+}
+
+/// Short form of an interval, forgotten cards come back within minutes
+pub fn interval_label(days: i64) -> String {
+    if days == 0 { format!("{}m", RELEARN_MINUTES) } else { format!("{}d", days) }
+}
+
+fn timestamp(secs: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(secs, 0).unwrap_or_default()
+}
+
+impl Card {
+    pub fn new(note: &NoteData, kind: CardKind, now: DateTime<Utc>) -> Self {
+        Self {
+            id: 0,
+            word: note.hanzi.clone(),
+            kind,
+            pinyin: note.pinyin.clone(),
+            meaning: note.meaning.clone(),
+            sentence: note.sentence.clone(),
+            source: note.source.clone(),
+            due: now,
+            interval: 0,
+            ease: EASE_START,
+            reps: 0,
+            lapses: 0,
+        }
+    }
+
+    /// Kinds of card a note can make: sentence cards need a sentence with the word
+    pub fn kinds(note: &NoteData) -> Vec<CardKind> {
+        CardKind::ALL.iter()
+            .copied()
+            .filter(|k| match k {
+                CardKind::Meaning => !note.meaning.is_empty(),
+                CardKind::Pinyin => !note.pinyin.is_empty(),
+                CardKind::Sentence => note.sentence.contains(note.hanzi.as_str()),
+            })
+            .collect()
+    }
+
+    pub fn question(&self) -> String {
+        match self.kind {
+            CardKind::Meaning | CardKind::Pinyin => self.word.clone(),
+            CardKind::Sentence => self.sentence.replace(self.word.as_str(), CLOZE),
+        }
+    }
+
+    pub fn answer(&self) -> String {
+        match self.kind {
+            CardKind::Meaning => format!("{}\n{}", self.pinyin, self.meaning),
+            CardKind::Pinyin => self.pinyin.clone(),
+            CardKind::Sentence => format!("{}\n{} [{}]\n{}", self.sentence, self.word, self.pinyin, self.meaning),
+        }
+    }
+
+    /// Days to the next review after answering `grade`
+    pub fn interval_after(&self, grade: Grade) -> i64 {
+        let prev = self.interval as f64;
+        let good = match self.reps {
+            0 => 1.0,
+            1 => GOOD_SECOND,
+            _ => (prev * self.ease).max(prev + 1.0),
+        };
+        let days = match grade {
+            Grade::Again => 0.0,
+            Grade::Hard => (prev * HARD_FACTOR).max(1.0),
+            Grade::Good => good,
+            Grade::Easy => (good * EASY_BONUS).max(if self.reps == 0 { EASY_FIRST } else { good + 1.0 }),
+        };
+        days.round() as i64
+    }
+
+    /// Schedule the card after an answer, following SM-2
+    pub fn review(&mut self, grade: Grade, now: DateTime<Utc>) {
+        let interval = self.interval_after(grade);
+        match grade {
+            Grade::Again => {
+                if self.interval > 0 {
+                    self.lapses += 1;
+                }
+                self.reps = 0;
+                self.ease = (self.ease - EASE_LAPSE).max(EASE_MIN);
+                self.due = now + Duration::minutes(RELEARN_MINUTES);
+            }
+            _ => {
+                // Distance from a perfect answer: hard 2, good 1, easy 0
+                let d = (Grade::Easy as i64 - grade as i64) as f64;
+                self.ease = (self.ease + 0.1 - d * (0.08 + d * 0.02)).max(EASE_MIN);
+                self.reps += 1;
+                self.due = now + Duration::days(interval);
+            }
+        }
+        self.interval = interval;
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            word: row.get(1)?,
+            kind: CardKind::from(row.get::<_, String>(2)?),
+            pinyin: row.get(3)?,
+            meaning: row.get(4)?,
+            sentence: row.get(5)?,
+            source: row.get(6)?,
+            due: timestamp(row.get(7)?),
+            interval: row.get(8)?,
+            ease: row.get(9)?,
+            reps: row.get(10)?,
+            lapses: row.get(11)?,
+        })
+    }
+}
+
+const CARD_COLUMNS: &str = "Id, Word, Kind, Pinyin, Meaning, Sentence, Source, Due, Interval, Ease, Reps, Lapses";
+
+pub fn init_srs(conn: &Connection) -> ReaderResult<()> {
+    conn.execute_batch("
+        CREATE TABLE IF NOT EXISTS Cards (
+            Id INTEGER,
+            Word TEXT NOT NULL,
+            Kind TEXT NOT NULL,
+            Pinyin TEXT NOT NULL DEFAULT '',
+            Meaning TEXT NOT NULL DEFAULT '',
+            Sentence TEXT NOT NULL DEFAULT '',
+            Source TEXT NOT NULL DEFAULT '',
+            Created INTEGER NOT NULL,
+            Due INTEGER NOT NULL,
+            Interval INTEGER NOT NULL DEFAULT 0,
+            Ease REAL NOT NULL DEFAULT 2.5,
+            Reps INTEGER NOT NULL DEFAULT 0,
+            Lapses INTEGER NOT NULL DEFAULT 0,
+            UNIQUE(Word, Kind),
+            PRIMARY KEY(Id AUTOINCREMENT) );
+        CREATE TABLE IF NOT EXISTS Reviews (
+            Id INTEGER,
+            Card INTEGER NOT NULL REFERENCES Cards(Id) ON DELETE CASCADE,
+            Time INTEGER NOT NULL,
+            Grade TEXT NOT NULL,
+            Interval INTEGER NOT NULL,
+            PRIMARY KEY(Id AUTOINCREMENT) );
+        CREATE INDEX IF NOT EXISTS CardsDue ON Cards(Due);
+    ")?;
+    Ok(())
+}
+
+/// Cards for each note, skipping the ones already there; returns the number of new cards
+pub fn add_cards(conn: &mut Connection, notes: &[NoteData], now: DateTime<Utc>) -> ReaderResult<usize> {
+    let tx = conn.transaction()?;
+    let mut added = 0;
+    {
+        let mut st = tx.prepare("INSERT OR IGNORE INTO Cards (Word, Kind, Pinyin, Meaning, Sentence, Source, Created, Due)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7)")?;
+        for note in notes.iter().filter(|n| !n.hanzi.is_empty()) {
+            for kind in Card::kinds(note) {
+                added += st.execute(params![note.hanzi, kind.as_str(), note.pinyin, note.meaning, note.sentence, note.source, now.timestamp()])?;
+            }
+        }
+    }
+    tx.commit()?;
+    debug!("Added {} review cards", added);
+    Ok(added)
+}
+
+/// The card due the longest
+pub fn next_due(conn: &Connection, now: DateTime<Utc>) -> ReaderResult<Option<Card>> {
+    let sql = format!("SELECT {} FROM Cards WHERE Due <= ?1 ORDER BY Due, Id LIMIT 1", CARD_COLUMNS);
+    Ok(conn.query_row(sql.as_str(), [now.timestamp()], Card::from_row).optional()?)
+}
+
+/// Cards due now and cards in total
+pub fn count_cards(conn: &Connection, now: DateTime<Utc>) -> ReaderResult<(usize, usize)> {
+    let res = conn.query_row("SELECT count(*) FILTER (WHERE Due <= ?1), count(*) FROM Cards", [now.timestamp()],
+        |row| Ok((row.get::<_, i64>(0)? as usize, row.get::<_, i64>(1)? as usize)))?;
+    Ok(res)
+}
+
+/// Schedule a card and log the answer
+pub fn save_review(conn: &mut Connection, card: &mut Card, grade: Grade, now: DateTime<Utc>) -> ReaderResult<()> {
+    card.review(grade, now);
+    let tx = conn.transaction()?;
+    tx.execute("UPDATE Cards SET Due = ?2, Interval = ?3, Ease = ?4, Reps = ?5, Lapses = ?6 WHERE Id = ?1",
+        params![card.id, card.due.timestamp(), card.interval, card.ease, card.reps, card.lapses])?;
+    tx.execute("INSERT INTO Reviews (Card, Time, Grade, Interval) VALUES (?1, ?2, ?3, ?4)",
+        params![card.id, now.timestamp(), grade.as_str(), card.interval])?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Latest answers, newest first
+pub fn history(conn: &Connection, limit: usize) -> ReaderResult<Vec<ReviewLog>> {
+    let mut st = conn.prepare("SELECT c.Word, c.Kind, r.Time, r.Grade, r.Interval FROM Reviews r
+        JOIN Cards c ON c.Id = r.Card ORDER BY r.Time DESC, r.Id DESC LIMIT ?1")?;
+    let res = st.query_map([limit as i64], |row| {
+        Ok(ReviewLog {
+            word: row.get(0)?,
+            kind: CardKind::from(row.get::<_, String>(1)?),
+            time: timestamp(row.get(2)?),
+            grade: Grade::from(row.get::<_, String>(3)?),
+            interval: row.get(4)?,
+        })
+    })?.collect::<Result<Vec<_>, _>>()?;
+    Ok(res)
+}
+
+pub fn count_reviews(conn: &Connection, since: DateTime<Utc>) -> ReaderResult<usize> {
+    let n: i64 = conn.query_row("SELECT count(*) FROM Reviews WHERE Time >= ?1", [since.timestamp()], |row| row.get(0))?;
+    Ok(n as usize)
+}
+
+fn tsv_field(s: &str) -> String {
+    s.replace(['\t', '\n', '\r'], " ")
+}
+
+/// Every card with its schedule, as TSV
+pub fn export_tsv(conn: &Connection) -> ReaderResult<String> {
+    let sql = format!("SELECT {} FROM Cards ORDER BY Id", CARD_COLUMNS);
+    let mut st = conn.prepare(sql.as_str())?;
+    let cards = st.query_map([], Card::from_row)?.collect::<Result<Vec<_>, _>>()?;
+    let mut res = String::from(TSV_HEADER);
+    res.push('\n');
+    for c in cards {
+        let fields = [c.word.as_str(), c.kind.as_str(), c.pinyin.as_str(), c.meaning.as_str(), c.sentence.as_str(), c.source.as_str()]
+            .map(tsv_field);
+        res.push_str(format!("{}\t{}\t{}\t{:.2}\t{}\t{}\n", fields.join("\t"), c.due.to_rfc3339(), c.interval, c.ease, c.reps, c.lapses).as_str());
+    }
+    Ok(res)
+}
+
+/// Cards exported by `export_tsv`; cards already there take the imported schedule.
+/// Returns the number of cards read
+pub fn import_tsv(conn: &mut Connection, tsv: &str, now: DateTime<Utc>) -> ReaderResult<usize> {
+    let mut lines = tsv.lines();
+    if lines.next().map(|h| h.trim_start_matches('\u{feff}').trim_end()) != Some(TSV_HEADER) {
+        return Err(ReaderError::Srs(String::from("not a card export")));
+    }
+    let tx = conn.transaction()?;
+    let mut count = 0;
+    {
+        let mut st = tx.prepare("INSERT INTO Cards (Word, Kind, Pinyin, Meaning, Sentence, Source, Created, Due, Interval, Ease, Reps, Lapses)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+            ON CONFLICT(Word, Kind) DO UPDATE SET Due = ?8, Interval = ?9, Ease = ?10, Reps = ?11, Lapses = ?12")?;
+        for (ix, line) in lines.enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+            let f: Vec<&str> = line.split('\t').collect();
+            let invalid = || ReaderError::Srs(format!("invalid line {}", ix + 2));
+            if f.len() != 11 {
+                return Err(invalid());
+            }
+            let due = DateTime::parse_from_rfc3339(f[6]).map_err(|_| invalid())?.timestamp();
+            let interval: i64 = f[7].parse().map_err(|_| invalid())?;
+            let ease: f64 = f[8].parse().map_err(|_| invalid())?;
+            let reps: i64 = f[9].parse().map_err(|_| invalid())?;
+            let lapses: i64 = f[10].parse().map_err(|_| invalid())?;
+            let kind = CardKind::from(f[1].to_string());
+            st.execute(params![f[0], kind.as_str(), f[2], f[3], f[4], f[5], now.timestamp(), due, interval, ease.max(EASE_MIN), reps, lapses])?;
+            count += 1;
+        }
+    }
+    tx.commit()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn note(word: &str, sentence: &str) -> NoteData {
+        NoteData {
+            hanzi: word.to_string(),
+            pinyin: String::from("xǐhuan"),
+            meaning: String::from("to like"),
+            sentence: sentence.to_string(),
+            source: String::from("test"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_schedule() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let mut card = Card::new(&note("喜欢", ""), CardKind::Meaning, now);
+        assert_eq!(Grade::ALL.iter().map(|g| card.interval_after(*g)).collect::<Vec<_>>(), [0, 1, 1, 4]);
+
+        card.review(Grade::Good, now);
+        card.review(Grade::Good, now);
+        assert_eq!((card.reps, card.interval), (2, 6));
+        assert_eq!(card.due, now + Duration::days(6));
+        card.review(Grade::Good, now);
+        assert_eq!(card.interval, 15);
+
+        card.review(Grade::Hard, now);
+        assert_eq!(card.interval, 18);
+        assert!(card.ease < EASE_START);
+
+        card.review(Grade::Again, now);
+        assert_eq!((card.reps, card.lapses, card.interval), (0, 1, 0));
+        assert_eq!(card.due, now + Duration::minutes(RELEARN_MINUTES));
+        for _ in 0..20 {
+            card.review(Grade::Again, now);
+        }
+        assert_eq!(card.ease, EASE_MIN);
+        assert_eq!(card.lapses, 1);
+    }
+
+    #[test]
+    fn test_cards() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        init_srs(&conn).unwrap();
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let notes = [note("喜欢", "我喜欢看书。"), note("书", "")];
+        assert_eq!(add_cards(&mut conn, &notes, now).unwrap(), 5);
+        assert_eq!(add_cards(&mut conn, &notes, now).unwrap(), 0);
+        assert_eq!(count_cards(&conn, now).unwrap(), (5, 5));
+
+        let mut card = next_due(&conn, now).unwrap().unwrap();
+        assert_eq!((card.word.as_str(), card.kind), ("喜欢", CardKind::Meaning));
+        save_review(&mut conn, &mut card, Grade::Good, now).unwrap();
+        assert_eq!(count_cards(&conn, now).unwrap(), (4, 5));
+        let next = next_due(&conn, now).unwrap().unwrap();
+        assert_eq!(next.kind, CardKind::Pinyin);
+
+        let log = history(&conn, 10).unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!((log[0].grade, log[0].interval), (Grade::Good, 1));
+        assert_eq!(count_reviews(&conn, now).unwrap(), 1);
+
+        let tsv = export_tsv(&conn).unwrap();
+        assert_eq!(tsv.lines().count(), 6);
+        let mut other = Connection::open_in_memory().unwrap();
+        init_srs(&other).unwrap();
+        assert_eq!(import_tsv(&mut other, &tsv, now).unwrap(), 5);
+        assert_eq!(count_cards(&other, now).unwrap(), (4, 5));
+        assert_eq!(export_tsv(&other).unwrap(), tsv);
+        assert!(matches!(import_tsv(&mut other, "word\tkind", now), Err(ReaderError::Srs(_))));
+    }
+}
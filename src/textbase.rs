@@ -1,9 +1,10 @@
+use chrono::Utc;
 use rusqlite::{Connection, OptionalExtension, params};
 use tracing::{debug, info, error};
+use crate::anchor::{Anchor, NoteColor, byte_offset, position};
 use crate::error::ReaderResult;
 use std::fmt::{Display, Result as FResult};
 use rig::Embed;
-use tokio::io::{BufReader, AsyncBufReadExt};
 
 #[derive(Embed, Clone, Default, Debug)]
 pub struct Document {
@@ -37,12 +38,17 @@ impl Display for Document {
     }
 }
 
+/// A note at a position, or a highlight from (line, char) to (end_line, end_char)
 #[derive(Clone, Default, Debug)]
 pub struct Note {
     pub id: u32,
     pub doc: u32,
     pub line: i64,
     pub char: i64,
+    pub end_line: i64,
+    pub end_char: i64,
+    pub color: NoteColor,
+    pub anchor: Anchor,
     pub text: String,
 }
 
@@ -54,6 +60,43 @@ impl Note {
     pub fn pos(&self) -> (i64,i64) {
         (self.line,self.char)
     }
+
+    pub fn end(&self) -> (i64,i64) {
+        if self.is_range() { (self.end_line, self.end_char) } else { self.pos() }
+    }
+
+    pub fn is_range(&self) -> bool {
+        (self.end_line, self.end_char) > self.pos()
+    }
+
+    /// Whether the cursor at `line`, `char` is on the note
+    pub fn contains(&self, line: i64, char: i64) -> bool {
+        (self.pos()..=self.end()).contains(&(line, char))
+    }
+
+    /// The note with the anchor taken from the text its positions refer to
+    pub fn anchored(mut self, text: &str) -> Self {
+        let (end_line, end_char) = self.end();
+        let start = byte_offset(text, self.line as usize, self.char as usize);
+        let end = byte_offset(text, end_line as usize, end_char as usize).max(start);
+        self.anchor = Anchor::new(text, start, end);
+        self
+    }
+
+    /// A note from the first eleven columns, in the order of `get_notes`
+    pub(crate) fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Note {
+            id: row.get(0)?,
+            line: row.get(1)?,
+            char: row.get(2)?,
+            doc: row.get(3)?,
+            text: row.get(4)?,
+            end_line: row.get(5)?,
+            end_char: row.get(6)?,
+            color: NoteColor::parse(&row.get::<_, String>(7)?),
+            anchor: Anchor { quote: row.get(8)?, prefix: row.get(9)?, suffix: row.get(10)? },
+        })
+    }
 }
 
 impl PartialEq for Note {
@@ -68,6 +111,40 @@ impl Display for Note {
     }
 }
 
+const SENTENCE_END: &[char] = &['。', '！', '？', '!', '?', '；', ';', '…'];
+const SENTENCE_CLOSE: &[char] = &['”', '」', '』', '）', ')', '"', '\'', '’', '》'];
+
+/// Split a line into sentences, returns (byte offset, sentence)
+pub fn sentences(line: &str) -> Vec<(usize, &str)> {
+    let mut res = vec![];
+    let mut start = 0;
+    let mut chars = line.char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
+        if SENTENCE_END.contains(&c) {
+            while let Some((_, n)) = chars.peek() {
+                if SENTENCE_END.contains(n) || SENTENCE_CLOSE.contains(n) {
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let end = chars.peek().map(|(i,_)| *i).unwrap_or(line.len());
+            res.push((start, &line[start..end]));
+            start = end;
+        }
+    }
+    if start < line.len() && !line[start..].trim().is_empty() {
+        res.push((start, &line[start..]));
+    }
+    res
+}
+
+/// The sentence containing the byte position `pos` of the line
+pub fn sentence_at(line: &str, pos: usize) -> Option<(usize, &str)> {
+    sentences(line).into_iter()
+        .find(|(start, s)| pos >= *start && pos < start + s.len())
+}
+
 pub fn get_documents(conn: &Connection) -> ReaderResult<Vec<Document>> {
     let mut stmt = conn.prepare("SELECT Id, Title, Line, Character FROM Documents")?;
     let doc_iter = stmt.query_map([], |row| {
@@ -97,65 +174,167 @@ pub fn get_content(conn: &Connection, doc_id: u32) -> ReaderResult<Option<String
 }
 
 pub fn save_text(conn: &mut Connection, id: u32, title: &str, content: &str) -> ReaderResult<i64> {
-    debug!("Save text {id}/{title}");
     let tx = conn.transaction()?;
-    if id == 0 {
+    let id = save_text_in(&tx, id, title, content)?;
+    tx.commit()?;
+    Ok(id)
+}
+
+/// Like `save_text`, in a transaction the caller commits
+pub fn save_text_in(tx: &Connection, id: u32, title: &str, content: &str) -> ReaderResult<i64> {
+    debug!("Save text {id}/{title}");
+    let id = if id == 0 {
         debug!("INSERT title={}", title);
         tx.execute("INSERT INTO Documents (Title, Content, Line) VALUES (?1, ?2, 0)", [title, content])?;
+        let id = tx.last_insert_rowid();
+        crate::library::set_added(tx, id, Utc::now())?;
+        id
     } else {
         debug!("UPDATE title={}", title);
+        let old = get_content(tx, id)?.unwrap_or_default();
+        crate::revisions::record(tx, id, content)?;
         tx.execute("UPDATE Documents SET Content = ?2, title = ?3 WHERE Id = ?1", params![id, content, title])?;
-    }
-    let id = tx.last_insert_rowid();
-    tx.commit()?;
+        let moved = reanchor_notes(tx, id, content)?;
+        debug!("{} notes moved", moved);
+        if old != content {
+            crate::import::move_lines(tx, id, &old, content)?;
+        }
+        id as i64
+    };
+    crate::search::index_document(tx, id, title, content)?;
     Ok(id)
 }
 
-pub fn save_note(conn: &mut Connection, line: i64, character: i64, document: u32, content: &str) -> ReaderResult<i64> {
-    debug!("Save note: {}/{}:{}",document,line,character);
+pub fn save_note(conn: &mut Connection, note: &Note) -> ReaderResult<i64> {
+    debug!("Save note: {}/{}:{}", note.doc, note.line, note.char);
+    let (end_line, end_char) = note.end();
     let tx = conn.transaction()?;
-    tx.execute("INSERT INTO Notes (Line, Character, Document, Content) VALUES (?1, ?2, ?3, ?4)", params![line, character, document, content])?;
+    tx.execute("INSERT INTO Notes (Line, Character, Document, Content, EndLine, EndCharacter, Color, Quote, Prefix, Suffix, Created)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![note.line, note.char, note.doc, note.text, end_line, end_char, note.color.as_str(),
+            note.anchor.quote, note.anchor.prefix, note.anchor.suffix, Utc::now().timestamp()])?;
     let id = tx.last_insert_rowid();
+    crate::search::index_note(&tx, id, note.doc, note.text.as_str())?;
     tx.commit()?;
     Ok(id)
 }
 
+pub fn set_note_color(conn: &Connection, id: u32, color: NoteColor) -> ReaderResult<()> {
+    conn.execute("UPDATE Notes SET Color = ?2 WHERE Id = ?1", params![id, color.as_str()])?;
+    Ok(())
+}
+
+/// Notes get a range, a colour and the text around them. The notes already there
+/// were saved at the byte column of the editor, they get their character column
+/// and their context from their document
+pub fn anchor_notes(conn: &Connection) -> ReaderResult<()> {
+    conn.execute_batch("
+        ALTER TABLE Notes ADD COLUMN EndLine INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE Notes ADD COLUMN EndCharacter INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE Notes ADD COLUMN Color TEXT NOT NULL DEFAULT '';
+        ALTER TABLE Notes ADD COLUMN Quote TEXT NOT NULL DEFAULT '';
+        ALTER TABLE Notes ADD COLUMN Prefix TEXT NOT NULL DEFAULT '';
+        ALTER TABLE Notes ADD COLUMN Suffix TEXT NOT NULL DEFAULT '';
+        UPDATE Notes SET Character = COALESCE(Character, 0);
+        UPDATE Notes SET EndLine = Line, EndCharacter = Character;
+    ")?;
+    let documents = {
+        let mut st = conn.prepare("SELECT DISTINCT N.Document, D.Content FROM Notes N JOIN Documents D ON D.Id = N.Document")?;
+        st.query_map([], |row| Ok((row.get::<_, u32>(0)?, row.get::<_, Option<String>>(1)?.unwrap_or_default())))?
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    for (document, content) in documents {
+        for note in get_notes(conn, document)? {
+            let char = content.lines().nth(note.line.max(0) as usize)
+                .map(|line| crate::utils::char_column(line, note.char.max(0) as usize) as i64)
+                .unwrap_or(note.char);
+            let note = Note { char, end_char: char, ..note }.anchored(&content);
+            conn.execute("UPDATE Notes SET Character = ?2, EndCharacter = ?3, Prefix = ?4, Suffix = ?5 WHERE Id = ?1",
+                params![note.id, note.char, note.end_char, note.anchor.prefix, note.anchor.suffix])?;
+        }
+    }
+    Ok(())
+}
+
+/// Move the notes of a document to where their anchors are in its new content.
+/// Notes whose text is gone stay where they were; returns the number of notes moved
+pub fn reanchor_notes(conn: &Connection, document: u32, content: &str) -> ReaderResult<usize> {
+    let mut moved = 0;
+    for note in get_notes(conn, document)? {
+        if note.anchor.is_empty() {
+            continue;
+        }
+        let hint = byte_offset(content, note.line as usize, note.char as usize);
+        let Some((start, end)) = note.anchor.find(content, hint) else {
+            debug!("Note {} lost its anchor", note.id);
+            continue;
+        };
+        let (line, char) = position(content, start);
+        let (end_line, end_char) = position(content, end);
+        let (line, char, end_line, end_char) = (line as i64, char as i64, end_line as i64, end_char as i64);
+        let anchor = Anchor::new(content, start, end);
+        if (line, char, end_line, end_char) != (note.line, note.char, note.end().0, note.end().1)
+            || anchor != note.anchor {
+            conn.execute("UPDATE Notes SET Line = ?2, Character = ?3, EndLine = ?4, EndCharacter = ?5, Prefix = ?6, Suffix = ?7 WHERE Id = ?1",
+                params![note.id, line, char, end_line, end_char, anchor.prefix, anchor.suffix])?;
+            moved += 1;
+        }
+    }
+    Ok(moved)
+}
+
 pub fn delete_text(conn: &mut Connection, title: &str) -> ReaderResult<()> {
+    let ids = {
+        let mut st = conn.prepare("SELECT Id FROM Documents WHERE Title = ?1")?;
+        st.query_map([title], |row| row.get::<_, u32>(0))?
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    delete_documents(conn, &ids)
+}
+
+/// Delete documents with their notes, metadata, import records and revisions
+pub fn delete_documents(conn: &mut Connection, ids: &[u32]) -> ReaderResult<()> {
     let tx = conn.transaction()?;
-    tx.execute("DELETE FROM Notes WHERE Document IN (SELECT Id FROM Documents WHERE Title = ?1)", [title])?;
-    tx.execute("DELETE FROM Documents WHERE Title = ?1", [title])?;
+    for id in ids {
+        crate::search::unindex_document(&tx, *id as i64)?;
+        tx.execute("DELETE FROM NoteTags WHERE Note IN (SELECT Id FROM Notes WHERE Document = ?1)", [id])?;
+        for table in ["Notes", "DocumentInfo", "DocumentTags", "Imports", "OcrLines", "Cues", "ReadingSessions", "DocumentViews", "Revisions"] {
+            tx.execute(&format!("DELETE FROM {} WHERE Document = ?1", table), [id])?;
+        }
+        tx.execute("DELETE FROM Documents WHERE Id = ?1", [id])?;
+    }
     tx.commit()?;
     Ok(())
 }
 
 pub fn delete_note(conn: &mut Connection, document: u32, line: i64, character: i64) -> ReaderResult<()> {
     let tx = conn.transaction()?;
+    let ids = {
+        let mut st = tx.prepare("SELECT Id FROM Notes WHERE Document = ?1 AND Line = ?2 AND Character = ?3")?;
+        st.query_map(params![document, line, character], |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    crate::search::unindex_notes(&tx, ids.as_slice())?;
+    for id in &ids {
+        tx.execute("DELETE FROM NoteTags WHERE Note = ?1", [id])?;
+    }
     tx.execute("DELETE FROM Notes WHERE Document = ?1 AND Line = ?2 AND Character = ?3", params![document, line, character])?;
     tx.commit()?;
     Ok(())
 }
 
 pub fn get_notes(conn: &Connection, document: u32) -> ReaderResult<Vec<Note>> {
-    let mut stmt = conn.prepare("SELECT Id, Line, Character, Document, Content FROM Notes WHERE Document = ?1 ORDER BY Line,Character")?;
-    let notes_iter = stmt.query_map([document], |row| {
-        Ok(Note {
-            id: row.get(0)?,
-            line: row.get(1)?,
-            char: row.get(2)?,
-            doc: row.get(3)?,
-            text: row.get(4)?,
-        })
-    })?;
+    let mut stmt = conn.prepare("SELECT Id, Line, Character, Document, Content, EndLine, EndCharacter, Color, Quote, Prefix, Suffix
+        FROM Notes WHERE Document = ?1 ORDER BY Line,Character")?;
+    let notes_iter = stmt.query_map([document], Note::from_row)?;
 
     Ok(notes_iter.map(|doc| doc.unwrap()).collect())
 }
 
+/// The notes of a document as Markdown, see `notes::export`
 pub fn export_notes(conn: &Connection, document: u32) -> ReaderResult<String> {
-    let notes = get_notes(conn, document)?
-        .iter().map(|note| note.to_string())
-        .reduce(|acc,c| format!("{}\n{}",acc,c));
-    
-    Ok(notes.unwrap_or_default())
+    let entries = crate::notes::get_entries(conn, Some(document))?;
+    Ok(crate::notes::export(&entries.iter().collect::<Vec<_>>()))
 }
 
 pub async fn get_doc_md(db_file: &str, doc_id: u32) -> ReaderResult<String> {
@@ -171,40 +350,79 @@ pub async fn get_doc_md(db_file: &str, doc_id: u32) -> ReaderResult<String> {
             info!("None found for document!");
             Ok(String::new())
         },
-        Ok((Some(doc),notes)) => {
-            debug!("Found doc!");
-            let reader = BufReader::new(std::io::Cursor::new(doc.as_str()));
-            let mut lines = reader.lines();
-            
-            let mut result = String::new();
-            let link_len = "[*](n:{}:{})".len();
-            result.reserve(link_len * notes.len() + doc.len());
-            let mut last_pos: usize;
-            let mut i: usize = 0;
-
-            while let Ok(Some(line)) = lines.next_line().await {
-                debug!("New line found: {}", i);
-                last_pos = 0;
-
-                let line_notes = notes.iter().filter(|n| n.line == i as i64);
-
-                for note in line_notes {
-                    debug!("Inserting string at {}:{}, last_pos={}", note.line, note.char, last_pos);
-                    result.push_str(&line[last_pos..(note.char as usize)]);
-                    result.push_str( format!("[*](n:{}:{})",note.line,note.char).as_str() );
-                    last_pos = note.char as usize;
-                };
-                result.push_str(&line[last_pos..]);
-                result.push_str("\n\n");
-                debug!("Finished line {}", i);
-                i += 1;
-            }
-            debug!("Finished: {}", result.len());
-            Ok(result)
-        },
+        Ok((Some(doc),notes)) => Ok(doc_md(&doc, &notes)),
         Err(e) => {
             error!("Error loading md: {}", e);
             Err(e)
         },
     }
 }
+
+/// The text as markdown, a paragraph per line. Highlights become links to their note
+/// shown as inline code after a square of their colour, other notes a `*` link
+pub fn doc_md(doc: &str, notes: &[Note]) -> String {
+    let mut result = String::with_capacity(doc.len() + notes.len() * "[*](n:0)".len());
+    for (i, line) in doc.lines().enumerate() {
+        let i = i as i64;
+        let chars: Vec<char> = line.chars().collect();
+        let mut marks: Vec<(usize, usize, &Note)> = notes.iter()
+            .filter(|n| n.line <= i && i <= n.end().0)
+            .map(|n| {
+                let from = if n.line == i { n.char as usize } else { 0 };
+                let to = if n.end().0 == i { n.end().1 as usize } else { chars.len() };
+                (from.min(chars.len()), to.min(chars.len()), n)
+            })
+            .collect();
+        marks.sort_by_key(|(from, to, _)| (*from, *to));
+
+        let mut last_pos = 0;
+        for (from, to, note) in marks {
+            let from = from.max(last_pos);
+            let to = to.max(from);
+            result.extend(&chars[last_pos..from]);
+            if note.is_range() && to > from {
+                let quote: String = chars[from..to].iter().collect();
+                let fence = if quote.contains('`') { "`` " } else { "`" };
+                let close: String = fence.chars().rev().collect();
+                result.push_str(&format!("{}[{}{}{}](n:{})", note.color.marker(), fence, quote, close, note.id));
+            } else if !note.is_range() {
+                result.push_str(&format!("[*](n:{})", note.id));
+            }
+            last_pos = to;
+        }
+        result.extend(&chars[last_pos..]);
+        result.push_str("\n\n");
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notes() {
+        let mut conn = crate::scraper::db::init_db(":memory:").unwrap();
+        let text = "我冒了严寒，回到故乡去。\n渐近故乡时，天气又阴晦了。";
+        let id = save_text(&mut conn, 0, "故乡", text).unwrap() as u32;
+        let highlight = Note { doc: id, line: 1, char: 2, end_line: 1, end_char: 4, color: NoteColor::Green, text: String::from("hometown"), ..Default::default() };
+        let point = Note { doc: id, line: 0, char: 5, text: String::from("comma"), ..Default::default() };
+        for note in [highlight, point] {
+            save_note(&mut conn, &note.anchored(text)).unwrap();
+        }
+        let notes = get_notes(&conn, id).unwrap();
+        assert_eq!(doc_md(text, &notes), format!("我冒了严寒[*](n:{})，回到故乡去。\n\n渐近🟩[`故乡`](n:{})时，天气又阴晦了。\n\n", notes[0].id, notes[1].id));
+        assert!(notes[1].contains(1, 4) && !notes[1].contains(1, 5) && !notes[0].contains(0, 4));
+
+        save_text(&mut conn, id, "故乡", "我冒着严寒，回到故乡去。\n时候既然是深冬；渐近故乡时，天气又阴晦了。").unwrap();
+        let notes = get_notes(&conn, id).unwrap();
+        assert_eq!((notes[0].pos(), notes[0].end(), notes[0].color), ((0, 5), (0, 5), NoteColor::Yellow));
+        assert_eq!((notes[1].pos(), notes[1].end(), notes[1].color), ((1, 10), (1, 12), NoteColor::Green));
+
+        // The editor cursor counts bytes, notes count characters
+        let line = "时候既然是深冬；渐近故乡时，天气又阴晦了。";
+        let cursor = line.find("乡").unwrap();
+        assert!(!notes[1].contains(1, cursor as i64));
+        assert!(notes[1].contains(1, crate::utils::char_column(line, cursor) as i64));
+    }
+}
@@ -1,4 +1,5 @@
-use tracing::debug;
+use std::path::PathBuf;
+use tracing::{debug, warn};
 #[cfg(target_family="unix")]
 use wl_clipboard_rs::paste::{get_contents, ClipboardType, MimeType, Seat};
 #[cfg(target_family="windows")]
@@ -201,6 +202,25 @@ pub fn random_name() -> String {
 
 }
 
+/// A temporary file, removed when dropped
+pub struct TempFile(pub PathBuf);
+
+impl TempFile {
+    pub fn new(extension: &str) -> Self {
+        let mut path = std::env::temp_dir();
+        path.push(format!("{}-{}.{}", APP_NAME, random_name(), extension));
+        Self(path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if self.0.exists() && let Err(e) = std::fs::remove_file(&self.0) {
+            warn!("Error removing {:?}: {}", self.0, e);
+        }
+    }
+}
+
 pub fn get_models(p: &Provider) -> Vec<String> {
     //
     match p {
@@ -315,6 +335,16 @@ pub fn is_chinese_char(c: &char) -> bool {
     ('\u{f900}'..='\u{faff}').contains(c)
 }
 
+/// Character column of a byte column of a line, a byte inside a character counts as its start
+pub fn char_column(line: &str, column: usize) -> usize {
+    line.char_indices().take_while(|(ix, c)| ix + c.len_utf8() <= column).count()
+}
+
+/// Byte column of a character column of a line, clamped to its end
+pub fn byte_column(line: &str, column: usize) -> usize {
+    line.char_indices().nth(column).map(|(ix, _)| ix).unwrap_or(line.len())
+}
+
 pub fn extract_variant(mea: &str) -> String {
     debug!("ev::mea: {}", mea);
     // Look for patterns like "variant of 叱吒" and extract the Chinese word
@@ -385,6 +415,15 @@ mod tests {
         assert!(!is_chinese_char(&'ź'));
     }
 
+    #[test]
+    fn test_columns() {
+        // Editor columns count bytes
+        let line = "时候既然是深冬；渐近故乡时，天气又阴晦了。";
+        let column = line.find("故乡").unwrap();
+        assert_eq!((char_column(line, column), char_column(line, column + 1)), (10, 10));
+        assert_eq!((byte_column(line, 10), byte_column(line, 100)), (column, line.len()));
+    }
+
     #[test]
     fn test_extract_variant() {
         // Test basic extraction